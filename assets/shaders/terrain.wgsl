@@ -13,47 +13,76 @@ var<uniform> globals: Globals;
 @group(1) @binding(0)
 var<uniform> chunk_pos: vec2<i32>;
 
+// The biome tint of each column of the chunk, indexed by x + z * 16
+@group(1) @binding(1)
+var<uniform> biome_tints: array<vec4<f32>, 256>;
+
 struct VertexInput {
     @builtin(vertex_index) v_index: u32,
     @location(0) data: u32,
+    @location(1) light: u32,
 };
 
 struct VertexOutput {
     @builtin(position) vertices: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
+    // Position on the face in blocks. It grows past 1 on merged quads, which repeats the texture.
+    @location(0) tile_coords: vec2<f32>,
     @location(1) normal: vec3<i32>,
     @location(2) local_pos: vec3<f32>,
+    // Top left corner of the block texture in the atlas
+    @location(3) @interpolate(flat) tile_origin: vec2<f32>,
+    @location(4) ao: f32,
+    // Sky light and block light, from 0 to 1
+    @location(5) light: vec2<f32>,
+    // Whether the texture repeats across the face. Block models use their own
+    // texture coordinates instead.
+    @location(6) @interpolate(flat) tiled: u32,
+    // Whether the texture is colored by the biome, like the top of grass
+    @location(7) @interpolate(flat) tinted: u32,
 };
 
-fn calculate_texture_coordinates(v_index: u32, data: u32) -> vec2<f32> {
-    // Calculate the texture coordinates based on the texture id
-    // let texture_id = data & 0x1FFFu; // mask 13 bits
-    // mask 10 bits
-    let texture_id = data & 0x3FFu;
-    let texture_width = globals.tile_size;
-    let texture_height = globals.tile_size;
+fn calculate_tile_origin(data: u32) -> vec2<f32> {
+    // mask 8 bits
+    let texture_id = data & 0xFFu;
     // number of columns in the atlas
-    let cols = globals.atlas_size / texture_width; 
-    let pixel_x = f32((texture_id % cols) * texture_width);
-    let pixel_y = f32((texture_id / cols) * texture_height);
-
-    switch (v_index % 4u) {
-          case 0u: {
-              return vec2<f32>(pixel_x / f32(globals.atlas_size), (pixel_y + f32(texture_height)) / f32(globals.atlas_size));
-          }
-          case 1u: {
-              return vec2<f32>((pixel_x + f32(texture_width)) / f32(globals.atlas_size), (pixel_y + f32(texture_height)) / f32(globals.atlas_size));
-          }
-          case 2u: {
-              return vec2<f32>((pixel_x + f32(texture_width)) / f32(globals.atlas_size), pixel_y / f32(globals.atlas_size));
-          }
-          case 3u: {
-              return vec2<f32>(pixel_x / f32(globals.atlas_size), pixel_y  / f32(globals.atlas_size));
-          }
-          default: {
-              return vec2<f32>(0.0, 0.0);
-          }
-      }
+    let cols = globals.atlas_size / globals.tile_size;
+    let pixel_x = f32((texture_id % cols) * globals.tile_size);
+    let pixel_y = f32((texture_id / cols) * globals.tile_size);
+    return vec2<f32>(pixel_x, pixel_y) / f32(globals.atlas_size);
+}
+
+// Maps a position on a face to texture coordinates, with the texture upright on side faces.
+// The v axis points down because the image rows go from top to bottom.
+fn calculate_tile_coordinates(face: u32, pos: vec3<f32>) -> vec2<f32> {
+    switch (face) {
+        // North
+        case 0u: {
+            return vec2<f32>(-pos.x, -pos.y);
+        }
+        // South
+        case 1u: {
+            return vec2<f32>(pos.x, -pos.y);
+        }
+        // East
+        case 2u: {
+            return vec2<f32>(pos.z, -pos.y);
+        }
+        // West
+        case 3u: {
+            return vec2<f32>(-pos.z, -pos.y);
+        }
+        // Up
+        case 4u: {
+            return vec2<f32>(pos.x, -pos.z);
+        }
+        // Down
+        case 5u: {
+            return vec2<f32>(pos.z, -pos.x);
+        }
+        default: {
+            return vec2<f32>(0.0, 0.0);
+        }
+    }
 }
 
 fn unpack_vertex_data(data: u32) -> vec3<f32> {
@@ -63,31 +92,118 @@ fn unpack_vertex_data(data: u32) -> vec3<f32> {
     return vec3<f32>(f32(x), f32(y), f32(z));
 }
 
-fn unpack_normals(data: u32) -> vec3<i32> {
-    let x = (data >> 12u) & 0x1u;
-    let y = (data >> 11u) & 0x1u;
-    let z = (data >> 10u) & 0x1u;
-    // map range [0, 1] to [-1, 1]
-    return vec3<i32>(
-        i32(x) * 2 - 1,
-        i32(y) * 2 - 1,
-        i32(z) * 2 - 1
-    );
+// How much light reaches a vertex for each ambient occlusion level
+fn unpack_ao(data: u32) -> f32 {
+    switch ((data >> 8u) & 0x3u) {
+        case 0u: {
+            return 0.45;
+        }
+        case 1u: {
+            return 0.6;
+        }
+        case 2u: {
+            return 0.8;
+        }
+        default: {
+            return 1.0;
+        }
+    }
+}
+
+fn is_model_vertex(light: u32) -> bool {
+    return ((light >> 30u) & 0x1u) == 1u;
+}
+
+// The offset of a block model vertex inside its block
+fn unpack_model_offset(light: u32) -> vec3<f32> {
+    let x = (light >> 8u) & 0xFu;
+    let y = (light >> 12u) & 0xFu;
+    let z = (light >> 16u) & 0xFu;
+    return vec3<f32>(f32(x), f32(y), f32(z)) / 16.0;
+}
+
+fn unpack_model_uv(light: u32) -> vec2<f32> {
+    let u = (light >> 20u) & 0x1Fu;
+    let v = (light >> 25u) & 0x1Fu;
+    return vec2<f32>(f32(u), f32(v)) / 16.0;
+}
+
+fn is_tinted(light: u32) -> bool {
+    return ((light >> 31u) & 0x1u) == 1u;
+}
+
+fn unpack_light(light: u32) -> vec2<f32> {
+    let sky = (light >> 4u) & 0xFu;
+    let block = light & 0xFu;
+    return vec2<f32>(f32(sky), f32(block)) / 15.0;
+}
+
+// How bright a block is for its light levels. Each level is 20% darker than the one above,
+// and fully dark blocks keep a little light so caves aren't pitch black.
+fn light_brightness(light: vec2<f32>) -> f32 {
+    let level = max(light.x, light.y) * 15.0;
+    return max(pow(0.8, 15.0 - level), 0.03);
+}
+
+fn unpack_face(data: u32) -> u32 {
+    return (data >> 10u) & 0x7u;
+}
+
+// The faces are in the same order as `Direction` in the game code
+fn face_normal(face: u32) -> vec3<i32> {
+    switch (face) {
+        case 0u: {
+            return vec3<i32>(0, 0, 1);
+        }
+        case 1u: {
+            return vec3<i32>(0, 0, -1);
+        }
+        case 2u: {
+            return vec3<i32>(1, 0, 0);
+        }
+        case 3u: {
+            return vec3<i32>(-1, 0, 0);
+        }
+        case 4u: {
+            return vec3<i32>(0, 1, 0);
+        }
+        case 5u: {
+            return vec3<i32>(0, -1, 0);
+        }
+        default: {
+            return vec3<i32>(0, 0, 0);
+        }
+    }
 }
 
 @vertex
 fn vs_main(input: VertexInput) -> VertexOutput {
     var output: VertexOutput;
 
-    let local_pos = unpack_vertex_data(input.data);
+    let model = is_model_vertex(input.light);
+    var local_pos = unpack_vertex_data(input.data);
+    if (model) {
+        local_pos += unpack_model_offset(input.light);
+    }
     let world_pos = vec3<f32>(
         f32(chunk_pos.x) * 16.0 + local_pos.x,
         local_pos.y,
         f32(chunk_pos.y) * 16.0 + local_pos.z
     );
     output.vertices = globals.proj * globals.view * vec4<f32>(world_pos, 1.0);
-    output.tex_coords = calculate_texture_coordinates(input.v_index, input.data);
-    output.normal = unpack_normals(input.data);
+    let face = unpack_face(input.data);
+    if (model) {
+        output.tile_coords = unpack_model_uv(input.light);
+        output.tiled = 0u;
+    } else {
+        output.tile_coords = calculate_tile_coordinates(face, local_pos);
+        output.tiled = 1u;
+    }
+    output.tile_origin = calculate_tile_origin(input.data);
+    output.normal = face_normal(face);
+    output.ao = unpack_ao(input.data);
+    output.light = unpack_light(input.light);
+    output.tinted = select(0u, 1u, is_tinted(input.light));
     output.local_pos = local_pos;
     return output;
 }
@@ -97,18 +213,56 @@ var texture: texture_2d<f32>;
 @group(0) @binding(2)
 var texture_sampler: sampler;
 
-@fragment
-fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
-    let obj_color = textureSample(texture, texture_sampler, input.tex_coords);
+fn sample_block(input: VertexOutput) -> vec4<f32> {
+    let tile_size = f32(globals.tile_size) / f32(globals.atlas_size);
+    var coords = clamp(input.tile_coords, vec2<f32>(0.0), vec2<f32>(0.9999));
+    if (input.tiled == 1u) {
+        coords = fract(input.tile_coords);
+    }
+    let tex_coords = input.tile_origin + coords * tile_size;
+    let color = textureSample(texture, texture_sampler, tex_coords);
+    if (input.tinted == 1u) {
+        return vec4<f32>(color.rgb * biome_tint(input), color.a);
+    }
+    return color;
+}
+
+// The tint of the column the fragment belongs to. Side faces lie on the edge of their
+// column, so the position is moved half a block back inside the block.
+fn biome_tint(input: VertexOutput) -> vec3<f32> {
+    let inside = input.local_pos.xz - vec2<f32>(input.normal.xz) * 0.5;
+    let column = clamp(vec2<i32>(floor(inside)), vec2<i32>(0), vec2<i32>(15));
+    return biome_tints[column.x + column.y * 16].rgb;
+}
+
+fn shade(input: VertexOutput, obj_color: vec4<f32>) -> vec4<f32> {
     if (globals.enable_lighting == 0u) {
-        return obj_color;
+        return vec4<f32>(obj_color.xyz * input.ao, obj_color.w);
     }
     let ambient_factor = 0.36;
     let light_color = vec3<f32>(1.0, 1.0, 1.0);
     let ambient = ambient_factor * light_color;
     let light_dir = normalize(globals.sun_pos - input.local_pos);
-    let diff = max(dot(vec3<f32>(input.normal), light_dir), 0.0);
+    // Only the sky light is lit by the sun, block light glows the same on every face
+    let diff = max(dot(vec3<f32>(input.normal), light_dir), 0.0) * input.light.x;
     let diffuse = diff * light_color;
-    let result = (diffuse + ambient) * obj_color.xyz;
+    let brightness = light_brightness(input.light);
+    let result = (diffuse + ambient) * brightness * obj_color.xyz * input.ao;
     return vec4<f32>(result, obj_color.w);
 }
+
+// Used by the opaque and translucent layers
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(input, sample_block(input));
+}
+
+// Used by the cutout layer, the transparent pixels of its textures are not drawn at all
+@fragment
+fn fs_cutout(input: VertexOutput) -> @location(0) vec4<f32> {
+    let obj_color = sample_block(input);
+    if (obj_color.a < 0.5) {
+        discard;
+    }
+    return shade(input, obj_color);
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+/// The kind of landscape of a column of blocks.
+///
+/// Biomes are chosen by the world generator and stored per column in each [`crate::chunk::Chunk`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Biome {
+    #[default]
+    Plains,
+    Forest,
+    Desert,
+    Tundra,
+    Mountains,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 5] = [
+        Biome::Plains,
+        Biome::Forest,
+        Biome::Desert,
+        Biome::Tundra,
+        Biome::Mountains,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Biome::Plains => "Plains",
+            Biome::Forest => "Forest",
+            Biome::Desert => "Desert",
+            Biome::Tundra => "Tundra",
+            Biome::Mountains => "Mountains",
+        }
+    }
+
+    /// The color multiplied with the textures of tinted block faces, like the top of grass.
+    pub const fn grass_tint(self) -> [f32; 3] {
+        match self {
+            Biome::Plains => [0.55, 0.78, 0.35],
+            Biome::Forest => [0.36, 0.62, 0.27],
+            Biome::Desert => [0.75, 0.72, 0.4],
+            Biome::Tundra => [0.55, 0.7, 0.6],
+            Biome::Mountains => [0.5, 0.66, 0.45],
+        }
+    }
+}
@@ -1,27 +1,807 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
 use serde::{Deserialize, Serialize};
+use vek::Aabb;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub enum BlockId {
-    Air,
-    Dirt,
-    Grass,
-    Stone,
-}
+use crate::{dir::Direction, model::BlockModel};
+
+/// The directory the block descriptors are loaded from.
+pub const BLOCKS_DIR: &str = "assets/blocks";
+
+/// A numeric block id assigned by the [`BlockRegistry`].
+///
+/// Ids are only meaningful together with the registry that assigned them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockId(pub u16);
 
 impl BlockId {
+    /// Air is always registered with the id 0.
+    pub const AIR: BlockId = BlockId(0);
+
+    pub const fn is_air(self) -> bool {
+        self.0 == Self::AIR.0
+    }
+}
+
+/// The state of a block, encoding the values of the properties declared in its [`BlockDescriptor`].
+///
+/// The state 0 is the default state, where every property has its first value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockState(pub u16);
+
+/// A single voxel: the block type and its state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Block {
+    pub id: BlockId,
+    pub state: BlockState,
+}
+
+impl Block {
+    pub const AIR: Block = Block::new(BlockId::AIR);
+
+    /// Creates a block in its default state.
+    pub const fn new(id: BlockId) -> Self {
+        Self {
+            id,
+            state: BlockState(0),
+        }
+    }
+
+    pub const fn with_state(id: BlockId, state: BlockState) -> Self {
+        Self { id, state }
+    }
+
     pub const fn is_air(self) -> bool {
-        matches!(self, BlockId::Air)
+        self.id.is_air()
+    }
+}
+
+impl From<BlockId> for Block {
+    fn from(id: BlockId) -> Self {
+        Self::new(id)
+    }
+}
+
+/// How the faces of a block are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderLayer {
+    /// Fully opaque faces, drawn first.
+    #[default]
+    Opaque,
+    /// Faces with fully transparent holes, like leaves or flowers.
+    Cutout,
+    /// Faces blended with what is behind them, like glass or water. They are drawn last.
+    Translucent,
+}
+
+impl RenderLayer {
+    /// Every layer, in the order they are drawn.
+    pub const ALL: [RenderLayer; 3] = [
+        RenderLayer::Opaque,
+        RenderLayer::Cutout,
+        RenderLayer::Translucent,
+    ];
+}
+
+/// The faces of a block colored by the biome it is in, see [`crate::biome::Biome::grass_tint`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tint {
+    #[default]
+    None,
+    /// Only the top face, like grass.
+    Top,
+    /// Every face.
+    All,
+}
+
+impl Tint {
+    pub const fn applies_to(self, face: Direction) -> bool {
+        match self {
+            Tint::None => false,
+            Tint::Top => matches!(face, Direction::Up),
+            Tint::All => true,
+        }
+    }
+}
+
+/// Describes a block type. Block descriptors are loaded from TOML files in [`BLOCKS_DIR`].
+///
+/// A block can declare named properties with the values they can take:
+///
+/// ```toml
+/// [states]
+/// axis = ["y", "x", "z"]
+///
+/// [[variants]]
+/// when = { axis = "x" }
+/// x = 90
+/// y = 90
+/// ```
+///
+/// Variants choose the rotation and textures used for the states they match.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockDescriptor {
+    pub name: String,
+    #[serde(default)]
+    pub textures: Textures,
+    /// Whether entities collide with this block.
+    #[serde(default = "default_solid")]
+    pub solid: bool,
+    /// Whether light and faces behind this block can be seen through it.
+    /// Blocks outside of the opaque render layer are always transparent.
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default)]
+    pub render_layer: RenderLayer,
+    #[serde(default)]
+    pub tint: Tint,
+    /// The shape of the block. Light goes through the sides it doesn't fill.
+    #[serde(default)]
+    pub model: BlockModel,
+    /// Makes this block a fluid. Fluids must declare a `level` property, see [`FluidDescriptor`].
+    #[serde(default)]
+    pub fluid: Option<FluidDescriptor>,
+    /// The light level emitted by this block, from 0 to 15.
+    #[serde(default)]
+    pub light_emission: u8,
+    /// How long this block takes to break.
+    #[serde(default = "default_hardness")]
+    pub hardness: f32,
+    /// The properties of this block and the values each one can take.
+    #[serde(default)]
+    pub states: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub variants: Vec<BlockVariant>,
+}
+
+const fn default_solid() -> bool {
+    true
+}
+
+const fn default_hardness() -> f32 {
+    1.0
+}
+
+/// How a fluid flows.
+///
+/// The level of a fluid is stored in its `level` property, which must list the levels
+/// from `"0"` upwards. Level 0 is a source block and never drains.
+/// Flowing fluid gets one level higher with every block it spreads sideways,
+/// and stops at the last level.
+///
+/// ```toml
+/// [fluid]
+/// tick_delay = 5
+///
+/// [states]
+/// level = ["0", "1", "2", "3"]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FluidDescriptor {
+    /// How many fluid ticks pass before the fluid reacts to a change next to it.
+    #[serde(default = "default_tick_delay")]
+    pub tick_delay: u32,
+}
+
+const fn default_tick_delay() -> u32 {
+    5
+}
+
+/// The appearance of a block for the states matching `when`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockVariant {
+    /// The property values this variant applies to. Properties that are not listed match any value.
+    #[serde(default)]
+    pub when: BTreeMap<String, String>,
+    /// The rotation around the X axis in degrees. Must be a multiple of 90.
+    #[serde(default)]
+    pub x: i32,
+    /// The rotation around the Y axis in degrees, applied after `x`. Must be a multiple of 90.
+    #[serde(default)]
+    pub y: i32,
+    /// Replaces the textures of the block.
+    pub textures: Option<Textures>,
+}
+
+impl BlockDescriptor {
+    fn air() -> Self {
+        Self {
+            name: "Air".to_owned(),
+            textures: Textures::default(),
+            solid: false,
+            transparent: true,
+            render_layer: RenderLayer::Opaque,
+            tint: Tint::None,
+            model: BlockModel::Cube,
+            fluid: None,
+            light_emission: 0,
+            hardness: 0.0,
+            states: BTreeMap::new(),
+            variants: Vec::new(),
+        }
+    }
+
+    /// Whether light and the faces behind this block can be seen through any of its sides.
+    pub fn is_transparent(&self) -> bool {
+        self.transparent
+            || self.render_layer != RenderLayer::Opaque
+            || self.model != BlockModel::Cube
+    }
+
+    /// Whether the given state hides the whole side of the block facing `face`,
+    /// so that light can't go through that side.
+    pub fn covers_face(&self, state: BlockState, face: Direction) -> bool {
+        if self.transparent || self.render_layer != RenderLayer::Opaque {
+            return false;
+        }
+        let (x, y) = self.rotation(state);
+        self.model.covers_face(x, y, face)
+    }
+
+    /// Returns the fluid level of the given state, or `None` if this block is not a fluid.
+    pub fn fluid_level(&self, state: BlockState) -> Option<u8> {
+        self.fluid.as_ref()?;
+        self.property(state, "level")?.parse().ok()
+    }
+
+    /// The highest level this fluid reaches while spreading.
+    pub fn max_fluid_level(&self) -> u8 {
+        self.states
+            .get("level")
+            .map_or(0, |levels| levels.len().saturating_sub(1) as u8)
+    }
+
+    /// Returns the state of this fluid with the given level, keeping the other properties at their default.
+    pub fn fluid_state(&self, level: u8) -> Option<BlockState> {
+        self.fluid.as_ref()?;
+        self.state(&[("level", &level.to_string())])
+    }
+
+    /// Returns the rotation of the given state in quarter turns around the X and Y axes.
+    pub fn rotation(&self, state: BlockState) -> (i32, i32) {
+        self.variant(state)
+            .map(|variant| (variant.x / 90, variant.y / 90))
+            .unwrap_or_default()
+    }
+
+    /// Returns the boxes entities collide with, relative to the block position.
+    pub fn collision_boxes(&self, state: BlockState) -> Vec<Aabb<f32>> {
+        if !self.solid {
+            return Vec::new();
+        }
+        let (x, y) = self.rotation(state);
+        self.model.collision_boxes(x, y)
+    }
+
+    /// The number of distinct states of this block.
+    pub fn state_count(&self) -> usize {
+        self.states
+            .values()
+            .map(|values| values.len().max(1))
+            .product()
+    }
+
+    /// Encodes the given property values into a state.
+    /// Properties that are not given keep their default value.
+    ///
+    /// Returns `None` if a property or value is not declared by this block.
+    pub fn state(&self, properties: &[(&str, &str)]) -> Option<BlockState> {
+        let mut state = 0;
+        let mut stride = 1;
+        for (name, values) in &self.states {
+            let value = match properties.iter().find(|(n, _)| n == name) {
+                Some((_, value)) => values.iter().position(|v| v == value)?,
+                None => 0,
+            };
+            state += value * stride;
+            stride *= values.len().max(1);
+        }
+        if properties
+            .iter()
+            .any(|(name, _)| !self.states.contains_key(*name))
+        {
+            return None;
+        }
+        Some(BlockState(state as u16))
+    }
+
+    /// Returns the value of a property in the given state.
+    pub fn property(&self, state: BlockState, name: &str) -> Option<&str> {
+        let mut state = state.0 as usize;
+        for (property, values) in &self.states {
+            let len = values.len().max(1);
+            if property == name {
+                return values.get(state % len).map(String::as_str);
+            }
+            state /= len;
+        }
+        None
+    }
+
+    /// Returns the first variant matching the given state.
+    pub fn variant(&self, state: BlockState) -> Option<&BlockVariant> {
+        self.variants.iter().find(|variant| {
+            variant
+                .when
+                .iter()
+                .all(|(name, value)| self.property(state, name) == Some(value.as_str()))
+        })
+    }
+
+    /// Returns the texture shown on the given face of this block, taking its rotation into account.
+    pub fn face_texture(&self, state: BlockState, face: Direction) -> Option<&str> {
+        match self.variant(state) {
+            Some(variant) => {
+                // Find which face of the unrotated block ends up facing `face`
+                let model_face = face.rotate_y(-variant.y / 90).rotate_x(-variant.x / 90);
+                variant
+                    .textures
+                    .as_ref()
+                    .unwrap_or(&self.textures)
+                    .face(model_face)
+            },
+            None => self.textures.face(face),
+        }
+    }
+
+    /// Iterates over every texture this block may use.
+    pub fn texture_names(&self) -> impl Iterator<Item = &String> {
+        self.textures
+            .iter()
+            .chain(
+                self.variants
+                    .iter()
+                    .filter_map(|variant| variant.textures.as_ref())
+                    .flat_map(Textures::iter),
+            )
+            .chain(self.model.texture_names())
+    }
+}
+
+/// The textures of a block. Specific faces take precedence over `side`, and `side` over `all`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Textures {
+    pub all: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub side: Option<String>,
+    pub north: Option<String>,
+    pub south: Option<String>,
+    pub east: Option<String>,
+    pub west: Option<String>,
+}
+
+impl Textures {
+    /// Returns the texture of the given face.
+    pub fn face(&self, face: Direction) -> Option<&str> {
+        let specific = match face {
+            Direction::Up => &self.top,
+            Direction::Down => &self.bottom,
+            Direction::North => &self.north,
+            Direction::South => &self.south,
+            Direction::East => &self.east,
+            Direction::West => &self.west,
+        };
+        let side = match face {
+            Direction::Up | Direction::Down => &None,
+            _ => &self.side,
+        };
+        specific
+            .as_ref()
+            .or(side.as_ref())
+            .or(self.all.as_ref())
+            .map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        [
+            &self.all,
+            &self.top,
+            &self.bottom,
+            &self.side,
+            &self.north,
+            &self.south,
+            &self.east,
+            &self.west,
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(std::io::Error),
+    Parse {
+        file: String,
+        error: toml::de::Error,
+    },
+    DuplicateBlock(String),
+    /// The block declares more states than fit in a [`BlockState`].
+    TooManyStates(String),
+    /// The block is known by the server but has no descriptor on this side.
+    MissingBlock(String),
+    /// The block is a fluid but its `level` property doesn't list the levels from 0 upwards.
+    InvalidFluid(String),
+    /// The model of the block has boxes or texture coordinates outside of the block.
+    InvalidModel {
+        block: String,
+        error: String,
+    },
+    /// The remote block names don't start with air, which always has the id 0.
+    AirNotFirst,
+}
+
+impl From<std::io::Error> for RegistryError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Maps numeric [`BlockId`]s to their [`BlockDescriptor`].
+///
+/// Air always has the id 0. The remaining blocks are numbered in name order,
+/// so two registries loaded from the same descriptors assign the same ids.
+/// The server sends its id mapping when a client connects, and the client calls [`BlockRegistry::remap`] with it.
+#[derive(Debug, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockDescriptor>,
+    ids: HashMap<String, BlockId>,
+}
+
+impl BlockRegistry {
+    /// Loads every block descriptor in the given directory.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, RegistryError> {
+        let mut descriptors = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                continue;
+            }
+            log::info!("Loading block: {:?}", path);
+            let file = std::fs::read_to_string(&path)?;
+            let descriptor =
+                toml::from_str::<BlockDescriptor>(&file).map_err(|error| RegistryError::Parse {
+                    file: path.display().to_string(),
+                    error,
+                })?;
+            descriptors.push(descriptor);
+        }
+        Self::from_descriptors(descriptors)
+    }
+
+    /// Creates a registry from a list of descriptors. Air is registered automatically.
+    pub fn from_descriptors(mut descriptors: Vec<BlockDescriptor>) -> Result<Self, RegistryError> {
+        descriptors.sort_by_key(|descriptor| descriptor.name.to_lowercase());
+
+        let mut registry = Self {
+            blocks: Vec::with_capacity(descriptors.len() + 1),
+            ids: HashMap::new(),
+        };
+        registry.register(BlockDescriptor::air())?;
+        for descriptor in descriptors {
+            registry.register(descriptor)?;
+        }
+        Ok(registry)
+    }
+
+    fn register(&mut self, descriptor: BlockDescriptor) -> Result<BlockId, RegistryError> {
+        let key = descriptor.name.to_lowercase();
+        if self.ids.contains_key(&key) {
+            return Err(RegistryError::DuplicateBlock(descriptor.name));
+        }
+        if descriptor.state_count() > u16::MAX as usize + 1 {
+            return Err(RegistryError::TooManyStates(descriptor.name));
+        }
+        if descriptor.fluid.is_some() && !has_fluid_levels(&descriptor) {
+            return Err(RegistryError::InvalidFluid(descriptor.name));
+        }
+        if let Err(error) = descriptor.model.validate() {
+            return Err(RegistryError::InvalidModel {
+                block: descriptor.name,
+                error,
+            });
+        }
+        let id = BlockId(self.blocks.len() as u16);
+        self.ids.insert(key, id);
+        self.blocks.push(descriptor);
+        Ok(id)
+    }
+
+    /// Renumbers the blocks so that they match the id mapping of another registry.
+    ///
+    /// `names` holds the block names indexed by id, as returned by [`BlockRegistry::names`].
+    /// Blocks that are not part of `names` are given the ids after it.
+    /// Air has to come first, so it keeps the id 0.
+    pub fn remap(&mut self, names: &[String]) -> Result<(), RegistryError> {
+        if names.first().and_then(|name| self.id(name)) != Some(BlockId::AIR) {
+            return Err(RegistryError::AirNotFirst);
+        }
+        let mut taken = vec![false; self.blocks.len()];
+        let mut order = Vec::with_capacity(self.blocks.len());
+        for name in names {
+            let id = self
+                .id(name)
+                .filter(|id| !taken[id.0 as usize])
+                .ok_or_else(|| RegistryError::MissingBlock(name.clone()))?;
+            taken[id.0 as usize] = true;
+            order.push(id.0 as usize);
+        }
+        order.extend((0..self.blocks.len()).filter(|id| !taken[*id]));
+
+        let ordered = order
+            .into_iter()
+            .map(|id| self.blocks[id].clone())
+            .collect::<Vec<_>>();
+        self.ids = ordered
+            .iter()
+            .enumerate()
+            .map(|(id, descriptor)| (descriptor.name.to_lowercase(), BlockId(id as u16)))
+            .collect();
+        self.blocks = ordered;
+        Ok(())
+    }
+
+    /// Returns the id of the block with the given name, ignoring case.
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.ids.get(&name.to_lowercase()).copied()
+    }
+
+    pub fn get(&self, id: BlockId) -> Option<&BlockDescriptor> {
+        self.blocks.get(id.0 as usize)
+    }
+
+    /// Returns the block names indexed by id.
+    pub fn names(&self) -> Vec<String> {
+        self.blocks.iter().map(|block| block.name.clone()).collect()
+    }
+
+    /// A hash of the block names and their number of states, which doesn't depend on the ids.
+    ///
+    /// Registries with the same hash can be [remapped](BlockRegistry::remap) to each other.
+    /// It uses FNV-1a, so it is the same on every platform and build.
+    pub fn hash(&self) -> u64 {
+        let mut blocks: Vec<_> = self
+            .blocks
+            .iter()
+            .map(|block| (block.name.to_lowercase(), block.state_count()))
+            .collect();
+        blocks.sort();
+
+        let mut hash = 0xcbf29ce484222325_u64;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+            }
+        };
+        for (name, states) in blocks {
+            write(name.as_bytes());
+            write(&[0]);
+            write(&(states as u32).to_le_bytes());
+        }
+        hash
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BlockId, &BlockDescriptor)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(id, block)| (BlockId(id as u16), block))
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
     }
 }
 
-impl From<&str> for BlockId {
-    fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "air" => BlockId::Air,
-            "dirt" => BlockId::Dirt,
-            "grass" => BlockId::Grass,
-            "stone" => BlockId::Stone,
-            _ => panic!("Unknown block id: {}", s),
+fn has_fluid_levels(descriptor: &BlockDescriptor) -> bool {
+    descriptor.states.get("level").is_some_and(|levels| {
+        levels.len() >= 2
+            && levels.len() <= u8::MAX as usize
+            && levels
+                .iter()
+                .enumerate()
+                .all(|(index, level)| *level == index.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dir::Direction, model::BlockModel};
+
+    use super::{BlockDescriptor, BlockId, BlockRegistry, BlockState, RegistryError, RenderLayer};
+
+    fn descriptor(name: &str) -> BlockDescriptor {
+        toml::from_str(&format!(
+            "name = \"{}\"\n[textures]\nall = \"{}\"",
+            name, name
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    pub fn registry_assigns_ids_in_name_order() {
+        let registry = BlockRegistry::from_descriptors(vec![
+            descriptor("Stone"),
+            descriptor("Dirt"),
+            descriptor("Grass"),
+        ])
+        .unwrap();
+
+        assert_eq!(registry.id("air"), Some(BlockId::AIR));
+        assert_eq!(registry.id("Dirt"), Some(BlockId(1)));
+        assert_eq!(registry.id("grass"), Some(BlockId(2)));
+        assert_eq!(registry.id("stone"), Some(BlockId(3)));
+        assert_eq!(registry.id("lava"), None);
+
+        let stone = registry.get(BlockId(3)).unwrap();
+        assert!(stone.solid);
+        assert!(!stone.transparent);
+        assert!(!registry.get(BlockId::AIR).unwrap().solid);
+    }
+
+    #[test]
+    pub fn registry_remap_matches_remote_ids() {
+        let mut registry =
+            BlockRegistry::from_descriptors(vec![descriptor("Stone"), descriptor("Dirt")]).unwrap();
+        let remote = vec!["Air".to_owned(), "Stone".to_owned(), "Dirt".to_owned()];
+        registry.remap(&remote).unwrap();
+        assert_eq!(registry.names(), remote);
+        assert_eq!(registry.id("stone"), Some(BlockId(1)));
+
+        let missing = vec!["Air".to_owned(), "Glass".to_owned()];
+        assert!(registry.remap(&missing).is_err());
+    }
+
+    #[test]
+    pub fn registry_remap_keeps_air_first() {
+        let mut registry =
+            BlockRegistry::from_descriptors(vec![descriptor("Stone"), descriptor("Dirt")]).unwrap();
+        let remote = vec!["Stone".to_owned(), "Air".to_owned(), "Dirt".to_owned()];
+        assert!(matches!(
+            registry.remap(&remote),
+            Err(RegistryError::AirNotFirst)
+        ));
+        assert!(matches!(
+            registry.remap(&[]),
+            Err(RegistryError::AirNotFirst)
+        ));
+        assert_eq!(registry.id("air"), Some(BlockId::AIR));
+    }
+
+    #[test]
+    pub fn registry_hash_ignores_the_ids() {
+        let registry =
+            BlockRegistry::from_descriptors(vec![descriptor("Stone"), descriptor("Dirt")]).unwrap();
+        let mut remapped =
+            BlockRegistry::from_descriptors(vec![descriptor("Dirt"), descriptor("Stone")]).unwrap();
+        remapped
+            .remap(&["Air".to_owned(), "Stone".to_owned(), "Dirt".to_owned()])
+            .unwrap();
+        assert_eq!(registry.hash(), remapped.hash());
+
+        let more = BlockRegistry::from_descriptors(vec![
+            descriptor("Stone"),
+            descriptor("Dirt"),
+            descriptor("Glass"),
+        ])
+        .unwrap();
+        assert_ne!(registry.hash(), more.hash());
+    }
+
+    #[test]
+    pub fn render_layers_are_transparent() {
+        let stone = descriptor("Stone");
+        assert_eq!(stone.render_layer, RenderLayer::Opaque);
+        assert!(!stone.is_transparent());
+
+        let glass: BlockDescriptor =
+            toml::from_str("name = \"Glass\"\nrender_layer = \"translucent\"").unwrap();
+        assert_eq!(glass.render_layer, RenderLayer::Translucent);
+        assert!(glass.is_transparent());
+    }
+
+    #[test]
+    pub fn slabs_collide_with_half_a_block() {
+        let slab: BlockDescriptor = toml::from_str(
+            r#"
+            name = "Slab"
+            [states]
+            half = ["bottom", "top"]
+            [[variants]]
+            when = { half = "top" }
+            x = 180
+            [model]
+            type = "boxes"
+            [[model.boxes]]
+            from = [0, 0, 0]
+            to = [16, 8, 16]
+            "#,
+        )
+        .unwrap();
+        assert!(slab.is_transparent());
+
+        let bottom = slab.collision_boxes(BlockState(0));
+        assert_eq!(bottom.len(), 1);
+        assert_eq!(bottom[0].max.y, 0.5);
+        let top = slab.collision_boxes(slab.state(&[("half", "top")]).unwrap());
+        assert_eq!(top[0].min.y, 0.5);
+        assert_eq!(top[0].max.y, 1.0);
+
+        // Light only goes through the sides the slab doesn't fill
+        let top = slab.state(&[("half", "top")]).unwrap();
+        assert!(slab.covers_face(BlockState(0), Direction::Down));
+        assert!(!slab.covers_face(BlockState(0), Direction::Up));
+        assert!(!slab.covers_face(BlockState(0), Direction::North));
+        assert!(slab.covers_face(top, Direction::Up));
+        assert!(!slab.covers_face(top, Direction::Down));
+
+        let mut invalid = slab.clone();
+        invalid.name = "Invalid".to_owned();
+        if let BlockModel::Boxes { boxes } = &mut invalid.model {
+            boxes[0].to = [16, 0, 16];
         }
+        assert!(BlockRegistry::from_descriptors(vec![invalid]).is_err());
+    }
+
+    #[test]
+    pub fn registry_rejects_duplicates() {
+        let result = BlockRegistry::from_descriptors(vec![descriptor("Dirt"), descriptor("dirt")]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    pub fn block_states_roundtrip() {
+        let log: BlockDescriptor = toml::from_str(
+            r#"
+            name = "Log"
+            [textures]
+            top = "log_top"
+            bottom = "log_top"
+            side = "log_side"
+            [states]
+            axis = ["y", "x", "z"]
+            half = ["bottom", "top"]
+            [[variants]]
+            when = { axis = "x" }
+            x = 90
+            y = 90
+            [[variants]]
+            when = { axis = "z" }
+            x = 90
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(log.state_count(), 6);
+        assert_eq!(log.state(&[]), Some(BlockState(0)));
+        let state = log.state(&[("axis", "z"), ("half", "top")]).unwrap();
+        assert_eq!(log.property(state, "axis"), Some("z"));
+        assert_eq!(log.property(state, "half"), Some("top"));
+        assert_eq!(log.state(&[("axis", "w")]), None);
+        assert_eq!(log.state(&[("color", "red")]), None);
+
+        let default = BlockState(0);
+        assert_eq!(log.face_texture(default, Direction::Up), Some("log_top"));
+        assert_eq!(log.face_texture(default, Direction::East), Some("log_side"));
+
+        let x = log.state(&[("axis", "x")]).unwrap();
+        assert_eq!(log.face_texture(x, Direction::East), Some("log_top"));
+        assert_eq!(log.face_texture(x, Direction::West), Some("log_top"));
+        assert_eq!(log.face_texture(x, Direction::Up), Some("log_side"));
+
+        let z = log.state(&[("axis", "z")]).unwrap();
+        assert_eq!(log.face_texture(z, Direction::North), Some("log_top"));
+        assert_eq!(log.face_texture(z, Direction::South), Some("log_top"));
+        assert_eq!(log.face_texture(z, Direction::East), Some("log_side"));
     }
 }
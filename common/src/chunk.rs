@@ -1,74 +1,172 @@
-use noise::{NoiseFn, Perlin};
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
 use vek::{Vec2, Vec3};
 
-use crate::block::BlockId;
+use crate::{
+    biome::Biome,
+    block::{Block, BlockId},
+    light::Light,
+    palette::PalettedStorage,
+};
 
+/// A column of blocks, split vertically into [`ChunkSection`]s.
+#[derive(Clone)]
 pub struct Chunk {
-    blocks: [BlockId; 16 * 256 * 16],
+    sections: Vec<ChunkSection>,
+    /// The biome of each column, indexed by [`Chunk::column_index`].
+    biomes: Vec<Biome>,
+    status: GenerationStatus,
+    /// The local positions of the blocks edited since the last call to [`Chunk::take_dirty`].
+    dirty: HashSet<Vec3<i32>>,
 }
 
-use rayon::{
-    iter::{IndexedParallelIterator, IntoParallelRefMutIterator},
-    prelude::ParallelIterator,
-};
+/// How far the world generator got with a chunk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GenerationStatus {
+    /// Only the terrain is there. Decorations from this chunk or its neighbours can still
+    /// be placed in it.
+    Terrain,
+    /// Every decoration reaching into the chunk is placed. Only full chunks are sent to clients.
+    #[default]
+    Full,
+}
+
+/// A 16x16x16 cube of blocks.
+///
+/// Blocks and their states are stored in a [`PalettedStorage`], so a section only pays for
+/// the block states it actually uses and a uniform section is stored as a single value.
+/// The light levels are stored the same way.
+#[derive(Clone)]
+pub struct ChunkSection {
+    blocks: PalettedStorage<Block>,
+    light: PalettedStorage<Light>,
+}
+
+impl ChunkSection {
+    pub const SIZE: Vec3<usize> = Vec3::new(16, 16, 16);
+
+    pub const VOLUME: usize = Self::SIZE.x * Self::SIZE.y * Self::SIZE.z;
+
+    pub fn flat(block: impl Into<Block>) -> Self {
+        Self {
+            blocks: PalettedStorage::new(Self::VOLUME, block.into()),
+            light: PalettedStorage::new(Self::VOLUME, Light::DARK),
+        }
+    }
+
+    /// Creates a section from blocks laid out in [`ChunkSection::index_of`] order.
+    pub fn from_blocks(blocks: &[Block]) -> Self {
+        debug_assert_eq!(blocks.len(), Self::VOLUME);
+        Self {
+            blocks: PalettedStorage::from_slice(blocks),
+            light: PalettedStorage::new(Self::VOLUME, Light::DARK),
+        }
+    }
+
+    pub fn index_of(pos: Vec3<usize>) -> usize {
+        pos.x + pos.y * Self::SIZE.x + pos.z * Self::SIZE.x * Self::SIZE.y
+    }
+
+    /// Returns the block at a position local to this section.
+    pub fn get(&self, pos: Vec3<usize>) -> Block {
+        self.blocks.get(Self::index_of(pos))
+    }
+
+    /// Replaces the block at a position local to this section and returns the previous one.
+    pub fn set(&mut self, pos: Vec3<usize>, block: Block) -> Block {
+        self.blocks.set(Self::index_of(pos), block)
+    }
+
+    /// Returns the light level at a position local to this section.
+    pub fn light(&self, pos: Vec3<usize>) -> Light {
+        self.light.get(Self::index_of(pos))
+    }
+
+    /// Replaces the light level at a position local to this section and returns the previous one.
+    pub fn set_light(&mut self, pos: Vec3<usize>, light: Light) -> Light {
+        self.light.set(Self::index_of(pos), light)
+    }
+
+    /// If every block in this section is the same, returns that block.
+    pub fn uniform(&self) -> Option<Block> {
+        self.blocks.uniform()
+    }
+
+    /// Whether this section only contains air.
+    pub fn is_air(&self) -> bool {
+        self.uniform().is_some_and(Block::is_air)
+    }
 
-pub fn compute_height(generator: &noise::BasicMulti<Perlin>, world_x: f64, world_z: f64) -> i32 {
-    let height = generator.get([world_x, world_z]);
-    // Noise values are in range [-1, 1]
-    // then adding 1 will transform them to [0, 2]
-    // Dividing each of the new values by 2 will re-scale them to the final range [0,1]
-    let height = height + 1.0 / 2.0;
-    // Now we scale it to appropiate chunk height
-    (height * Chunk::SIZE.y as f64) as i32
+    pub fn heap_size(&self) -> usize {
+        self.blocks.heap_size() + self.light.heap_size()
+    }
 }
 
 impl Chunk {
     pub const SIZE: Vec3<usize> = Vec3::new(16, 256, 16);
 
-    pub fn flat(id: BlockId) -> Self {
+    pub const VOLUME: usize = Self::SIZE.x * Self::SIZE.y * Self::SIZE.z;
+
+    /// The number of columns of blocks in a chunk.
+    pub const COLUMNS: usize = Self::SIZE.x * Self::SIZE.z;
+
+    /// The number of sections stacked in a chunk.
+    pub const SECTION_COUNT: usize = Self::SIZE.y / ChunkSection::SIZE.y;
+
+    pub fn flat(block: impl Into<Block>) -> Self {
         Self {
-            blocks: [id; Self::SIZE.x * Self::SIZE.y * Self::SIZE.z],
+            sections: vec![ChunkSection::flat(block); Self::SECTION_COUNT],
+            biomes: vec![Biome::default(); Self::COLUMNS],
+            status: GenerationStatus::Full,
+            dirty: HashSet::new(),
         }
     }
 
-    pub fn generate(generator: &noise::BasicMulti<Perlin>, offset: Vec2<i32>) -> Self {
-        let world_x = (offset.x * Self::SIZE.x as i32) as f64;
-        let world_z = (offset.y * Self::SIZE.z as i32) as f64;
-
-        let mut blocks = [BlockId::Air; 16 * 256 * 16];
+    /// Creates the terrain of a chunk from its sections, from the bottom up, and the biomes
+    /// of its columns. Its status is [`GenerationStatus::Terrain`].
+    pub fn from_sections(sections: Vec<ChunkSection>, biomes: Vec<Biome>) -> Self {
+        debug_assert_eq!(sections.len(), Self::SECTION_COUNT);
+        debug_assert_eq!(biomes.len(), Self::COLUMNS);
+        Self {
+            sections,
+            biomes,
+            status: GenerationStatus::Terrain,
+            dirty: HashSet::new(),
+        }
+    }
 
-        blocks.par_iter_mut().enumerate().for_each(|(id, block)| {
-            let x = id % Self::SIZE.x;
-            let y = (id / Self::SIZE.x) % Self::SIZE.y;
-            let z = (id / (Self::SIZE.x * Self::SIZE.y)) % Self::SIZE.z;
+    pub fn status(&self) -> GenerationStatus {
+        self.status
+    }
 
-            let noise_x = (world_x + x as f64) / 330.0;
-            let noise_z = (world_z + z as f64) / 400.0;
-            let height = compute_height(generator, noise_x, noise_z);
+    pub fn set_status(&mut self, status: GenerationStatus) {
+        self.status = status;
+    }
 
-            let offset = 700.0;
-            let noise_x = (world_x + x as f64) / offset;
-            let noise_z = (world_z + z as f64) / offset;
-            let stone_height = compute_height(generator, noise_x, noise_z);
-            let stone_height = ((stone_height as f32) * 0.7) as i32;
+    /// The index of a column in [`Chunk::biomes`], or `None` if it is out of bounds.
+    pub fn column_index(column: Vec2<i32>) -> Option<usize> {
+        let size = Self::SIZE.map(|x| x as i32);
+        if column.is_any_negative() || column.x >= size.x || column.y >= size.z {
+            return None;
+        }
+        Some((column.x + column.y * size.x) as usize)
+    }
 
-            let y = y as i32;
+    /// Returns the biome of a column, given by its local X and Z coordinates.
+    pub fn biome(&self, column: Vec2<i32>) -> Option<Biome> {
+        Self::column_index(column).map(|index| self.biomes[index])
+    }
 
-            if y == height {
-                *block = BlockId::Grass
-            } else if y < height && y > stone_height {
-                *block = BlockId::Dirt;
-                if y >= 255 {
-                    *block = BlockId::Grass;
-                }
-            } else if y < stone_height {
-                *block = BlockId::Stone
-            } else {
-                *block = BlockId::Air
-            }
-        });
+    pub fn set_biome(&mut self, column: Vec2<i32>, biome: Biome) {
+        if let Some(index) = Self::column_index(column) {
+            self.biomes[index] = biome;
+        }
+    }
 
-        Self { blocks }
+    /// The biome of every column, indexed by [`Chunk::column_index`].
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
     }
 
     pub fn index_of(pos: Vec3<i32>) -> Option<usize> {
@@ -85,7 +183,132 @@ impl Chunk {
     }
 
     pub fn get(&self, pos: Vec3<i32>) -> Option<BlockId> {
-        Self::index_of(pos).map(|idx| self.blocks[idx])
+        self.get_block(pos).map(|block| block.id)
+    }
+
+    /// Returns the block at `pos` together with its state.
+    pub fn get_block(&self, pos: Vec3<i32>) -> Option<Block> {
+        if Self::out_of_bounds(pos) {
+            return None;
+        }
+        let pos = pos.map(|x| x as usize);
+        let section = &self.sections[pos.y / ChunkSection::SIZE.y];
+        Some(section.get(Vec3::new(pos.x, pos.y % ChunkSection::SIZE.y, pos.z)))
+    }
+
+    /// Replaces the block at `pos` and returns the previous one.
+    ///
+    /// Returns `None` without doing anything if `pos` is out of bounds.
+    /// The position is recorded as dirty when the block changes.
+    pub fn set(&mut self, pos: Vec3<i32>, block: impl Into<Block>) -> Option<Block> {
+        if Self::out_of_bounds(pos) {
+            return None;
+        }
+        let block = block.into();
+        let local = pos.map(|x| x as usize);
+        let section = &mut self.sections[local.y / ChunkSection::SIZE.y];
+        let old = section.set(
+            Vec3::new(local.x, local.y % ChunkSection::SIZE.y, local.z),
+            block,
+        );
+        if old != block {
+            self.dirty.insert(pos);
+        }
+        Some(old)
+    }
+
+    /// Returns the light level at `pos`, or `None` if it is out of bounds.
+    pub fn light(&self, pos: Vec3<i32>) -> Option<Light> {
+        if Self::out_of_bounds(pos) {
+            return None;
+        }
+        let pos = pos.map(|x| x as usize);
+        let section = &self.sections[pos.y / ChunkSection::SIZE.y];
+        Some(section.light(Vec3::new(pos.x, pos.y % ChunkSection::SIZE.y, pos.z)))
+    }
+
+    /// Replaces the light level at `pos` and returns the previous one.
+    ///
+    /// Unlike [`Chunk::set`], this doesn't mark the position as dirty.
+    pub fn set_light(&mut self, pos: Vec3<i32>, light: Light) -> Option<Light> {
+        if Self::out_of_bounds(pos) {
+            return None;
+        }
+        let local = pos.map(|x| x as usize);
+        let section = &mut self.sections[local.y / ChunkSection::SIZE.y];
+        Some(section.set_light(
+            Vec3::new(local.x, local.y % ChunkSection::SIZE.y, local.z),
+            light,
+        ))
+    }
+
+    /// Whether any block was edited since the last call to [`Chunk::take_dirty`].
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// The local positions of the blocks edited since the last call to [`Chunk::take_dirty`].
+    pub fn dirty(&self) -> &HashSet<Vec3<i32>> {
+        &self.dirty
+    }
+
+    /// Returns the edited positions and clears the dirty set.
+    pub fn take_dirty(&mut self) -> HashSet<Vec3<i32>> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Splits a world block position into the position of its chunk and the position inside that chunk.
+    pub fn world_to_local(pos: Vec3<i32>) -> (Vec2<i32>, Vec3<i32>) {
+        let size = Self::SIZE.map(|x| x as i32);
+        let chunk = Vec2::new(pos.x.div_euclid(size.x), pos.z.div_euclid(size.z));
+        let local = Vec3::new(pos.x.rem_euclid(size.x), pos.y, pos.z.rem_euclid(size.z));
+        (chunk, local)
+    }
+
+    /// Returns the offsets of the neighbouring chunks touching a local position.
+    ///
+    /// That is none for the inner blocks, one for the blocks on a border
+    /// and three for the blocks in a corner, including the diagonal chunk.
+    pub fn touching_neighbours(local: Vec3<i32>) -> impl Iterator<Item = Vec2<i32>> {
+        let max = Self::SIZE.map(|x| x as i32 - 1);
+        let side = |x: i32, max: i32| match x {
+            0 => -1,
+            x if x == max => 1,
+            _ => 0,
+        };
+        let offset = Vec2::new(side(local.x, max.x), side(local.z, max.z));
+        let corner = offset.x != 0 && offset.y != 0;
+        [
+            Vec2::new(offset.x, 0),
+            Vec2::new(0, offset.y),
+            if corner { offset } else { Vec2::zero() },
+        ]
+        .into_iter()
+        .filter(|offset| *offset != Vec2::zero())
+    }
+
+    /// The inverse of [`Chunk::world_to_local`].
+    pub fn local_to_world(chunk: Vec2<i32>, local: Vec3<i32>) -> Vec3<i32> {
+        let size = Self::SIZE.map(|x| x as i32);
+        Vec3::new(
+            chunk.x * size.x + local.x,
+            local.y,
+            chunk.y * size.z + local.z,
+        )
+    }
+
+    /// Returns the section at the given index, counting from the bottom of the chunk.
+    pub fn section(&self, index: usize) -> Option<&ChunkSection> {
+        self.sections.get(index)
+    }
+
+    pub fn sections(&self) -> &[ChunkSection] {
+        &self.sections
+    }
+
+    /// Returns the approximate number of heap bytes used by the blocks and light of this chunk.
+    pub fn heap_size(&self) -> usize {
+        self.sections.iter().map(ChunkSection::heap_size).sum()
     }
 
     pub fn within_bounds(pos: Vec3<i32>) -> bool {
@@ -105,13 +328,72 @@ impl Chunk {
             size: Self::SIZE.map(|x| x as u32),
         }
     }
+
+    /// Iterates over the positions of every section that is not entirely air.
+    ///
+    /// Positions are yielded section by section, from the bottom of the chunk to the top.
+    pub fn iter_non_air(&self) -> impl Iterator<Item = Vec3<i32>> + '_ {
+        self.sections
+            .iter()
+            .enumerate()
+            .filter(|(_, section)| !section.is_air())
+            .flat_map(|(index, _)| {
+                let base_y = (index * ChunkSection::SIZE.y) as i32;
+                ChunkIter {
+                    index: 0,
+                    size: ChunkSection::SIZE.map(|x| x as u32),
+                }
+                .map(move |pos| pos + Vec3::unit_y() * base_y)
+            })
+    }
 }
 
-pub fn compress(c: &Chunk) -> Vec<(BlockId, u32)> {
-    let mut compressed = Vec::with_capacity(600);
-    let mut current_block = c.blocks[0];
+/// A section of a chunk encoded for the network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CompressedSection {
+    /// Every block in the section is the same.
+    Uniform(Block),
+    /// The blocks of the section, run length encoded.
+    Runs(Vec<(Block, u32)>),
+}
+
+/// A chunk encoded for the network.
+///
+/// Sections made entirely of air are not included.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompressedChunk {
+    pub sections: Vec<(u8, CompressedSection)>,
+    /// The biomes of the columns, run length encoded.
+    pub biomes: Vec<(Biome, u16)>,
+}
+
+pub fn compress(c: &Chunk) -> CompressedChunk {
+    let mut sections = Vec::with_capacity(Chunk::SECTION_COUNT);
+    for (index, section) in c.sections.iter().enumerate() {
+        if section.is_air() {
+            continue;
+        }
+        let compressed = match section.uniform() {
+            Some(block) => CompressedSection::Uniform(block),
+            None => CompressedSection::Runs(compress_section(section)),
+        };
+        sections.push((index as u8, compressed));
+    }
+    let mut biomes: Vec<(Biome, u16)> = Vec::new();
+    for biome in &c.biomes {
+        match biomes.last_mut() {
+            Some((last, count)) if last == biome => *count += 1,
+            _ => biomes.push((*biome, 1)),
+        }
+    }
+    CompressedChunk { sections, biomes }
+}
+
+fn compress_section(section: &ChunkSection) -> Vec<(Block, u32)> {
+    let mut compressed = Vec::with_capacity(64);
+    let mut current_block = section.blocks.get(0);
     let mut count = 1;
-    for &block in c.blocks.iter().skip(1) {
+    for block in section.blocks.iter().skip(1) {
         // run length encoding
         if block == current_block {
             count += 1;
@@ -126,16 +408,34 @@ pub fn compress(c: &Chunk) -> Vec<(BlockId, u32)> {
     compressed
 }
 
-pub fn decompress(compressed: &[(BlockId, u32)]) -> Chunk {
-    let mut blocks = [BlockId::Air; 16 * 256 * 16];
-    let mut index = 0;
-    for (block, count) in compressed {
-        for _ in 0..*count {
-            blocks[index] = *block;
-            index += 1;
-        }
+pub fn decompress(compressed: &CompressedChunk) -> Chunk {
+    let mut chunk = Chunk::flat(Block::AIR);
+    for (index, section) in &compressed.sections {
+        let Some(target) = chunk.sections.get_mut(*index as usize) else {
+            log::warn!("Ignoring out of bounds chunk section {}", index);
+            continue;
+        };
+        *target = match section {
+            CompressedSection::Uniform(block) => ChunkSection::flat(*block),
+            CompressedSection::Runs(runs) => {
+                let mut blocks = Vec::with_capacity(ChunkSection::VOLUME);
+                // The runs come from the network, so they can't grow the section past its volume
+                for (block, count) in runs {
+                    let count = (*count as usize).min(ChunkSection::VOLUME - blocks.len());
+                    blocks.resize(blocks.len() + count, *block);
+                }
+                blocks.resize(ChunkSection::VOLUME, Block::AIR);
+                ChunkSection::from_blocks(&blocks)
+            },
+        };
+    }
+    let mut column = 0;
+    for (biome, count) in &compressed.biomes {
+        let end = (column + *count as usize).min(Chunk::COLUMNS);
+        chunk.biomes[column..end].fill(*biome);
+        column = end;
     }
-    Chunk { blocks }
+    chunk
 }
 
 pub struct ChunkIter {
@@ -161,16 +461,21 @@ impl Iterator for ChunkIter {
 
 #[cfg(test)]
 mod tests {
-    use vek::Vec3;
+    use vek::{Vec2, Vec3};
 
     use crate::{
-        block::BlockId,
-        chunk::{compress, Chunk},
+        biome::Biome,
+        block::{Block, BlockId, BlockState},
+        chunk::{compress, decompress, Chunk, CompressedChunk, CompressedSection},
     };
 
+    const DIRT: BlockId = BlockId(1);
+    const GRASS: BlockId = BlockId(2);
+    const STONE: BlockId = BlockId(3);
+
     #[test]
     pub fn chunk_iter_works() {
-        let chunk = Chunk::flat(BlockId::Air);
+        let chunk = Chunk::flat(BlockId::AIR);
         let mut count = 0;
 
         for pos in chunk.iter() {
@@ -193,9 +498,124 @@ mod tests {
 
     #[test]
     pub fn chunk_compression_test() {
-        let chunk = Chunk::flat(BlockId::Dirt);
+        let chunk = Chunk::flat(DIRT);
         let compressed = compress(&chunk);
-        assert_eq!(compressed.len(), 1);
-        assert_eq!(compressed[0], (BlockId::Dirt, 16 * 256 * 16));
+        assert_eq!(compressed.sections.len(), Chunk::SECTION_COUNT);
+        assert!(compressed
+            .sections
+            .iter()
+            .all(|(_, s)| *s == CompressedSection::Uniform(Block::new(DIRT))));
+
+        let chunk = Chunk::flat(BlockId::AIR);
+        assert!(compress(&chunk).sections.is_empty());
+        assert_eq!(chunk.iter_non_air().count(), 0);
+    }
+
+    #[test]
+    pub fn chunk_decompression_roundtrip() {
+        let runs = vec![
+            (Block::new(STONE), 16 * 16 * 8),
+            (Block::new(DIRT), 16 * 16 * 4),
+            (Block::with_state(GRASS, BlockState(1)), 16 * 16),
+            (Block::AIR, 16 * 16 * 3),
+        ];
+        let compressed = crate::chunk::CompressedChunk {
+            sections: vec![
+                (0, CompressedSection::Uniform(Block::new(STONE))),
+                (3, CompressedSection::Runs(runs)),
+            ],
+            biomes: vec![(Biome::Plains, 256)],
+        };
+        let chunk = decompress(&compressed);
+        assert_eq!(chunk.get(Vec3::new(3, 10, 0)), Some(STONE));
+        assert_eq!(chunk.get(Vec3::new(3, 20, 0)), Some(BlockId::AIR));
+        assert_eq!(chunk.get(Vec3::new(3, 48, 3)), Some(STONE));
+        assert_eq!(chunk.get(Vec3::new(0, 50, 12)), Some(GRASS));
+        assert_eq!(
+            chunk.get_block(Vec3::new(0, 50, 12)).map(|b| b.state),
+            Some(BlockState(1))
+        );
+        assert_eq!(chunk.get(Vec3::new(15, 255, 15)), Some(BlockId::AIR));
+        assert_eq!(compress(&chunk), compressed);
+        assert_eq!(chunk.iter_non_air().count(), 2 * 16 * 16 * 16);
+        // 4 block types fit in 2 bits per block
+        assert!(chunk.heap_size() < 4096);
+    }
+
+    #[test]
+    pub fn biomes_survive_compression() {
+        let mut chunk = Chunk::flat(DIRT);
+        for x in 0..16 {
+            chunk.set_biome(Vec2::new(x, 3), Biome::Desert);
+        }
+        chunk.set_biome(Vec2::new(15, 15), Biome::Tundra);
+        chunk.set_biome(Vec2::new(16, 0), Biome::Forest);
+
+        let compressed = compress(&chunk);
+        assert_eq!(
+            compressed.biomes,
+            vec![
+                (Biome::Plains, 48),
+                (Biome::Desert, 16),
+                (Biome::Plains, 191),
+                (Biome::Tundra, 1),
+            ]
+        );
+        let chunk = decompress(&compressed);
+        assert_eq!(chunk.biome(Vec2::new(7, 3)), Some(Biome::Desert));
+        assert_eq!(chunk.biome(Vec2::new(7, 4)), Some(Biome::Plains));
+        assert_eq!(chunk.biome(Vec2::new(15, 15)), Some(Biome::Tundra));
+        assert_eq!(chunk.biome(Vec2::new(16, 0)), None);
+    }
+
+    #[test]
+    pub fn oversized_runs_are_clamped() {
+        let compressed = CompressedChunk {
+            sections: vec![(
+                0,
+                CompressedSection::Runs(vec![
+                    (Block::new(STONE), u32::MAX),
+                    (Block::new(DIRT), u32::MAX),
+                ]),
+            )],
+            biomes: Vec::new(),
+        };
+        let chunk = decompress(&compressed);
+        assert_eq!(
+            chunk.get_block(Vec3::new(15, 15, 15)),
+            Some(Block::new(STONE))
+        );
+        assert_eq!(chunk.get_block(Vec3::new(0, 16, 0)), Some(Block::AIR));
+    }
+
+    #[test]
+    pub fn chunk_set_records_dirty_blocks() {
+        let mut chunk = Chunk::flat(BlockId::AIR);
+        assert_eq!(chunk.set(Vec3::new(1, 40, 2), STONE), Some(Block::AIR));
+        assert_eq!(
+            chunk.set(Vec3::new(1, 40, 2), STONE),
+            Some(Block::new(STONE))
+        );
+        assert_eq!(chunk.set(Vec3::new(0, 256, 0), STONE), None);
+        assert_eq!(chunk.get(Vec3::new(1, 40, 2)), Some(STONE));
+        assert_eq!(chunk.iter_non_air().count(), 16 * 16 * 16);
+
+        assert!(chunk.is_dirty());
+        let dirty = chunk.take_dirty();
+        assert_eq!(dirty.len(), 1);
+        assert!(dirty.contains(&Vec3::new(1, 40, 2)));
+        assert!(!chunk.is_dirty());
+    }
+
+    #[test]
+    pub fn world_to_local_handles_negative_positions() {
+        let (chunk, local) = Chunk::world_to_local(Vec3::new(-1, 10, -16));
+        assert_eq!(chunk, vek::Vec2::new(-1, -1));
+        assert_eq!(local, Vec3::new(15, 10, 0));
+        assert_eq!(Chunk::local_to_world(chunk, local), Vec3::new(-1, 10, -16));
+
+        let (chunk, local) = Chunk::world_to_local(Vec3::new(33, 0, 15));
+        assert_eq!(chunk, vek::Vec2::new(2, 0));
+        assert_eq!(local, Vec3::new(1, 0, 15));
     }
 }
@@ -1,6 +1,6 @@
 use vek::Vec3;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Direction {
     North,
     South,
@@ -11,6 +11,15 @@ pub enum Direction {
 }
 
 impl Direction {
+    pub const ALL: [Direction; 6] = [
+        Direction::North,
+        Direction::South,
+        Direction::East,
+        Direction::West,
+        Direction::Up,
+        Direction::Down,
+    ];
+
     pub fn vec(self) -> Vec3<i32> {
         match self {
             Direction::North => Vec3::unit_z(),
@@ -21,4 +30,42 @@ impl Direction {
             Direction::Down => -Vec3::unit_y(),
         }
     }
+
+    /// Returns the direction pointing along the given unit vector.
+    pub fn from_vec(vec: Vec3<i32>) -> Option<Self> {
+        Self::ALL.into_iter().find(|dir| dir.vec() == vec)
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        }
+    }
+
+    /// Rotates this direction by `turns` quarter turns around the X axis.
+    ///
+    /// A positive quarter turn takes `Up` to `North`.
+    pub fn rotate_x(self, turns: i32) -> Self {
+        let mut v = self.vec();
+        for _ in 0..turns.rem_euclid(4) {
+            v = Vec3::new(v.x, -v.z, v.y);
+        }
+        Self::from_vec(v).unwrap()
+    }
+
+    /// Rotates this direction by `turns` quarter turns around the Y axis.
+    ///
+    /// A positive quarter turn takes `North` to `East`.
+    pub fn rotate_y(self, turns: i32) -> Self {
+        let mut v = self.vec();
+        for _ in 0..turns.rem_euclid(4) {
+            v = Vec3::new(v.z, v.y, -v.x);
+        }
+        Self::from_vec(v).unwrap()
+    }
 }
@@ -0,0 +1,345 @@
+use std::collections::{BTreeMap, HashSet};
+
+use vek::Vec3;
+
+use crate::{
+    block::{Block, BlockDescriptor, BlockId, BlockRegistry},
+    dir::Direction,
+    model::{BlockModel, ModelBox, ModelFaces, MODEL_SIZE},
+    resources::TerrainMap,
+};
+
+/// The sides fluids spread to when they can't fall.
+const HORIZONTAL: [Direction; 4] = [
+    Direction::North,
+    Direction::South,
+    Direction::East,
+    Direction::West,
+];
+
+/// The height of a fluid block in model units, with the same fluid above it or not.
+///
+/// Sources are a bit lower than a full block so the surface stays visible,
+/// and each level drops the surface further.
+pub fn fill_height(level: u8, max_level: u8, covered: bool) -> u8 {
+    if covered {
+        return MODEL_SIZE;
+    }
+    let levels = max_level as u32 + 1;
+    let height = (levels - level as u32) * (MODEL_SIZE as u32 - 2) / levels;
+    height.max(2) as u8
+}
+
+/// The model of a fluid block filled up to `height`.
+pub fn fluid_model(height: u8) -> BlockModel {
+    BlockModel::Boxes {
+        boxes: vec![ModelBox {
+            from: [0; 3],
+            to: [MODEL_SIZE, height, MODEL_SIZE],
+            faces: ModelFaces::default(),
+        }],
+    }
+}
+
+/// Spreads and drains fluids through the loaded terrain.
+///
+/// Positions are scheduled when a block changes next to them and updated a few fluid
+/// ticks later, depending on [`crate::block::FluidDescriptor::tick_delay`].
+/// The blocks are edited with [`TerrainMap::set_block`], so the changes end up in the
+/// dirty sets of the chunks like any other edit.
+#[derive(Default)]
+pub struct FluidSimulation {
+    tick: u64,
+    /// Time left over from the previous call to [`FluidSimulation::advance`].
+    elapsed: f32,
+    scheduled: BTreeMap<u64, HashSet<Vec3<i32>>>,
+}
+
+impl FluidSimulation {
+    /// The duration of a fluid tick in seconds.
+    pub const TICK: f32 = 0.05;
+
+    /// The most fluid ticks run by a single call to [`FluidSimulation::advance`].
+    /// A slow server drops the rest rather than falling further behind.
+    const MAX_STEPS: u32 = 4;
+
+    /// Runs the fluid ticks that fit in `dt` seconds.
+    pub fn advance(&mut self, terrain: &mut TerrainMap, registry: &BlockRegistry, dt: f32) {
+        self.elapsed += dt;
+        let mut steps = 0;
+        while self.elapsed >= Self::TICK {
+            self.elapsed -= Self::TICK;
+            if steps == Self::MAX_STEPS {
+                self.elapsed = 0.0;
+                break;
+            }
+            self.step(terrain, registry);
+            steps += 1;
+        }
+    }
+
+    /// Runs a single fluid tick.
+    pub fn step(&mut self, terrain: &mut TerrainMap, registry: &BlockRegistry) {
+        self.tick += 1;
+        let due = self
+            .scheduled
+            .keys()
+            .take_while(|tick| **tick <= self.tick)
+            .copied()
+            .collect::<Vec<_>>();
+        for tick in due {
+            for pos in self.scheduled.remove(&tick).unwrap_or_default() {
+                self.update(terrain, registry, pos);
+            }
+        }
+    }
+
+    /// Whether any update is waiting for a future tick.
+    pub fn is_idle(&self) -> bool {
+        self.scheduled.is_empty()
+    }
+
+    /// Schedules an update of the fluids at and around a block that changed.
+    pub fn block_changed(
+        &mut self,
+        terrain: &TerrainMap,
+        registry: &BlockRegistry,
+        pos: Vec3<i32>,
+    ) {
+        let around = Direction::ALL.map(|dir| pos + dir.vec());
+        for pos in std::iter::once(pos).chain(around) {
+            let fluid = terrain
+                .get_block(pos)
+                .and_then(|block| registry.get(block.id))
+                .and_then(|descriptor| descriptor.fluid.as_ref());
+            if let Some(fluid) = fluid {
+                self.scheduled
+                    .entry(self.tick + fluid.tick_delay.max(1) as u64)
+                    .or_default()
+                    .insert(pos);
+            }
+        }
+    }
+
+    fn update(&mut self, terrain: &mut TerrainMap, registry: &BlockRegistry, pos: Vec3<i32>) {
+        let Some((block, descriptor, mut level)) = fluid_at(terrain, registry, pos) else {
+            return;
+        };
+        let max_level = descriptor.max_fluid_level();
+
+        // Flowing fluid is fed by the fluid above it, or by a higher level next to it.
+        if level > 0 {
+            let above = fluid_at(terrain, registry, pos + Vec3::unit_y())
+                .filter(|(above, _, _)| above.id == block.id);
+            let fed = if above.is_some() {
+                Some(1)
+            } else {
+                HORIZONTAL
+                    .iter()
+                    .filter_map(|dir| fluid_at(terrain, registry, pos + dir.vec()))
+                    .filter(|(side, _, _)| side.id == block.id)
+                    .map(|(_, _, side_level)| side_level + 1)
+                    .filter(|fed| *fed <= max_level)
+                    .min()
+            };
+            match fed {
+                None => {
+                    self.set(terrain, registry, pos, Block::AIR);
+                    return;
+                },
+                Some(fed) if fed != level => {
+                    self.set_level(terrain, registry, descriptor, block.id, pos, fed);
+                    level = fed;
+                },
+                Some(_) => {},
+            }
+        }
+
+        // Falling comes first, fluid only spreads sideways once it rests on something
+        let below = pos - Vec3::unit_y();
+        match terrain.get_block(below) {
+            Some(other) if other.id == block.id => return,
+            Some(other) if can_flow_into(registry, other, block.id, 1) => {
+                self.set_level(terrain, registry, descriptor, block.id, below, 1);
+                return;
+            },
+            _ => {},
+        }
+        if level >= max_level {
+            return;
+        }
+        for dir in HORIZONTAL {
+            let side = pos + dir.vec();
+            if terrain
+                .get_block(side)
+                .is_some_and(|other| can_flow_into(registry, other, block.id, level + 1))
+            {
+                self.set_level(terrain, registry, descriptor, block.id, side, level + 1);
+            }
+        }
+    }
+
+    fn set_level(
+        &mut self,
+        terrain: &mut TerrainMap,
+        registry: &BlockRegistry,
+        descriptor: &BlockDescriptor,
+        id: BlockId,
+        pos: Vec3<i32>,
+        level: u8,
+    ) {
+        if let Some(state) = descriptor.fluid_state(level) {
+            self.set(terrain, registry, pos, Block::with_state(id, state));
+        }
+    }
+
+    fn set(
+        &mut self,
+        terrain: &mut TerrainMap,
+        registry: &BlockRegistry,
+        pos: Vec3<i32>,
+        block: Block,
+    ) {
+        if terrain
+            .set_block(pos, block)
+            .is_some_and(|old| old != block)
+        {
+            self.block_changed(terrain, registry, pos);
+        }
+    }
+}
+
+/// Returns the fluid block at `pos`, its descriptor and its level.
+fn fluid_at<'a>(
+    terrain: &TerrainMap,
+    registry: &'a BlockRegistry,
+    pos: Vec3<i32>,
+) -> Option<(Block, &'a BlockDescriptor, u8)> {
+    let block = terrain.get_block(pos)?;
+    let descriptor = registry.get(block.id)?;
+    let level = descriptor.fluid_level(block.state)?;
+    Some((block, descriptor, level))
+}
+
+/// Fluids replace air and blocks entities walk through, like plants,
+/// and lower the level of the same fluid.
+fn can_flow_into(registry: &BlockRegistry, block: Block, fluid: BlockId, level: u8) -> bool {
+    if block.is_air() {
+        return true;
+    }
+    let Some(descriptor) = registry.get(block.id) else {
+        return false;
+    };
+    if block.id == fluid {
+        return descriptor
+            .fluid_level(block.state)
+            .is_some_and(|current| current > level);
+    }
+    !descriptor.solid && descriptor.fluid.is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::{Vec2, Vec3};
+
+    use crate::{
+        block::{Block, BlockDescriptor, BlockRegistry},
+        chunk::Chunk,
+        resources::TerrainMap,
+    };
+
+    use super::{fill_height, FluidSimulation};
+
+    fn registry() -> BlockRegistry {
+        let stone: BlockDescriptor =
+            toml::from_str("name = \"Stone\"\n[textures]\nall = \"stone\"").unwrap();
+        let water: BlockDescriptor = toml::from_str(
+            r#"
+            name = "Water"
+            solid = false
+            [fluid]
+            tick_delay = 1
+            [states]
+            level = ["0", "1", "2", "3"]
+            "#,
+        )
+        .unwrap();
+        BlockRegistry::from_descriptors(vec![stone, water]).unwrap()
+    }
+
+    /// Two chunks side by side with a stone floor at y = 9.
+    fn terrain(registry: &BlockRegistry) -> TerrainMap {
+        let stone = registry.id("stone").unwrap();
+        let mut terrain = TerrainMap::default();
+        for pos in [Vec2::new(0, 0), Vec2::new(-1, 0)] {
+            let mut chunk = Chunk::flat(Block::AIR);
+            for x in 0..16 {
+                for z in 0..16 {
+                    chunk.set(Vec3::new(x, 9, z), stone);
+                }
+            }
+            chunk.take_dirty();
+            terrain.chunks.insert(pos, chunk);
+        }
+        terrain
+    }
+
+    fn run(simulation: &mut FluidSimulation, terrain: &mut TerrainMap, registry: &BlockRegistry) {
+        for _ in 0..100 {
+            if simulation.is_idle() {
+                return;
+            }
+            simulation.step(terrain, registry);
+        }
+        panic!("The fluid never settled");
+    }
+
+    fn level(terrain: &TerrainMap, registry: &BlockRegistry, pos: Vec3<i32>) -> Option<u8> {
+        let block = terrain.get_block(pos)?;
+        registry.get(block.id)?.fluid_level(block.state)
+    }
+
+    #[test]
+    pub fn fluids_fall_then_spread_across_chunks() {
+        let registry = registry();
+        let water = registry.id("water").unwrap();
+        let mut terrain = terrain(&registry);
+        let mut simulation = FluidSimulation::default();
+
+        let source = Vec3::new(1, 12, 4);
+        terrain.set_block(source, water);
+        simulation.block_changed(&terrain, &registry, source);
+        run(&mut simulation, &mut terrain, &registry);
+
+        // It falls down to the floor, then spreads one level per block
+        assert_eq!(level(&terrain, &registry, Vec3::new(1, 11, 4)), Some(1));
+        assert_eq!(level(&terrain, &registry, Vec3::new(1, 10, 4)), Some(1));
+        assert_eq!(level(&terrain, &registry, Vec3::new(0, 10, 4)), Some(2));
+        // Into the chunk on the west
+        assert_eq!(level(&terrain, &registry, Vec3::new(-1, 10, 4)), Some(3));
+        assert_eq!(level(&terrain, &registry, Vec3::new(-2, 10, 4)), None);
+        // Falling water doesn't spread sideways
+        assert_eq!(level(&terrain, &registry, Vec3::new(0, 11, 4)), None);
+
+        // Removing the source drains everything
+        terrain.set_block(source, Block::AIR);
+        simulation.block_changed(&terrain, &registry, source);
+        run(&mut simulation, &mut terrain, &registry);
+        for pos in [
+            Vec3::new(1, 11, 4),
+            Vec3::new(1, 10, 4),
+            Vec3::new(-1, 10, 4),
+        ] {
+            assert_eq!(terrain.get_block(pos), Some(Block::AIR));
+        }
+        assert!(!terrain.chunks[&Vec2::new(-1, 0)].dirty().is_empty());
+    }
+
+    #[test]
+    pub fn fill_height_drops_with_the_level() {
+        assert_eq!(fill_height(0, 7, false), 14);
+        assert_eq!(fill_height(7, 7, false), 2);
+        assert!(fill_height(3, 7, false) < fill_height(2, 7, false));
+        assert_eq!(fill_height(5, 7, true), 16);
+    }
+}
@@ -1,10 +1,16 @@
+pub mod biome;
 pub mod block;
 pub mod chunk;
 pub mod clock;
 pub mod components;
 pub mod dir;
 pub mod event;
+pub mod fluid;
+pub mod light;
+pub mod model;
 pub mod net;
+pub mod palette;
+pub mod ray;
 pub mod resources;
 pub mod state;
 pub mod uid;
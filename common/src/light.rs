@@ -0,0 +1,498 @@
+use std::collections::{HashSet, VecDeque};
+
+use vek::{Vec2, Vec3};
+
+use crate::{
+    block::{Block, BlockRegistry},
+    chunk::Chunk,
+    dir::Direction,
+    resources::TerrainMap,
+};
+
+/// The light level of a block, with the sky light in the high 4 bits
+/// and the block light in the low 4 bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Light(pub u8);
+
+impl Light {
+    pub const DARK: Self = Self(0);
+
+    /// The brightest level of either kind of light.
+    pub const MAX_LEVEL: u8 = 15;
+
+    pub const fn new(sky: u8, block: u8) -> Self {
+        Self((sky << 4) | (block & 0xF))
+    }
+
+    pub const fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub const fn block(self) -> u8 {
+        self.0 & 0xF
+    }
+
+    pub const fn get(self, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Sky => self.sky(),
+            LightChannel::Block => self.block(),
+        }
+    }
+
+    pub const fn with(self, channel: LightChannel, level: u8) -> Self {
+        match channel {
+            LightChannel::Sky => Self::new(level, self.block()),
+            LightChannel::Block => Self::new(self.sky(), level),
+        }
+    }
+}
+
+/// The two kinds of light, which spread independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightChannel {
+    /// Light coming from the sky. It falls straight down without losing any strength.
+    Sky,
+    /// Light emitted by blocks, see [`crate::block::BlockDescriptor::light_emission`].
+    Block,
+}
+
+impl LightChannel {
+    pub const ALL: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+}
+
+/// Spreads light through the loaded terrain with breadth first flood fills.
+///
+/// Light crosses chunk borders as long as the chunk on the other side is loaded.
+/// Every chunk whose light changed is collected, see [`LightEngine::into_changed`].
+pub struct LightEngine<'a> {
+    terrain: &'a mut TerrainMap,
+    registry: &'a BlockRegistry,
+    changed: HashSet<Vec2<i32>>,
+}
+
+impl<'a> LightEngine<'a> {
+    pub fn new(terrain: &'a mut TerrainMap, registry: &'a BlockRegistry) -> Self {
+        Self {
+            terrain,
+            registry,
+            changed: HashSet::new(),
+        }
+    }
+
+    /// Returns the chunks whose light changed.
+    ///
+    /// A change on a chunk border also includes the chunks touching it,
+    /// since their faces on that border are lit by it.
+    pub fn into_changed(self) -> HashSet<Vec2<i32>> {
+        self.changed
+    }
+
+    /// Computes the light of a chunk that was just loaded.
+    ///
+    /// The chunk is expected to be dark. Light is also exchanged with the loaded neighbours.
+    pub fn light_chunk(&mut self, pos: Vec2<i32>) {
+        let Some(chunk) = self.terrain.chunks.get(&pos) else {
+            return;
+        };
+        let size = Chunk::SIZE.map(|x| x as i32);
+        let origin = Chunk::local_to_world(pos, Vec3::zero());
+
+        let mut emitters = Vec::new();
+        for local in chunk.iter_non_air() {
+            let emission = chunk
+                .get_block(local)
+                .map_or(0, |block| self.emission(block));
+            if emission > 0 {
+                emitters.push((origin + local, emission));
+            }
+        }
+
+        let mut sky = VecDeque::new();
+        let mut block = VecDeque::new();
+        for (pos, emission) in emitters {
+            self.set_level(pos, LightChannel::Block, emission);
+            block.push_back(pos);
+        }
+
+        // Sky light falls straight down until the first block that stops it
+        let mut heights = vec![0; (size.x * size.z) as usize];
+        for z in 0..size.z {
+            for x in 0..size.x {
+                let column = origin + Vec3::new(x, 0, z);
+                let height = self.column_height(column).unwrap_or(0);
+                for y in height..size.y {
+                    self.set_level(column.with_y(y), LightChannel::Sky, Light::MAX_LEVEL);
+                }
+                heights[(x + z * size.x) as usize] = height;
+            }
+        }
+        // It only spreads sideways below the top of the neighbouring columns,
+        // higher up every block around is already fully lit
+        for z in 0..size.z {
+            for x in 0..size.x {
+                let column = origin + Vec3::new(x, 0, z);
+                let height = heights[(x + z * size.x) as usize];
+                let top = Direction::ALL[..4]
+                    .iter()
+                    .map(|dir| {
+                        let local = Vec3::new(x, 0, z) + dir.vec();
+                        if Chunk::within_bounds(local) {
+                            heights[(local.x + local.z * size.x) as usize]
+                        } else {
+                            self.column_height(column + dir.vec()).unwrap_or(height)
+                        }
+                    })
+                    .max()
+                    .unwrap_or(height);
+                for y in height..top {
+                    sky.push_back(column.with_y(y));
+                }
+            }
+        }
+
+        // Light from the loaded neighbours flows into this chunk
+        for dir in &Direction::ALL[..4] {
+            let normal = dir.vec();
+            let neighbour = pos + Vec2::new(normal.x, normal.z);
+            if !self.terrain.chunks.contains_key(&neighbour) {
+                continue;
+            }
+            for i in 0..size.x {
+                // The column of the neighbour touching this chunk
+                let local = match dir {
+                    Direction::North => Vec3::new(i, 0, size.z),
+                    Direction::South => Vec3::new(i, 0, -1),
+                    Direction::East => Vec3::new(size.x, 0, i),
+                    _ => Vec3::new(-1, 0, i),
+                };
+                for y in 0..size.y {
+                    let border = origin + local.with_y(y);
+                    let light = self.terrain.get_light(border).unwrap_or_default();
+                    if light.sky() > 1 {
+                        sky.push_back(border);
+                    }
+                    if light.block() > 1 {
+                        block.push_back(border);
+                    }
+                }
+            }
+        }
+
+        self.changed.insert(pos);
+        self.spread(LightChannel::Sky, sky);
+        self.spread(LightChannel::Block, block);
+    }
+
+    /// Updates the light around a block that was placed or removed.
+    pub fn update_block(&mut self, pos: Vec3<i32>) {
+        let Some(block) = self.terrain.get_block(pos) else {
+            return;
+        };
+        let top = Chunk::SIZE.y as i32 - 1;
+        for channel in LightChannel::ALL {
+            // First remove the light that went through this block
+            let mut removed = VecDeque::new();
+            let mut spread = VecDeque::new();
+            let old = self.level(pos, channel).unwrap_or(0);
+            if old > 0 {
+                self.set_level(pos, channel, 0);
+                removed.push_back((pos, old));
+            }
+            self.unspread(channel, removed, &mut spread);
+
+            // Then light it again from the block itself and from its surroundings
+            let open_top = !self.covers(block, Direction::Up);
+            match channel {
+                LightChannel::Block => {
+                    let emission = self.emission(block);
+                    if emission > 0 {
+                        self.set_level(pos, channel, emission);
+                        spread.push_back(pos);
+                    }
+                },
+                LightChannel::Sky if open_top && pos.y == top => {
+                    self.set_level(pos, channel, Light::MAX_LEVEL);
+                    spread.push_back(pos);
+                },
+                LightChannel::Sky => {},
+            }
+            for dir in Direction::ALL {
+                let neighbour = pos + dir.vec();
+                if self
+                    .level(neighbour, channel)
+                    .is_some_and(|level| level > 0)
+                {
+                    spread.push_back(neighbour);
+                }
+            }
+            self.spread(channel, spread);
+        }
+    }
+
+    /// Spreads the light of the queued positions to their surroundings.
+    fn spread(&mut self, channel: LightChannel, mut queue: VecDeque<Vec3<i32>>) {
+        while let Some(pos) = queue.pop_front() {
+            let Some(level) = self.level(pos, channel) else {
+                continue;
+            };
+            for dir in Direction::ALL {
+                let next = pos + dir.vec();
+                let next_level = falloff(channel, dir, level);
+                if next_level == 0 || !self.passes(pos, dir) {
+                    continue;
+                }
+                if self.level(next, channel).is_some_and(|l| l < next_level) {
+                    self.set_level(next, channel, next_level);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    /// Removes the light that came from the queued positions, given with their previous level.
+    ///
+    /// Positions lit by another source are left alone and queued in `spread`,
+    /// so that their light can flow back into the darkened area.
+    fn unspread(
+        &mut self,
+        channel: LightChannel,
+        mut queue: VecDeque<(Vec3<i32>, u8)>,
+        spread: &mut VecDeque<Vec3<i32>>,
+    ) {
+        while let Some((pos, level)) = queue.pop_front() {
+            for dir in Direction::ALL {
+                let next = pos + dir.vec();
+                let Some(next_level) = self.level(next, channel) else {
+                    continue;
+                };
+                if next_level == 0 {
+                    continue;
+                }
+                if next_level <= falloff(channel, dir, level) {
+                    self.set_level(next, channel, 0);
+                    queue.push_back((next, next_level));
+                    // Emitters keep their own light
+                    let emission = match channel {
+                        LightChannel::Block => self
+                            .terrain
+                            .get_block(next)
+                            .map_or(0, |block| self.emission(block)),
+                        LightChannel::Sky => 0,
+                    };
+                    if emission > 0 {
+                        self.set_level(next, channel, emission);
+                        spread.push_back(next);
+                    }
+                } else {
+                    spread.push_back(next);
+                }
+            }
+        }
+    }
+
+    /// Returns the y of the lowest block reached by the sky light in a column,
+    /// or `None` if the column is not loaded.
+    fn column_height(&self, column: Vec3<i32>) -> Option<i32> {
+        let (chunk_pos, local) = Chunk::world_to_local(column);
+        let chunk = self.terrain.chunks.get(&chunk_pos)?;
+        let mut y = Chunk::SIZE.y as i32;
+        while y > 0 {
+            let block = chunk.get_block(local.with_y(y - 1))?;
+            if self.covers(block, Direction::Up) {
+                break;
+            }
+            y -= 1;
+            // Like a bottom slab, the block is lit but nothing under it
+            if self.covers(block, Direction::Down) {
+                break;
+            }
+        }
+        Some(y)
+    }
+
+    fn level(&self, pos: Vec3<i32>, channel: LightChannel) -> Option<u8> {
+        self.terrain.get_light(pos).map(|light| light.get(channel))
+    }
+
+    fn set_level(&mut self, pos: Vec3<i32>, channel: LightChannel, level: u8) {
+        let Some(light) = self.terrain.get_light(pos) else {
+            return;
+        };
+        if light.get(channel) == level {
+            return;
+        }
+        self.terrain.set_light(pos, light.with(channel, level));
+        let (chunk_pos, local) = Chunk::world_to_local(pos);
+        self.changed.insert(chunk_pos);
+        self.changed
+            .extend(Chunk::touching_neighbours(local).map(|offset| chunk_pos + offset));
+    }
+
+    /// Whether light can go from `pos` to the block next to it in `dir`.
+    ///
+    /// It can't cross a side filled by either block, except the sides of emitters,
+    /// which light their surroundings. Unloaded blocks stop the light.
+    fn passes(&self, pos: Vec3<i32>, dir: Direction) -> bool {
+        let Some(from) = self.terrain.get_block(pos) else {
+            return false;
+        };
+        let Some(to) = self.terrain.get_block(pos + dir.vec()) else {
+            return false;
+        };
+        (self.emission(from) > 0 || !self.covers(from, dir)) && !self.covers(to, dir.opposite())
+    }
+
+    /// Whether `block` fills its side facing `face`.
+    fn covers(&self, block: Block, face: Direction) -> bool {
+        !block.is_air()
+            && self
+                .registry
+                .get(block.id)
+                .is_some_and(|descriptor| descriptor.covers_face(block.state, face))
+    }
+
+    fn emission(&self, block: Block) -> u8 {
+        self.registry.get(block.id).map_or(0, |descriptor| {
+            descriptor.light_emission.min(Light::MAX_LEVEL)
+        })
+    }
+}
+
+/// The level of light after moving one block from a block lit at `level`.
+fn falloff(channel: LightChannel, dir: Direction, level: u8) -> u8 {
+    if channel == LightChannel::Sky && dir == Direction::Down && level == Light::MAX_LEVEL {
+        Light::MAX_LEVEL
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::{Vec2, Vec3};
+
+    use crate::{
+        block::{Block, BlockDescriptor, BlockRegistry},
+        chunk::Chunk,
+        resources::TerrainMap,
+    };
+
+    use super::{Light, LightEngine};
+
+    fn registry() -> BlockRegistry {
+        let stone: BlockDescriptor =
+            toml::from_str("name = \"Stone\"\n[textures]\nall = \"stone\"").unwrap();
+        let lamp: BlockDescriptor =
+            toml::from_str("name = \"Lamp\"\nlight_emission = 15\n[textures]\nall = \"lamp\"")
+                .unwrap();
+        let slab: BlockDescriptor = toml::from_str(
+            "name = \"Slab\"\n[model]\ntype = \"boxes\"\n[[model.boxes]]\nfrom = [0, 0, 0]\nto = [16, 8, 16]",
+        )
+        .unwrap();
+        BlockRegistry::from_descriptors(vec![stone, lamp, slab]).unwrap()
+    }
+
+    /// Two chunks side by side along X, filled with stone up to y = 10.
+    fn terrain(registry: &BlockRegistry) -> TerrainMap {
+        let stone = registry.id("stone").unwrap();
+        let mut terrain = TerrainMap::default();
+        for pos in [Vec2::new(0, 0), Vec2::new(1, 0)] {
+            let mut chunk = Chunk::flat(Block::AIR);
+            for local in chunk.iter().filter(|p| p.y <= 10).collect::<Vec<_>>() {
+                chunk.set(local, stone);
+            }
+            chunk.take_dirty();
+            terrain.chunks.insert(pos, chunk);
+        }
+        terrain
+    }
+
+    fn light_all(terrain: &mut TerrainMap, registry: &BlockRegistry) {
+        let mut engine = LightEngine::new(terrain, registry);
+        engine.light_chunk(Vec2::new(0, 0));
+        engine.light_chunk(Vec2::new(1, 0));
+    }
+
+    #[test]
+    pub fn sky_light_falls_and_spreads_under_overhangs() {
+        let registry = registry();
+        let stone = registry.id("stone").unwrap();
+        let mut terrain = terrain(&registry);
+        // A roof over (5, 11..13, 5)
+        terrain.set_block(Vec3::new(5, 14, 5), stone);
+        light_all(&mut terrain, &registry);
+
+        assert_eq!(terrain.get_light(Vec3::new(3, 11, 3)).unwrap().sky(), 15);
+        assert_eq!(terrain.get_light(Vec3::new(3, 10, 3)).unwrap().sky(), 0);
+        assert_eq!(terrain.get_light(Vec3::new(5, 15, 5)).unwrap().sky(), 15);
+        // Right below the roof the light comes from the side
+        assert_eq!(terrain.get_light(Vec3::new(5, 13, 5)).unwrap().sky(), 14);
+        assert_eq!(terrain.get_light(Vec3::new(5, 11, 5)).unwrap().sky(), 14);
+    }
+
+    #[test]
+    pub fn slabs_stop_the_light_below_them() {
+        let registry = registry();
+        let slab = registry.id("slab").unwrap();
+        let mut terrain = terrain(&registry);
+        terrain.set_block(Vec3::new(5, 14, 5), slab);
+        light_all(&mut terrain, &registry);
+
+        // The top half of the slab is open to the sky, its bottom is not
+        assert_eq!(terrain.get_light(Vec3::new(5, 14, 5)).unwrap().sky(), 15);
+        assert_eq!(terrain.get_light(Vec3::new(5, 13, 5)).unwrap().sky(), 14);
+    }
+
+    #[test]
+    pub fn block_light_crosses_chunk_borders() {
+        let registry = registry();
+        let lamp = registry.id("lamp").unwrap();
+        let mut terrain = terrain(&registry);
+        // Dig a tunnel at y = 5 along X, across the chunk border
+        for x in 10..24 {
+            terrain.set_block(Vec3::new(x, 5, 8), Block::AIR);
+        }
+        terrain.set_block(Vec3::new(12, 5, 8), lamp);
+        light_all(&mut terrain, &registry);
+
+        let light = |x| terrain.get_light(Vec3::new(x, 5, 8)).unwrap();
+        assert_eq!(light(12), Light::new(0, 15));
+        assert_eq!(light(15).block(), 12);
+        assert_eq!(light(16).block(), 11);
+        assert_eq!(light(23).block(), 4);
+        // The stone around the tunnel stays dark
+        assert_eq!(terrain.get_light(Vec3::new(16, 6, 8)), Some(Light::DARK));
+    }
+
+    #[test]
+    pub fn light_updates_when_blocks_change() {
+        let registry = registry();
+        let stone = registry.id("stone").unwrap();
+        let lamp = registry.id("lamp").unwrap();
+        let mut terrain = terrain(&registry);
+        light_all(&mut terrain, &registry);
+
+        // Open a shaft down to y = 6
+        for y in 6..=10 {
+            terrain.set_block(Vec3::new(20, y, 4), Block::AIR);
+            LightEngine::new(&mut terrain, &registry).update_block(Vec3::new(20, y, 4));
+        }
+        assert_eq!(terrain.get_light(Vec3::new(20, 6, 4)).unwrap().sky(), 15);
+
+        // Cover it again
+        terrain.set_block(Vec3::new(20, 10, 4), stone);
+        let mut engine = LightEngine::new(&mut terrain, &registry);
+        engine.update_block(Vec3::new(20, 10, 4));
+        let changed = engine.into_changed();
+        assert!(changed.contains(&Vec2::new(1, 0)));
+        assert_eq!(terrain.get_light(Vec3::new(20, 6, 4)), Some(Light::DARK));
+
+        // Place a lamp at the bottom and remove it
+        terrain.set_block(Vec3::new(20, 6, 4), lamp);
+        LightEngine::new(&mut terrain, &registry).update_block(Vec3::new(20, 6, 4));
+        assert_eq!(terrain.get_light(Vec3::new(20, 8, 4)).unwrap().block(), 13);
+        terrain.set_block(Vec3::new(20, 6, 4), Block::AIR);
+        LightEngine::new(&mut terrain, &registry).update_block(Vec3::new(20, 6, 4));
+        assert_eq!(terrain.get_light(Vec3::new(20, 8, 4)), Some(Light::DARK));
+    }
+}
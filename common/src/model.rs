@@ -0,0 +1,440 @@
+use serde::{Deserialize, Serialize};
+use vek::{Aabb, Vec2, Vec3};
+
+use crate::dir::Direction;
+
+/// The size of a block in model units. Model coordinates go from 0 to 16 on every axis,
+/// and texture coordinates from 0 to 16 across a texture.
+pub const MODEL_SIZE: u8 = 16;
+
+/// The shape of a block, used both to draw it and to collide with it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BlockModel {
+    /// A full block.
+    #[default]
+    Cube,
+    /// A set of axis aligned boxes, like slabs or stairs.
+    Boxes { boxes: Vec<ModelBox> },
+    /// Two quads crossing diagonally, like plants. It has no collision.
+    Cross,
+}
+
+/// A box of a model, in model units.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelBox {
+    pub from: [u8; 3],
+    pub to: [u8; 3],
+    #[serde(default)]
+    pub faces: ModelFaces,
+}
+
+/// Overrides for the faces of a [`ModelBox`]. Every face of a box is drawn.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelFaces {
+    pub north: Option<ModelFace>,
+    pub south: Option<ModelFace>,
+    pub east: Option<ModelFace>,
+    pub west: Option<ModelFace>,
+    pub up: Option<ModelFace>,
+    pub down: Option<ModelFace>,
+}
+
+impl ModelFaces {
+    pub fn get(&self, face: Direction) -> Option<&ModelFace> {
+        match face {
+            Direction::North => self.north.as_ref(),
+            Direction::South => self.south.as_ref(),
+            Direction::East => self.east.as_ref(),
+            Direction::West => self.west.as_ref(),
+            Direction::Up => self.up.as_ref(),
+            Direction::Down => self.down.as_ref(),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &ModelFace> {
+        Direction::ALL
+            .into_iter()
+            .filter_map(move |face| self.get(face))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelFace {
+    /// Replaces the texture the block has on this face.
+    pub texture: Option<String>,
+    /// The part of the texture shown on this face, as `[u0, v0, u1, v1]`.
+    ///
+    /// By default it is the part matching the position of the face in the block,
+    /// so that a slab looks like the bottom half of a full block.
+    pub uv: Option<[u8; 4]>,
+}
+
+/// A quad of a block model, ready to be meshed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelQuad<'a> {
+    /// The direction the quad faces. Quads of crossed models face up.
+    pub face: Direction,
+    /// The corners in model units, in the same order as [`face_corners`].
+    pub corners: [Vec3<u8>; 4],
+    /// The texture coordinates of each corner.
+    pub uvs: [Vec2<u8>; 4],
+    /// Replaces the texture of the block.
+    pub texture: Option<&'a str>,
+    /// The side of the block the quad lies on, if any.
+    /// Such a quad is hidden when the neighbour on that side covers it.
+    pub cull: Option<Direction>,
+}
+
+impl ModelQuad<'_> {
+    /// The rectangle covered by the quad on its plane, as the min and max
+    /// along the two other axes.
+    pub fn rect(&self) -> (Vec2<u8>, Vec2<u8>) {
+        let (u, v) = plane_axes(self.face);
+        let min = self
+            .corners
+            .iter()
+            .fold(Vec2::broadcast(u8::MAX), |min, c| {
+                Vec2::new(min.x.min(c[u]), min.y.min(c[v]))
+            });
+        let max = self.corners.iter().fold(Vec2::<u8>::zero(), |max, c| {
+            Vec2::new(max.x.max(c[u]), max.y.max(c[v]))
+        });
+        (min, max)
+    }
+}
+
+/// The corners of a unit quad for each face, in the order expected by the index buffer.
+///
+/// Seen from the front, they are the bottom left, bottom right, top right and top left corners.
+pub const fn face_corners(face: Direction) -> [Vec3<u8>; 4] {
+    match face {
+        Direction::North => [
+            Vec3::new(1, 0, 1),
+            Vec3::new(0, 0, 1),
+            Vec3::new(0, 1, 1),
+            Vec3::new(1, 1, 1),
+        ],
+        Direction::South => [
+            Vec3::new(0, 0, 0),
+            Vec3::new(1, 0, 0),
+            Vec3::new(1, 1, 0),
+            Vec3::new(0, 1, 0),
+        ],
+        Direction::East => [
+            Vec3::new(1, 0, 0),
+            Vec3::new(1, 0, 1),
+            Vec3::new(1, 1, 1),
+            Vec3::new(1, 1, 0),
+        ],
+        Direction::West => [
+            Vec3::new(0, 0, 1),
+            Vec3::new(0, 0, 0),
+            Vec3::new(0, 1, 0),
+            Vec3::new(0, 1, 1),
+        ],
+        Direction::Up => [
+            Vec3::new(0, 1, 0),
+            Vec3::new(1, 1, 0),
+            Vec3::new(1, 1, 1),
+            Vec3::new(0, 1, 1),
+        ],
+        Direction::Down => [
+            Vec3::new(0, 0, 0),
+            Vec3::new(0, 0, 1),
+            Vec3::new(1, 0, 1),
+            Vec3::new(1, 0, 0),
+        ],
+    }
+}
+
+/// The axes spanning the plane of a face.
+fn plane_axes(face: Direction) -> (usize, usize) {
+    match face {
+        Direction::East | Direction::West => (2, 1),
+        Direction::Up | Direction::Down => (0, 2),
+        Direction::North | Direction::South => (0, 1),
+    }
+}
+
+/// The texture coordinates of a point on a face, so that textures are upright on the sides
+/// and continue across neighbouring blocks. Must match `calculate_tile_coordinates` in terrain.wgsl.
+fn default_uv(face: Direction, point: Vec3<u8>) -> Vec2<u8> {
+    let size = MODEL_SIZE;
+    match face {
+        Direction::North => Vec2::new(size - point.x, size - point.y),
+        Direction::South => Vec2::new(point.x, size - point.y),
+        Direction::East => Vec2::new(point.z, size - point.y),
+        Direction::West => Vec2::new(size - point.z, size - point.y),
+        Direction::Up => Vec2::new(point.x, size - point.z),
+        Direction::Down => Vec2::new(point.z, size - point.x),
+    }
+}
+
+/// Rotates a point of a model around the center of the block,
+/// by `x` quarter turns around the X axis then `y` around the Y axis.
+///
+/// Uses the same convention as [`Direction::rotate_x`] and [`Direction::rotate_y`].
+fn rotate(point: Vec3<u8>, x: i32, y: i32) -> Vec3<u8> {
+    let half = MODEL_SIZE as i32 / 2;
+    let mut v = point.map(|c| c as i32 - half);
+    for _ in 0..x.rem_euclid(4) {
+        v = Vec3::new(v.x, -v.z, v.y);
+    }
+    for _ in 0..y.rem_euclid(4) {
+        v = Vec3::new(v.z, v.y, -v.x);
+    }
+    v.map(|c| (c + half) as u8)
+}
+
+impl BlockModel {
+    /// Returns the quads of the model after rotating it by `x` quarter turns around
+    /// the X axis, then `y` around the Y axis.
+    pub fn quads(&self, x: i32, y: i32) -> Vec<ModelQuad<'_>> {
+        match self {
+            BlockModel::Cube => {
+                let cube = ModelBox {
+                    from: [0; 3],
+                    to: [MODEL_SIZE; 3],
+                    faces: ModelFaces::default(),
+                };
+                box_quads(&cube, x, y)
+                    .into_iter()
+                    .map(|quad| ModelQuad {
+                        texture: None,
+                        ..quad
+                    })
+                    .collect()
+            },
+            BlockModel::Boxes { boxes } => boxes.iter().flat_map(|b| box_quads(b, x, y)).collect(),
+            BlockModel::Cross => cross_quads(),
+        }
+    }
+
+    /// Returns the collision boxes of the model in block units, after rotating it
+    /// like [`BlockModel::quads`].
+    pub fn collision_boxes(&self, x: i32, y: i32) -> Vec<Aabb<f32>> {
+        let to_aabb = |from: [u8; 3], to: [u8; 3]| {
+            let a = rotate(Vec3::from(from), x, y).map(|c| c as f32);
+            let b = rotate(Vec3::from(to), x, y).map(|c| c as f32);
+            Aabb {
+                min: Vec3::partial_min(a, b) / MODEL_SIZE as f32,
+                max: Vec3::partial_max(a, b) / MODEL_SIZE as f32,
+            }
+        };
+        match self {
+            BlockModel::Cube => vec![to_aabb([0; 3], [MODEL_SIZE; 3])],
+            BlockModel::Boxes { boxes } => boxes.iter().map(|b| to_aabb(b.from, b.to)).collect(),
+            BlockModel::Cross => Vec::new(),
+        }
+    }
+
+    /// Whether the model, rotated like [`BlockModel::quads`], fills the whole side of
+    /// the block facing `face`.
+    pub fn covers_face(&self, x: i32, y: i32, face: Direction) -> bool {
+        match self {
+            BlockModel::Cube => true,
+            BlockModel::Boxes { .. } => self.quads(x, y).iter().any(|quad| {
+                quad.cull == Some(face)
+                    && quad.rect() == (Vec2::zero(), Vec2::broadcast(MODEL_SIZE))
+            }),
+            BlockModel::Cross => false,
+        }
+    }
+
+    /// Checks that every box is inside the block and not empty, and that every
+    /// texture rectangle is inside its texture.
+    pub fn validate(&self) -> Result<(), String> {
+        let BlockModel::Boxes { boxes } = self else {
+            return Ok(());
+        };
+        if boxes.is_empty() {
+            return Err("the model has no boxes".to_owned());
+        }
+        for (index, b) in boxes.iter().enumerate() {
+            let inside = (0..3).all(|axis| b.from[axis] < b.to[axis] && b.to[axis] <= MODEL_SIZE);
+            if !inside {
+                return Err(format!(
+                    "box {} from {:?} to {:?} is empty or outside of the block",
+                    index, b.from, b.to
+                ));
+            }
+            let uv_inside = b
+                .faces
+                .iter()
+                .filter_map(|face| face.uv)
+                .all(|uv| uv.iter().all(|c| *c <= MODEL_SIZE));
+            if !uv_inside {
+                return Err(format!("box {} has texture coordinates above 16", index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Iterates over the textures given to the faces of the model.
+    pub fn texture_names(&self) -> impl Iterator<Item = &String> {
+        let boxes = match self {
+            BlockModel::Boxes { boxes } => boxes.as_slice(),
+            _ => &[],
+        };
+        boxes
+            .iter()
+            .flat_map(|b| b.faces.iter())
+            .filter_map(|face| face.texture.as_ref())
+    }
+}
+
+fn box_quads(model_box: &ModelBox, x: i32, y: i32) -> Vec<ModelQuad<'_>> {
+    let from = Vec3::from(model_box.from);
+    let to = Vec3::from(model_box.to);
+    Direction::ALL
+        .into_iter()
+        .map(|face| {
+            let corners = face_corners(face).map(|unit| {
+                let point = from + unit * (to - from);
+                rotate(point, x, y)
+            });
+            let rotated_face = face.rotate_x(x).rotate_y(y);
+            let overrides = model_box.faces.get(face);
+            let uvs = match overrides.and_then(|face| face.uv) {
+                Some([u0, v0, u1, v1]) => [
+                    Vec2::new(u0, v1),
+                    Vec2::new(u1, v1),
+                    Vec2::new(u1, v0),
+                    Vec2::new(u0, v0),
+                ],
+                None => corners.map(|corner| default_uv(rotated_face, corner)),
+            };
+            // A face lies on the side of the block when it touches the border it faces
+            let normal = rotated_face.vec();
+            let on_border = corners.iter().all(|corner| {
+                (0..3).all(|axis| match normal[axis] {
+                    1 => corner[axis] == MODEL_SIZE,
+                    -1 => corner[axis] == 0,
+                    _ => true,
+                })
+            });
+            ModelQuad {
+                face: rotated_face,
+                corners,
+                uvs,
+                texture: overrides.and_then(|face| face.texture.as_deref()),
+                cull: on_border.then_some(rotated_face),
+            }
+        })
+        .collect()
+}
+
+/// Two vertical quads along the diagonals of the block, each drawn from both sides.
+fn cross_quads() -> Vec<ModelQuad<'static>> {
+    let s = MODEL_SIZE;
+    let diagonals = [
+        (Vec3::new(0, 0, 0), Vec3::new(s, 0, s)),
+        (Vec3::new(0, 0, s), Vec3::new(s, 0, 0)),
+    ];
+    let uvs = [
+        Vec2::new(0, s),
+        Vec2::new(s, s),
+        Vec2::new(s, 0),
+        Vec2::new(0, 0),
+    ];
+    let mut quads = Vec::with_capacity(4);
+    for (start, end) in diagonals {
+        let up = Vec3::new(0, s, 0);
+        let front = [start, end, end + up, start + up];
+        let back = [end, start, start + up, end + up];
+        for corners in [front, back] {
+            quads.push(ModelQuad {
+                face: Direction::Up,
+                corners,
+                uvs,
+                texture: None,
+                cull: None,
+            });
+        }
+    }
+    quads
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::{Aabb, Vec2, Vec3};
+
+    use crate::dir::Direction;
+
+    use super::{BlockModel, ModelBox, ModelFaces};
+
+    fn slab() -> BlockModel {
+        BlockModel::Boxes {
+            boxes: vec![ModelBox {
+                from: [0, 0, 0],
+                to: [16, 8, 16],
+                faces: ModelFaces::default(),
+            }],
+        }
+    }
+
+    #[test]
+    pub fn slab_quads_cull_only_on_the_block_sides() {
+        let model = slab();
+        let quads = model.quads(0, 0);
+        assert_eq!(quads.len(), 6);
+
+        let top = quads.iter().find(|q| q.face == Direction::Up).unwrap();
+        assert_eq!(top.cull, None);
+        assert!(top.corners.iter().all(|c| c.y == 8));
+
+        let bottom = quads.iter().find(|q| q.face == Direction::Down).unwrap();
+        assert_eq!(bottom.cull, Some(Direction::Down));
+        assert_eq!(bottom.rect(), (Vec2::new(0, 0), Vec2::new(16, 16)));
+
+        // The sides show the bottom half of the texture
+        let south = quads.iter().find(|q| q.face == Direction::South).unwrap();
+        assert_eq!(south.cull, Some(Direction::South));
+        assert_eq!(south.rect(), (Vec2::new(0, 0), Vec2::new(16, 8)));
+        assert_eq!(south.uvs[0], Vec2::new(0, 16));
+        assert_eq!(south.uvs[2], Vec2::new(16, 8));
+    }
+
+    #[test]
+    pub fn rotated_models_rotate_their_collision() {
+        let model = slab();
+        assert_eq!(
+            model.collision_boxes(0, 0),
+            vec![Aabb {
+                min: Vec3::zero(),
+                max: Vec3::new(1.0, 0.5, 1.0),
+            }]
+        );
+        // Upside down
+        assert_eq!(
+            model.collision_boxes(2, 0),
+            vec![Aabb {
+                min: Vec3::new(0.0, 0.5, 0.0),
+                max: Vec3::one(),
+            }]
+        );
+        // A quarter turn around X turns the bottom of the slab to the south
+        let quads = model.quads(1, 0);
+        let south = quads.iter().find(|q| q.face == Direction::South).unwrap();
+        assert_eq!(south.cull, Some(Direction::South));
+        let north = quads.iter().find(|q| q.face == Direction::North).unwrap();
+        assert_eq!(north.cull, None);
+        assert!(BlockModel::Cross.collision_boxes(0, 0).is_empty());
+    }
+
+    #[test]
+    pub fn invalid_boxes_are_rejected() {
+        let model: BlockModel = toml::from_str(
+            r#"
+            type = "boxes"
+            [[boxes]]
+            from = [0, 0, 0]
+            to = [16, 20, 16]
+            "#,
+        )
+        .unwrap();
+        assert!(model.validate().is_err());
+        assert!(slab().validate().is_ok());
+    }
+}
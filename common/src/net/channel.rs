@@ -0,0 +1,817 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::NetworkError;
+
+/// How a message is delivered to the remote host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Delivery {
+    /// Sent once, it may be lost, duplicated or arrive out of order.
+    Unreliable,
+    /// Resent until it is acknowledged, and delivered exactly once in any order.
+    ReliableUnordered,
+    /// Resent until it is acknowledged, and delivered exactly once after every
+    /// ordered message sent before it.
+    ReliableOrdered,
+}
+
+/// The largest payload sent in a single datagram, larger ones are split into fragments.
+pub const FRAGMENT_SIZE: usize = 1024;
+/// The largest datagram sent, a fragment with its header and acknowledgements.
+/// It stays under the usual MTU, so datagrams are not fragmented by IP.
+pub const MAX_DATAGRAM_SIZE: usize = 1400;
+/// The largest payload that can be sent, in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+/// How long a partly received unreliable message is kept without receiving any of its
+/// missing fragments. Reliable ones are kept until they are complete, as their fragments
+/// are already acknowledged.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// The most bytes reserved for partly received messages. Every message reserves room
+/// for all of its fragments when its first one arrives.
+const MAX_REASSEMBLY_SIZE: usize = 4 * MAX_MESSAGE_SIZE;
+
+/// The round trip time assumed before the first acknowledgement.
+const INITIAL_RTT: Duration = Duration::from_millis(100);
+const MIN_RESEND_TIMEOUT: Duration = Duration::from_millis(20);
+const MAX_RESEND_TIMEOUT: Duration = Duration::from_secs(1);
+/// The most messages resent by a single [`Channel::update`], the others wait for the next one.
+const MAX_RESENDS_PER_UPDATE: usize = 64;
+/// The most acknowledgements carried by a single datagram.
+const MAX_ACKS: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Header {
+    Unreliable,
+    Reliable { sequence: u64 },
+    Ordered { sequence: u64, order: u64 },
+}
+
+/// Where a fragment goes in its message.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Fragment {
+    /// The same for every fragment of a message.
+    id: u64,
+    index: u16,
+    count: u16,
+}
+
+impl Fragment {
+    fn is_valid(&self) -> bool {
+        self.index < self.count && self.count as usize <= MAX_MESSAGE_SIZE.div_ceil(FRAGMENT_SIZE)
+    }
+}
+
+/// A message, or a fragment of one. Every fragment of a reliable message has its own
+/// sequence, so only the lost ones are resent, and they all share the order of the message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Message {
+    header: Header,
+    fragment: Option<Fragment>,
+    payload: Vec<u8>,
+}
+
+/// The fragments received of a message.
+struct Reassembly {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    last_fragment: Instant,
+    reliable: bool,
+}
+
+impl Reassembly {
+    /// The bytes reserved for the message, enough for all of its fragments.
+    fn reserved(&self) -> usize {
+        self.fragments.len() * FRAGMENT_SIZE
+    }
+}
+
+/// What is sent in a single datagram: the sequences of the reliable messages received
+/// since the last datagram, and maybe a message.
+#[derive(Debug, Serialize, Deserialize)]
+struct Frame {
+    acks: Vec<u64>,
+    message: Option<Message>,
+}
+
+struct Unacked {
+    message: Message,
+    sent_at: Instant,
+    /// How many times the message was resent. The resend timeout doubles every time.
+    resends: u32,
+}
+
+/// The reliability state of the messages exchanged with a single remote host.
+///
+/// It doesn't do any IO: payloads are turned into datagrams by [`Channel::send`], and
+/// received datagrams are turned back into payloads by [`Channel::receive`]. Reliable
+/// messages get a sequence number that the remote host acknowledges, and are resent
+/// by [`Channel::update`] until it does.
+pub struct Channel {
+    next_sequence: u64,
+    next_order: u64,
+    unacked: BTreeMap<u64, Unacked>,
+    /// Smoothed round trip time, measured from the messages acknowledged on the first try.
+    rtt: Duration,
+
+    /// Every reliable message below this sequence has been received.
+    received_below: u64,
+    /// The reliable messages received at or above `received_below`.
+    received: BTreeSet<u64>,
+    /// The sequences to acknowledge in the next datagrams.
+    pending_acks: VecDeque<u64>,
+    /// The order of the next ordered message to deliver.
+    next_delivered_order: u64,
+    /// Ordered messages received before the ones they have to wait for.
+    waiting: BTreeMap<u64, Vec<u8>>,
+    next_fragmented: u64,
+    /// The messages whose fragments are being received, by fragment id.
+    reassembly: HashMap<u64, Reassembly>,
+    /// The bytes reserved by the messages in `reassembly`.
+    reassembly_size: usize,
+    /// The last time a valid datagram was received, or the first message was sent
+    /// if nothing was received yet.
+    last_activity: Option<Instant>,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Channel {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            next_order: 0,
+            unacked: BTreeMap::new(),
+            rtt: INITIAL_RTT,
+            received_below: 0,
+            received: BTreeSet::new(),
+            pending_acks: VecDeque::new(),
+            next_delivered_order: 0,
+            waiting: BTreeMap::new(),
+            next_fragmented: 0,
+            reassembly: HashMap::new(),
+            reassembly_size: 0,
+            last_activity: None,
+        }
+    }
+
+    /// Makes the datagrams of a new message, more than one if it has to be fragmented.
+    pub fn send(
+        &mut self,
+        payload: Vec<u8>,
+        delivery: Delivery,
+        now: Instant,
+    ) -> Result<Vec<Vec<u8>>, NetworkError> {
+        if payload.len() > MAX_MESSAGE_SIZE {
+            return Err(NetworkError::MessageTooLarge(payload.len()));
+        }
+        self.last_activity.get_or_insert(now);
+        let order = (delivery == Delivery::ReliableOrdered).then(|| {
+            self.next_order += 1;
+            self.next_order - 1
+        });
+        let parts = if payload.len() <= FRAGMENT_SIZE {
+            vec![(None, payload)]
+        } else {
+            let id = self.next_fragmented;
+            self.next_fragmented += 1;
+            let count = payload.len().div_ceil(FRAGMENT_SIZE) as u16;
+            payload
+                .chunks(FRAGMENT_SIZE)
+                .enumerate()
+                .map(|(index, part)| {
+                    let index = index as u16;
+                    (Some(Fragment { id, index, count }), part.to_vec())
+                })
+                .collect()
+        };
+
+        let mut datagrams = Vec::with_capacity(parts.len());
+        for (fragment, payload) in parts {
+            let header = match (delivery, order) {
+                (Delivery::Unreliable, _) => Header::Unreliable,
+                (_, Some(order)) => Header::Ordered {
+                    sequence: self.next_sequence(),
+                    order,
+                },
+                (_, None) => Header::Reliable {
+                    sequence: self.next_sequence(),
+                },
+            };
+            let message = Message {
+                header,
+                fragment,
+                payload,
+            };
+            if let Header::Reliable { sequence } | Header::Ordered { sequence, .. } = message.header
+            {
+                self.unacked.insert(
+                    sequence,
+                    Unacked {
+                        message: message.clone(),
+                        sent_at: now,
+                        resends: 0,
+                    },
+                );
+            }
+            datagrams.push(self.frame(Some(message)));
+        }
+        Ok(datagrams)
+    }
+
+    /// Reads a datagram and returns the payloads that can be delivered because of it,
+    /// in the order they have to be delivered.
+    pub fn receive(&mut self, datagram: &[u8], now: Instant) -> Result<Vec<Vec<u8>>, NetworkError> {
+        let frame: Frame =
+            bincode::deserialize(datagram).map_err(NetworkError::DeserializeError)?;
+        self.last_activity = Some(now);
+        for sequence in frame.acks {
+            self.acknowledged(sequence, now);
+        }
+
+        let Some(message) = frame.message else {
+            return Ok(Vec::new());
+        };
+        if message
+            .fragment
+            .is_some_and(|fragment| !fragment.is_valid() || message.payload.len() > FRAGMENT_SIZE)
+        {
+            return Err(NetworkError::InvalidFragment);
+        }
+        let (sequence, order) = match message.header {
+            Header::Unreliable => (None, None),
+            Header::Reliable { sequence } => (Some(sequence), None),
+            Header::Ordered { sequence, order } => (Some(sequence), Some(order)),
+        };
+        // Fragments are checked before their sequence is marked as received, a valid copy
+        // of an invalid one could not be received anymore. A fragment that doesn't fit
+        // is not acknowledged, so it is sent again later.
+        if let Some(fragment) = message.fragment {
+            let count = fragment.count as usize;
+            if self
+                .reassembly
+                .get(&fragment.id)
+                .is_some_and(|reassembly| reassembly.fragments.len() != count)
+            {
+                return Err(NetworkError::InvalidFragment);
+            }
+            if !sequence.is_some_and(|sequence| self.is_received(sequence)) {
+                self.reserve(fragment, sequence.is_some(), now)?;
+            }
+        }
+        if let Some(sequence) = sequence {
+            // Acknowledge duplicates too, the previous acknowledgement may have been lost
+            self.pending_acks.push_back(sequence);
+            if !self.mark_received(sequence) {
+                return Ok(Vec::new());
+            }
+        }
+        let payload = match message.fragment {
+            Some(fragment) => match self.reassemble(fragment, message.payload, now) {
+                Some(payload) => payload,
+                None => return Ok(Vec::new()),
+            },
+            None => message.payload,
+        };
+        let Some(order) = order else {
+            return Ok(vec![payload]);
+        };
+
+        self.waiting.insert(order, payload);
+        let mut delivered = Vec::new();
+        while let Some(payload) = self.waiting.remove(&self.next_delivered_order) {
+            delivered.push(payload);
+            self.next_delivered_order += 1;
+        }
+        Ok(delivered)
+    }
+
+    /// Returns the datagrams to send now: the reliable messages that were not acknowledged
+    /// in time, and the acknowledgements that could not be sent with a message.
+    ///
+    /// A message waits twice as long before every new resend, up to [`MAX_RESEND_TIMEOUT`],
+    /// so a host that went away isn't flooded.
+    ///
+    /// Also drops the unreliable messages that stopped receiving fragments.
+    pub fn update(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let reassembly_size = &mut self.reassembly_size;
+        self.reassembly.retain(|id, reassembly| {
+            let expired = !reassembly.reliable
+                && now.saturating_duration_since(reassembly.last_fragment) >= REASSEMBLY_TIMEOUT;
+            if expired {
+                log::debug!(
+                    "Dropping message {} with {} missing fragments",
+                    id,
+                    reassembly.missing
+                );
+                *reassembly_size -= reassembly.reserved();
+            }
+            !expired
+        });
+
+        let timeout = (self.rtt * 2).clamp(MIN_RESEND_TIMEOUT, MAX_RESEND_TIMEOUT);
+        let mut resends = Vec::new();
+        // The oldest messages first, the others are resent by the next updates
+        for unacked in self.unacked.values_mut() {
+            if resends.len() == MAX_RESENDS_PER_UPDATE {
+                break;
+            }
+            let backoff = timeout
+                .saturating_mul(1 << unacked.resends.min(16))
+                .min(MAX_RESEND_TIMEOUT);
+            if now.saturating_duration_since(unacked.sent_at) >= backoff {
+                unacked.sent_at = now;
+                unacked.resends += 1;
+                resends.push(unacked.message.clone());
+            }
+        }
+
+        let mut datagrams: Vec<_> = resends
+            .into_iter()
+            .map(|message| self.frame(Some(message)))
+            .collect();
+        while !self.pending_acks.is_empty() {
+            datagrams.push(self.frame(None));
+        }
+        datagrams
+    }
+
+    /// The reliable messages sent that were not acknowledged yet.
+    pub fn unacked(&self) -> usize {
+        self.unacked.len()
+    }
+
+    pub fn rtt(&self) -> Duration {
+        self.rtt
+    }
+
+    /// The last time a valid datagram was received, or the first message was sent if
+    /// nothing was received yet. `None` for a channel that was never used.
+    pub fn last_activity(&self) -> Option<Instant> {
+        self.last_activity
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        sequence
+    }
+
+    fn frame(&mut self, message: Option<Message>) -> Vec<u8> {
+        let count = self.pending_acks.len().min(MAX_ACKS);
+        let frame = Frame {
+            acks: self.pending_acks.drain(..count).collect(),
+            message,
+        };
+        bincode::serialize(&frame).expect("Failed to serialize frame")
+    }
+
+    fn acknowledged(&mut self, sequence: u64, now: Instant) {
+        let Some(unacked) = self.unacked.remove(&sequence) else {
+            return;
+        };
+        // The acknowledgement of a resent message may be for any of its copies
+        if unacked.resends == 0 {
+            let sample = now.saturating_duration_since(unacked.sent_at);
+            self.rtt = self.rtt.mul_f64(0.875) + sample.mul_f64(0.125);
+        }
+    }
+
+    /// Makes room for the message of a fragment if it is the first one received. The
+    /// unreliable messages are dropped if there is not enough room for it.
+    fn reserve(
+        &mut self,
+        fragment: Fragment,
+        reliable: bool,
+        now: Instant,
+    ) -> Result<(), NetworkError> {
+        if self.reassembly.contains_key(&fragment.id) {
+            return Ok(());
+        }
+        let size = fragment.count as usize * FRAGMENT_SIZE;
+        if self.reassembly_size + size > MAX_REASSEMBLY_SIZE {
+            let reassembly_size = &mut self.reassembly_size;
+            self.reassembly.retain(|id, reassembly| {
+                if reassembly.reliable {
+                    return true;
+                }
+                log::debug!("Dropping message {} to make room for a new one", id);
+                *reassembly_size -= reassembly.reserved();
+                false
+            });
+        }
+        if self.reassembly_size + size > MAX_REASSEMBLY_SIZE {
+            return Err(NetworkError::ReassemblyFull);
+        }
+        self.reassembly_size += size;
+        self.reassembly.insert(
+            fragment.id,
+            Reassembly {
+                fragments: vec![None; fragment.count as usize],
+                missing: fragment.count as usize,
+                last_fragment: now,
+                reliable,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stores a fragment, and returns the payload of its message once every fragment
+    /// has been received. Room for its message has to be reserved first, and the fragment
+    /// has to belong to it.
+    fn reassemble(
+        &mut self,
+        fragment: Fragment,
+        payload: Vec<u8>,
+        now: Instant,
+    ) -> Option<Vec<u8>> {
+        let reassembly = self
+            .reassembly
+            .get_mut(&fragment.id)
+            .expect("No room reserved for the fragment");
+        reassembly.last_fragment = now;
+        let slot = &mut reassembly.fragments[fragment.index as usize];
+        if slot.is_none() {
+            reassembly.missing -= 1;
+        }
+        *slot = Some(payload);
+        if reassembly.missing > 0 {
+            return None;
+        }
+
+        let reassembly = self.reassembly.remove(&fragment.id).unwrap();
+        self.reassembly_size -= reassembly.reserved();
+        Some(
+            reassembly
+                .fragments
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect(),
+        )
+    }
+
+    fn is_received(&self, sequence: u64) -> bool {
+        sequence < self.received_below || self.received.contains(&sequence)
+    }
+
+    /// Records a reliable message, and returns whether it is the first time it is received.
+    fn mark_received(&mut self, sequence: u64) -> bool {
+        if self.is_received(sequence) {
+            return false;
+        }
+        self.received.insert(sequence);
+        while self.received.remove(&self.received_below) {
+            self.received_below += 1;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    /// A network that loses, duplicates and delays datagrams.
+    struct Link {
+        rng: StdRng,
+        loss: f64,
+        duplication: f64,
+        max_delay: u32,
+        /// The datagrams in flight, with the step they arrive at and whether they go to `b`.
+        in_flight: Vec<(u32, bool, Vec<u8>)>,
+    }
+
+    impl Link {
+        fn new(seed: u64, loss: f64) -> Self {
+            Self {
+                rng: StdRng::seed_from_u64(seed),
+                loss,
+                duplication: 0.05,
+                max_delay: 8,
+                in_flight: Vec::new(),
+            }
+        }
+
+        fn transmit(&mut self, step: u32, to_b: bool, datagram: Vec<u8>) {
+            assert!(datagram.len() <= MAX_DATAGRAM_SIZE);
+            let copies = if self.rng.gen_bool(self.duplication) {
+                2
+            } else {
+                1
+            };
+            for _ in 0..copies {
+                if !self.rng.gen_bool(self.loss) {
+                    let arrival = step + self.rng.gen_range(1..=self.max_delay);
+                    self.in_flight.push((arrival, to_b, datagram.clone()));
+                }
+            }
+        }
+
+        /// Runs both ends for a number of 10ms steps and returns what `b` received.
+        fn run(
+            &mut self,
+            a: &mut Channel,
+            b: &mut Channel,
+            steps: u32,
+            mut send: impl FnMut(u32, &mut Channel, Instant) -> Vec<Vec<u8>>,
+        ) -> Vec<Vec<u8>> {
+            let start = Instant::now();
+            let mut received = Vec::new();
+            for step in 0..steps {
+                let now = start + Duration::from_millis(step as u64 * 10);
+                for datagram in send(step, a, now) {
+                    self.transmit(step, true, datagram);
+                }
+
+                let (arrived, in_flight) = std::mem::take(&mut self.in_flight)
+                    .into_iter()
+                    .partition(|(arrival, _, _)| *arrival <= step);
+                self.in_flight = in_flight;
+                for (_, to_b, datagram) in arrived {
+                    if to_b {
+                        received.extend(b.receive(&datagram, now).unwrap());
+                    } else {
+                        assert!(a.receive(&datagram, now).unwrap().is_empty());
+                    }
+                }
+
+                for datagram in a.update(now) {
+                    self.transmit(step, true, datagram);
+                }
+                for datagram in b.update(now) {
+                    self.transmit(step, false, datagram);
+                }
+            }
+            received
+        }
+    }
+
+    fn send_numbers(
+        count: u32,
+        delivery: Delivery,
+    ) -> impl FnMut(u32, &mut Channel, Instant) -> Vec<Vec<u8>> {
+        move |step, channel, now| {
+            if step >= count {
+                return Vec::new();
+            }
+            let payload = step.to_le_bytes().to_vec();
+            channel.send(payload, delivery, now).unwrap()
+        }
+    }
+
+    /// A payload of `len` bytes that is different for every `seed`.
+    fn large_payload(seed: u32, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i as u32 * 31 + seed) as u8).collect()
+    }
+
+    fn numbers(payloads: Vec<Vec<u8>>) -> Vec<u32> {
+        payloads
+            .into_iter()
+            .map(|payload| u32::from_le_bytes(payload.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    pub fn ordered_messages_arrive_in_order_over_a_lossy_link() {
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let mut link = Link::new(1, 0.3);
+        let received = link.run(
+            &mut a,
+            &mut b,
+            1000,
+            send_numbers(200, Delivery::ReliableOrdered),
+        );
+
+        assert_eq!(numbers(received), (0..200).collect::<Vec<_>>());
+        assert_eq!(a.unacked(), 0);
+    }
+
+    #[test]
+    pub fn unordered_messages_arrive_exactly_once() {
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let mut link = Link::new(2, 0.3);
+        let received = numbers(link.run(
+            &mut a,
+            &mut b,
+            1000,
+            send_numbers(200, Delivery::ReliableUnordered),
+        ));
+
+        assert_eq!(received.len(), 200);
+        assert_eq!(received.iter().collect::<HashSet<_>>().len(), 200);
+        // Without ordering, messages are delivered as soon as they arrive
+        assert!(received.windows(2).any(|pair| pair[0] > pair[1]));
+        assert_eq!(a.unacked(), 0);
+    }
+
+    #[test]
+    pub fn unreliable_messages_are_not_resent() {
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let mut link = Link::new(3, 0.3);
+        link.duplication = 0.0;
+        let received = link.run(
+            &mut a,
+            &mut b,
+            1000,
+            send_numbers(200, Delivery::Unreliable),
+        );
+
+        assert!(received.len() < 200);
+        assert_eq!(a.unacked(), 0);
+    }
+
+    #[test]
+    pub fn ordered_messages_wait_for_the_lost_ones() {
+        let now = Instant::now();
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let mut send = |payload, delivery| a.send(payload, delivery, now).unwrap().remove(0);
+        let first = send(vec![0], Delivery::ReliableOrdered);
+        let second = send(vec![1], Delivery::ReliableOrdered);
+        let unordered = send(vec![2], Delivery::ReliableUnordered);
+
+        assert!(b.receive(&second, now).unwrap().is_empty());
+        assert_eq!(b.receive(&unordered, now).unwrap(), vec![vec![2]]);
+        assert_eq!(b.receive(&first, now).unwrap(), vec![vec![0], vec![1]]);
+        assert!(b.receive(&first, now).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn large_messages_are_fragmented_over_a_lossy_link() {
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let mut link = Link::new(4, 0.2);
+        // Small and large messages, the largest one being split in 20 fragments
+        let sizes = |step: u32| [10, FRAGMENT_SIZE, 5000, 20 * FRAGMENT_SIZE][step as usize % 4];
+        let received = link.run(&mut a, &mut b, 1000, |step, channel, now| {
+            if step >= 40 {
+                return Vec::new();
+            }
+            let payload = large_payload(step, sizes(step));
+            channel
+                .send(payload, Delivery::ReliableOrdered, now)
+                .unwrap()
+        });
+
+        let expected: Vec<_> = (0..40)
+            .map(|step| large_payload(step, sizes(step)))
+            .collect();
+        assert_eq!(received, expected);
+        assert!(b.reassembly.is_empty());
+    }
+
+    #[test]
+    pub fn incomplete_unreliable_messages_time_out() {
+        let now = Instant::now();
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let payload = large_payload(0, 3 * FRAGMENT_SIZE);
+        let fragments = a.send(payload, Delivery::Unreliable, now).unwrap();
+        assert_eq!(fragments.len(), 3);
+
+        for fragment in &fragments[..2] {
+            assert!(b.receive(fragment, now).unwrap().is_empty());
+        }
+        b.update(now + REASSEMBLY_TIMEOUT / 2);
+        assert_eq!(b.reassembly.len(), 1);
+        b.update(now + REASSEMBLY_TIMEOUT);
+        assert!(b.reassembly.is_empty());
+        // The last fragment alone is not enough anymore
+        assert!(b.receive(&fragments[2], now).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn reliable_messages_wait_for_their_lost_fragments() {
+        let now = Instant::now();
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let payload = large_payload(0, 3 * FRAGMENT_SIZE);
+        let fragments = a
+            .send(payload.clone(), Delivery::ReliableOrdered, now)
+            .unwrap();
+        let next = a.send(vec![1], Delivery::ReliableOrdered, now).unwrap();
+
+        // The second fragment is lost
+        for datagram in [&fragments[0], &fragments[2], &next[0]] {
+            assert!(b.receive(datagram, now).unwrap().is_empty());
+        }
+        let later = now + REASSEMBLY_TIMEOUT * 2;
+        for datagram in b.update(later) {
+            assert!(a.receive(&datagram, later).unwrap().is_empty());
+        }
+        assert_eq!(b.reassembly.len(), 1);
+
+        let resent = a.update(later);
+        assert_eq!(resent.len(), 1);
+        assert_eq!(
+            b.receive(&resent[0], later).unwrap(),
+            vec![payload, vec![1]]
+        );
+        assert_eq!(b.reassembly_size, 0);
+    }
+
+    #[test]
+    pub fn invalid_fragments_do_not_stall_their_message() {
+        let now = Instant::now();
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let payload = large_payload(0, 3 * FRAGMENT_SIZE);
+        let fragments = a
+            .send(payload.clone(), Delivery::ReliableOrdered, now)
+            .unwrap();
+        assert!(b.receive(&fragments[0], now).unwrap().is_empty());
+
+        // The second fragment, claiming a different number of fragments
+        let forged = Frame {
+            acks: Vec::new(),
+            message: Some(Message {
+                header: Header::Ordered {
+                    sequence: 1,
+                    order: 0,
+                },
+                fragment: Some(Fragment {
+                    id: 0,
+                    index: 1,
+                    count: 2,
+                }),
+                payload: vec![0; FRAGMENT_SIZE],
+            }),
+        };
+        let forged = bincode::serialize(&forged).unwrap();
+        let result = b.receive(&forged, now);
+        assert!(matches!(result, Err(NetworkError::InvalidFragment)));
+
+        assert!(b.receive(&fragments[1], now).unwrap().is_empty());
+        assert_eq!(b.receive(&fragments[2], now).unwrap(), vec![payload]);
+    }
+
+    #[test]
+    pub fn reassembly_room_is_limited() {
+        let now = Instant::now();
+        let (mut a, mut b) = (Channel::new(), Channel::new());
+        let mut first_fragment =
+            |len, delivery| a.send(vec![0; len], delivery, now).unwrap()[0].clone();
+
+        let unreliable = first_fragment(3 * FRAGMENT_SIZE, Delivery::Unreliable);
+        assert!(b.receive(&unreliable, now).unwrap().is_empty());
+        // The last reliable message only fits without the unreliable one
+        for _ in 0..MAX_REASSEMBLY_SIZE / MAX_MESSAGE_SIZE {
+            let reliable = first_fragment(MAX_MESSAGE_SIZE, Delivery::ReliableOrdered);
+            assert!(b.receive(&reliable, now).unwrap().is_empty());
+        }
+        assert!(b.reassembly.values().all(|reassembly| reassembly.reliable));
+        assert_eq!(b.reassembly_size, MAX_REASSEMBLY_SIZE);
+
+        let pending_acks = b.pending_acks.len();
+        let refused = first_fragment(2 * FRAGMENT_SIZE, Delivery::ReliableUnordered);
+        let result = b.receive(&refused, now);
+        assert!(matches!(result, Err(NetworkError::ReassemblyFull)));
+        // It will be resent, so it is not acknowledged
+        assert_eq!(b.pending_acks.len(), pending_acks);
+    }
+
+    #[test]
+    pub fn resends_back_off_and_are_capped() {
+        let start = Instant::now();
+        let mut channel = Channel::new();
+        channel
+            .send(vec![0], Delivery::ReliableOrdered, start)
+            .unwrap();
+        // Nothing is ever acknowledged
+        let resends: usize = (1..=300)
+            .map(|step| {
+                let now = start + Duration::from_millis(step * 10);
+                channel.update(now).len()
+            })
+            .sum();
+        // After 200ms, then 400ms, 800ms and every second
+        assert_eq!(resends, 4);
+
+        for _ in 0..2 * MAX_RESENDS_PER_UPDATE {
+            channel
+                .send(vec![0], Delivery::ReliableUnordered, start)
+                .unwrap();
+        }
+        let later = start + Duration::from_secs(10);
+        assert_eq!(channel.update(later).len(), MAX_RESENDS_PER_UPDATE);
+        assert_eq!(channel.update(later).len(), MAX_RESENDS_PER_UPDATE);
+        assert_eq!(channel.update(later).len(), 1);
+    }
+
+    #[test]
+    pub fn oversized_messages_are_refused() {
+        let mut channel = Channel::new();
+        let payload = vec![0; MAX_MESSAGE_SIZE + 1];
+        let result = channel.send(payload, Delivery::ReliableOrdered, Instant::now());
+
+        assert!(matches!(result, Err(NetworkError::MessageTooLarge(_))));
+        assert_eq!(channel.unacked(), 0);
+    }
+}
@@ -1,21 +1,52 @@
-use serde::{de::DeserializeOwned, Serialize};
-use std::net::{SocketAddr, UdpSocket};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
 
-use super::{error::NetworkError, socket};
+use super::{
+    channel::{Channel, MAX_DATAGRAM_SIZE},
+    error::NetworkError,
+    packet::Packet,
+    socket,
+};
+
+/// How long the channel of a remote host is kept without receiving anything from it.
+/// It is longer than the game waits before dropping a silent host.
+const CHANNEL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The packets sent and received by a [`Connection`] since its counters were taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketCounters {
+    pub received: u32,
+    pub sent: u32,
+}
 
 /// Represents a connection that can either send or receive packets.
 ///
+/// Packets are delivered as their [`Packet::delivery`] asks, with a [`Channel`] for every
+/// remote host. Packets too large for a single datagram are split into fragments and
+/// put back together on the other end. [`Connection::update`] has to be called regularly
+/// to resend the reliable packets that were lost.
+///
 /// Generic Types:
 /// - `S` stands for Send, i.e the packet type that is sent
 /// - `R` stands for Receive, i.e the packet type that is received
-pub struct Connection<S: Serialize, R: DeserializeOwned> {
+pub struct Connection<S: Packet, R: DeserializeOwned> {
     /// The internal socket that is used to send and receive packets
     pub(crate) socket: UdpSocket,
+    /// The remote host the socket is connected to, if any.
+    remote: Option<SocketAddr>,
+    channels: HashMap<SocketAddr, Channel>,
+    /// Payloads that were received but not returned by [`Connection::recv`] yet.
+    received: VecDeque<(Vec<u8>, SocketAddr)>,
+    counters: PacketCounters,
     /// A marker to let the compiler know that it should allow the generic types.
     _marker: std::marker::PhantomData<(S, R)>,
 }
 
-impl<S: Serialize, R: DeserializeOwned> Connection<S, R> {
+impl<S: Packet, R: DeserializeOwned> Connection<S, R> {
     /// Connect to a remote host.
     ///
     /// This will bind a UDP socket to a random port and connect it to the remote host.
@@ -24,10 +55,7 @@ impl<S: Serialize, R: DeserializeOwned> Connection<S, R> {
         socket
             .connect(remote_addr)
             .map_err(|_| NetworkError::ConnectionFailed)?;
-        Ok(Self {
-            socket,
-            _marker: std::marker::PhantomData,
-        })
+        Ok(Self::new(socket, Some(remote_addr)))
     }
 
     /// Listen for incoming connections on a local address.
@@ -36,38 +64,140 @@ impl<S: Serialize, R: DeserializeOwned> Connection<S, R> {
     /// and will be able to receive packets from any remote host.
     pub fn listen(local_addr: SocketAddr) -> Result<Self, NetworkError> {
         let socket = Self::bind(local_addr)?;
-        Ok(Self {
+        Ok(Self::new(socket, None))
+    }
+
+    fn new(socket: UdpSocket, remote: Option<SocketAddr>) -> Self {
+        Self {
             socket,
+            remote,
+            channels: HashMap::new(),
+            received: VecDeque::new(),
+            counters: PacketCounters::default(),
             _marker: std::marker::PhantomData,
-        })
+        }
     }
 
     /// Send a packet to the remote host that this connection was made to.
     ///
     /// Fails if the socket is not connected.
-    pub fn send(&self, packet: S) -> Result<(), NetworkError> {
-        let packet = Self::serialize(&packet);
-        self.socket
-            .send(&packet)
-            .map_err(|e| NetworkError::IOError(e.kind()))?;
-        Ok(())
+    pub fn send(&mut self, packet: S) -> Result<(), NetworkError> {
+        let addr = self.remote.ok_or(NetworkError::ConnectionFailed)?;
+        self.send_to(packet, addr)
     }
 
-    pub fn send_to(&self, packet: S, addr: SocketAddr) -> Result<(), NetworkError> {
-        let packet = Self::serialize(&packet);
-        self.socket
-            .send_to(&packet, addr)
-            .map_err(|e| NetworkError::IOError(e.kind()))?;
+    pub fn send_to(&mut self, packet: S, addr: SocketAddr) -> Result<(), NetworkError> {
+        let delivery = packet.delivery();
+        let payload = Self::serialize(&packet);
+        let datagrams =
+            self.channels
+                .entry(addr)
+                .or_default()
+                .send(payload, delivery, Instant::now())?;
+        self.counters.sent += 1;
+        for datagram in datagrams {
+            self.send_datagram(&datagram, addr)?;
+        }
         Ok(())
     }
 
     /// Receive a packet. This will not block, if there is no packet it will return an error.
-    pub fn recv(&self) -> Result<(R, SocketAddr), NetworkError> {
-        let mut buf = [0; 10000];
-        match self.socket.recv_from(&mut buf) {
-            Ok((len, addr)) => Self::deserialize(&buf[..len]).map(|p| (p, addr)),
-            Err(e) => Err(NetworkError::IOError(e.kind())),
+    pub fn recv(&mut self) -> Result<(R, SocketAddr), NetworkError> {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        while self.received.is_empty() {
+            let (len, addr) = self
+                .socket
+                .recv_from(&mut buf)
+                .map_err(|e| NetworkError::IOError(e.kind()))?;
+            let now = Instant::now();
+            let result = match self.channels.get_mut(&addr) {
+                Some(channel) => channel.receive(&buf[..len], now),
+                // Only hosts sending valid frames get a channel
+                None => {
+                    let mut channel = Channel::new();
+                    let result = channel.receive(&buf[..len], now);
+                    if result.is_ok() {
+                        self.channels.insert(addr, channel);
+                    }
+                    result
+                },
+            };
+            match result {
+                Ok(payloads) => self
+                    .received
+                    .extend(payloads.into_iter().map(|payload| (payload, addr))),
+                Err(e) => log::warn!("Dropping invalid datagram from {}: {:?}", addr, e),
+            }
         }
+        let (payload, addr) = self.received.pop_front().unwrap();
+        self.counters.received += 1;
+        Self::deserialize(&payload).map(|p| (p, addr))
+    }
+
+    /// The local address the socket is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetworkError> {
+        self.socket
+            .local_addr()
+            .map_err(|e| NetworkError::IOError(e.kind()))
+    }
+
+    /// Returns the packet counters and resets them.
+    pub fn take_counters(&mut self) -> PacketCounters {
+        std::mem::take(&mut self.counters)
+    }
+
+    /// Resends the reliable packets that were not acknowledged in time, and acknowledges
+    /// the ones received since the last packet sent to their host.
+    ///
+    /// The channels of hosts that stayed silent for too long are dropped, except the one
+    /// of the host the socket is connected to.
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        self.expire_channels(now);
+        let datagrams: Vec<_> = self
+            .channels
+            .iter_mut()
+            .flat_map(|(addr, channel)| channel.update(now).into_iter().map(|d| (*addr, d)))
+            .collect();
+        for (addr, datagram) in datagrams {
+            if let Err(e) = self.send_datagram(&datagram, addr) {
+                log::error!("Failed to send datagram to {}: {:?}", addr, e);
+            }
+        }
+    }
+
+    /// Drops the delivery state of a remote host, and the packets it still had to acknowledge.
+    pub fn forget(&mut self, addr: SocketAddr) {
+        self.channels.remove(&addr);
+    }
+
+    fn expire_channels(&mut self, now: Instant) {
+        let remote = self.remote;
+        self.channels.retain(|addr, channel| {
+            let idle = channel
+                .last_activity()
+                .is_some_and(|last| now.saturating_duration_since(last) >= CHANNEL_TIMEOUT);
+            if idle && remote != Some(*addr) {
+                log::debug!(
+                    "Forgetting {}, nothing was received from it for too long",
+                    addr
+                );
+                return false;
+            }
+            true
+        });
+    }
+
+    fn send_datagram(&self, datagram: &[u8], addr: SocketAddr) -> Result<(), NetworkError> {
+        // Some platforms refuse `send_to` on connected sockets
+        let result = if self.remote == Some(addr) {
+            self.socket.send(datagram)
+        } else {
+            self.socket.send_to(datagram, addr)
+        };
+        result
+            .map(|_| ())
+            .map_err(|e| NetworkError::IOError(e.kind()))
     }
 
     fn bind(addr: SocketAddr) -> Result<UdpSocket, NetworkError> {
@@ -80,7 +210,7 @@ impl<S: Serialize, R: DeserializeOwned> Connection<S, R> {
     }
 
     fn deserialize(packet: &[u8]) -> Result<R, NetworkError> {
-        let buf = lz4_compress::decompress(packet).expect("decompression failed");
+        let buf = lz4_compress::decompress(packet).map_err(|_| NetworkError::DecompressError)?;
         match bincode::deserialize::<R>(buf.as_slice()) {
             Ok(t) => Ok(t),
             Err(e) => Err(NetworkError::DeserializeError(e)),
@@ -90,6 +220,105 @@ impl<S: Serialize, R: DeserializeOwned> Connection<S, R> {
 
 #[cfg(test)]
 pub mod tests {
+    use std::time::{Duration, Instant};
+
+    use vek::Vec2;
+
+    use super::*;
+    use crate::net::{
+        channel::Delivery,
+        packet::{ClientPacket, PingPacket, ServerPacket},
+    };
+
+    pub fn create_client_server() -> (
+        Connection<ClientPacket, ServerPacket>,
+        Connection<ServerPacket, ClientPacket>,
+    ) {
+        let server = Connection::listen(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = server.socket.local_addr().unwrap();
+        (Connection::connect(addr).unwrap(), server)
+    }
+
+    #[test]
+    pub fn every_sent_packet_is_received_and_counted() {
+        let (mut client, mut server) = create_client_server();
+        for x in 0..100 {
+            client
+                .send(ClientPacket::ChunkRequest(Vec2::new(x, 0)))
+                .unwrap();
+        }
+        client.send(ClientPacket::Ping(PingPacket::Ping)).unwrap();
 
-    pub fn create_client_server() {}
+        let mut received = Vec::new();
+        let start = Instant::now();
+        while received.len() < 101 && start.elapsed() < Duration::from_secs(5) {
+            match server.recv() {
+                Ok((packet, _)) => received.push(packet),
+                Err(_) => {
+                    client.update();
+                    std::thread::sleep(Duration::from_millis(1));
+                },
+            }
+        }
+
+        assert_eq!(received.len(), 101);
+        // The ping is unreliable, so it may overtake the ordered requests
+        let requests: Vec<_> = received
+            .iter()
+            .filter_map(|packet| match packet {
+                ClientPacket::ChunkRequest(pos) => Some(pos.x),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(requests, (0..100).collect::<Vec<_>>());
+        let expected = |received, sent| PacketCounters { received, sent };
+        assert_eq!(client.take_counters(), expected(0, 101));
+        assert_eq!(server.take_counters(), expected(101, 0));
+        assert_eq!(server.take_counters(), expected(0, 0));
+    }
+
+    #[test]
+    pub fn channels_are_only_kept_for_active_hosts() {
+        let (mut client, mut server) = create_client_server();
+        let addr = server.socket.local_addr().unwrap();
+        let stranger = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        stranger.send_to(b"not a frame", addr).unwrap();
+        client.send(ClientPacket::Ping(PingPacket::Ping)).unwrap();
+
+        let start = Instant::now();
+        while server.recv().is_err() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // Whatever the order of the datagrams, the stranger never got a channel
+        while server.recv().is_ok() {}
+        assert_eq!(server.channels.len(), 1);
+
+        server.expire_channels(Instant::now() + CHANNEL_TIMEOUT);
+        assert!(server.channels.is_empty());
+        // The client keeps the channel of its server
+        client.expire_channels(Instant::now() + CHANNEL_TIMEOUT);
+        assert_eq!(client.channels.len(), 1);
+    }
+
+    #[test]
+    pub fn invalid_compressed_packets_are_errors() {
+        let (_, mut server) = create_client_server();
+        let addr = server.local_addr().unwrap();
+        let stranger = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let datagrams = Channel::new()
+            .send(vec![0xff; 16], Delivery::Unreliable, Instant::now())
+            .unwrap();
+        stranger.send_to(&datagrams[0], addr).unwrap();
+
+        let start = Instant::now();
+        let result = loop {
+            match server.recv() {
+                Err(NetworkError::IOError(_)) if start.elapsed() < Duration::from_secs(5) => {
+                    std::thread::sleep(Duration::from_millis(1))
+                },
+                result => break result,
+            }
+        };
+        assert!(matches!(result, Err(NetworkError::DecompressError)));
+    }
 }
@@ -3,5 +3,13 @@ pub enum NetworkError {
     ConnectionFailed,
     SocketBindError,
     DeserializeError(bincode::Error),
+    /// The packet is not valid LZ4 data.
+    DecompressError,
     IOError(std::io::ErrorKind),
+    /// The serialized packet is larger than [`MAX_MESSAGE_SIZE`](super::channel::MAX_MESSAGE_SIZE).
+    MessageTooLarge(usize),
+    /// A fragment doesn't fit in the message it belongs to.
+    InvalidFragment,
+    /// There is no room left for the fragments of a new message.
+    ReassemblyFull,
 }
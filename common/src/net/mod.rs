@@ -1,3 +1,4 @@
+pub mod channel;
 pub mod connection;
 pub mod error;
 pub mod packet;
@@ -1,28 +1,135 @@
 use serde::{Deserialize, Serialize};
-use vek::Vec2;
+use vek::{Vec2, Vec3};
 
-use crate::{block::BlockId, uid::Uid};
+use crate::{
+    block::{Block, BlockRegistry},
+    chunk::CompressedChunk,
+    uid::Uid,
+};
+
+use super::channel::Delivery;
+
+/// A packet that can be sent through a [`Connection`](super::connection::Connection).
+pub trait Packet: Serialize {
+    /// How the packet is delivered to the remote host.
+    fn delivery(&self) -> Delivery;
+}
+
+/// The version of the packets and of their encoding. It has to be bumped whenever they
+/// change, so clients and servers of different builds refuse each other.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// The version of the game.
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What a client tells the server about itself when connecting.
+///
+/// Only clients whose connect packet can still be decoded are refused with a reason,
+/// the packets of the others are dropped and they time out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    pub game_version: String,
+    pub player_name: String,
+    /// The [`BlockRegistry::hash`] of the client's blocks.
+    pub registry_hash: u64,
+}
+
+impl Handshake {
+    pub fn new(player_name: impl Into<String>, registry: &BlockRegistry) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            game_version: GAME_VERSION.to_owned(),
+            player_name: player_name.into(),
+            registry_hash: registry.hash(),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientPacket {
-    Connect,
+    /// Asks to join the game.
+    Connect(Handshake),
     Disconnect,
     Ping(PingPacket),
     ChunkRequest(Vec2<i32>),
+    /// The client no longer has this chunk loaded.
+    ChunkUnload(Vec2<i32>),
+    /// The position of the player's eyes, sent when it changes. Moves are numbered from 1,
+    /// so the server drops the ones arriving after a newer move.
+    Move {
+        sequence: u64,
+        pos: Vec3<f32>,
+    },
+    /// Breaks the first block hit by the look ray. The origin of the ray has to be close
+    /// to the last position the server received.
+    BreakBlock {
+        origin: Vec3<f32>,
+        dir: Vec3<f32>,
+    },
+    /// Places a block against the face of the first block hit by the look ray.
+    PlaceBlock {
+        origin: Vec3<f32>,
+        dir: Vec3<f32>,
+        block: Block,
+    },
+}
+
+impl Packet for ClientPacket {
+    fn delivery(&self) -> Delivery {
+        match self {
+            // The client doesn't wait for the disconnect to be acknowledged, and only the
+            // latest position matters
+            ClientPacket::Disconnect | ClientPacket::Ping(_) | ClientPacket::Move { .. } => {
+                Delivery::Unreliable
+            },
+            // Chunk requests and unloads of the same chunk must not overtake each other
+            ClientPacket::Connect(_)
+            | ClientPacket::ChunkRequest(_)
+            | ClientPacket::ChunkUnload(_)
+            | ClientPacket::BreakBlock { .. }
+            | ClientPacket::PlaceBlock { .. } => Delivery::ReliableOrdered,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ServerPacket {
     ClientSync {
         uid: Uid,
+        /// The names of the server's blocks, indexed by their id.
+        blocks: Vec<String>,
+    },
+    /// The server refused the client or closed its connection.
+    Disconnect {
+        reason: String,
     },
     Ping(PingPacket),
     ChunkUpdate {
         pos: Vec2<i32>,
-        data: Vec<(BlockId, u32)>,
+        data: CompressedChunk,
+    },
+    /// A block changed in a chunk the client has loaded.
+    BlockUpdate {
+        pos: Vec3<i32>,
+        block: Block,
     },
 }
 
+impl Packet for ServerPacket {
+    fn delivery(&self) -> Delivery {
+        match self {
+            // The server forgets the client right after disconnecting it, so nothing
+            // would be resent anyway
+            ServerPacket::Ping(_) | ServerPacket::Disconnect { .. } => Delivery::Unreliable,
+            // Block updates are only applied to chunks that were received before them
+            ServerPacket::ClientSync { .. }
+            | ServerPacket::ChunkUpdate { .. }
+            | ServerPacket::BlockUpdate { .. } => Delivery::ReliableOrdered,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum PingPacket {
     Ping,
@@ -0,0 +1,284 @@
+/// A fixed size container that stores its values as indices into a small local palette.
+///
+/// Every distinct value is stored once in the palette and each entry only keeps
+/// the index of its value, packed at the smallest bit width that can address the whole palette.
+/// A container holding a single distinct value needs no index data at all.
+///
+/// The bit width grows automatically when a new value is inserted and the palette
+/// no longer fits in the current width.
+#[derive(Debug, Clone)]
+pub struct PalettedStorage<T> {
+    /// The distinct values stored in this container.
+    palette: Vec<T>,
+    /// The number of bits used by each packed index.
+    bits: u32,
+    /// The packed palette indices. Indices never span two words.
+    data: Vec<u64>,
+    /// The number of entries in this container.
+    len: usize,
+}
+
+impl<T: Copy + PartialEq> PalettedStorage<T> {
+    /// Creates a new container of `len` entries, all of them set to `value`.
+    pub fn new(len: usize, value: T) -> Self {
+        Self {
+            palette: vec![value],
+            bits: 0,
+            data: Vec::new(),
+            len,
+        }
+    }
+
+    /// Creates a new container holding a copy of the given values.
+    ///
+    /// # Panics
+    /// Panics if `values` is empty.
+    pub fn from_slice(values: &[T]) -> Self {
+        assert!(!values.is_empty(), "Cannot create an empty storage");
+        let mut palette = Vec::new();
+        for value in values {
+            if !palette.contains(value) {
+                palette.push(*value);
+            }
+        }
+
+        let mut storage = Self {
+            palette,
+            bits: 0,
+            data: Vec::new(),
+            len: values.len(),
+        };
+        storage.bits = bits_for(storage.palette.len());
+        storage.data = vec![0; words_for(storage.len, storage.bits)];
+
+        if storage.bits > 0 {
+            for (i, value) in values.iter().enumerate() {
+                let index = storage.palette_index(*value).unwrap();
+                storage.write_index(i, index);
+            }
+        }
+        storage
+    }
+
+    /// Returns the value stored at `index`.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn get(&self, index: usize) -> T {
+        assert!(index < self.len, "Index out of bounds: {}", index);
+        self.palette[self.read_index(index)]
+    }
+
+    /// Stores `value` at `index` and returns the value that was there before.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn set(&mut self, index: usize, value: T) -> T {
+        assert!(index < self.len, "Index out of bounds: {}", index);
+        let old = self.get(index);
+        if old == value {
+            return old;
+        }
+
+        let palette_index = match self.palette_index(value) {
+            Some(i) => i,
+            None => {
+                self.palette.push(value);
+                let bits = bits_for(self.palette.len());
+                if bits != self.bits {
+                    self.resize(bits);
+                }
+                self.palette.len() - 1
+            },
+        };
+        self.write_index(index, palette_index);
+        old
+    }
+
+    /// Fills the whole container with `value`, dropping any index data.
+    pub fn fill(&mut self, value: T) {
+        self.palette.clear();
+        self.palette.push(value);
+        self.bits = 0;
+        self.data = Vec::new();
+    }
+
+    /// Removes the palette entries that are no longer referenced and
+    /// repacks the indices at the smallest bit width possible.
+    pub fn compact(&mut self) {
+        if self.palette.len() == 1 {
+            return;
+        }
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..self.len {
+            used[self.read_index(i)] = true;
+        }
+        if used.iter().all(|u| *u) {
+            return;
+        }
+        let values = self.iter().collect::<Vec<_>>();
+        *self = Self::from_slice(&values);
+    }
+
+    /// Returns the number of entries in this container.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the distinct values stored in this container.
+    ///
+    /// The palette may contain values that are no longer referenced until [`Self::compact`] is called.
+    pub fn palette(&self) -> &[T] {
+        &self.palette
+    }
+
+    /// Returns the number of bits used by each packed index.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// If every entry holds the same value, returns that value.
+    pub fn uniform(&self) -> Option<T> {
+        match self.palette.len() {
+            1 => Some(self.palette[0]),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over every value in index order.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    /// Returns the approximate number of heap bytes used by this container.
+    pub fn heap_size(&self) -> usize {
+        self.palette.capacity() * std::mem::size_of::<T>()
+            + self.data.capacity() * std::mem::size_of::<u64>()
+    }
+
+    fn palette_index(&self, value: T) -> Option<usize> {
+        self.palette.iter().position(|v| *v == value)
+    }
+
+    fn resize(&mut self, bits: u32) {
+        let mut resized = Self {
+            palette: Vec::new(),
+            bits,
+            data: vec![0; words_for(self.len, bits)],
+            len: self.len,
+        };
+        if self.bits > 0 {
+            for i in 0..self.len {
+                resized.write_index(i, self.read_index(i));
+            }
+        }
+        self.bits = resized.bits;
+        self.data = resized.data;
+    }
+
+    fn read_index(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = (64 / self.bits) as usize;
+        let word = self.data[index / per_word];
+        let shift = (index % per_word) as u32 * self.bits;
+        ((word >> shift) & mask(self.bits)) as usize
+    }
+
+    fn write_index(&mut self, index: usize, value: usize) {
+        if self.bits == 0 {
+            return;
+        }
+        let per_word = (64 / self.bits) as usize;
+        let word = &mut self.data[index / per_word];
+        let shift = (index % per_word) as u32 * self.bits;
+        *word = (*word & !(mask(self.bits) << shift)) | ((value as u64) << shift);
+    }
+}
+
+/// The smallest number of bits that can address a palette of `len` entries.
+fn bits_for(len: usize) -> u32 {
+    if len <= 1 {
+        0
+    } else {
+        usize::BITS - (len - 1).leading_zeros()
+    }
+}
+
+fn words_for(len: usize, bits: u32) -> usize {
+    if bits == 0 {
+        return 0;
+    }
+    len.div_ceil((64 / bits) as usize)
+}
+
+const fn mask(bits: u32) -> u64 {
+    (1 << bits) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PalettedStorage;
+
+    #[test]
+    pub fn uniform_storage_has_no_index_data() {
+        let storage = PalettedStorage::new(4096, 7u16);
+        assert_eq!(storage.bits(), 0);
+        assert_eq!(storage.uniform(), Some(7));
+        assert!(storage.iter().all(|v| v == 7));
+    }
+
+    #[test]
+    pub fn storage_grows_bit_width() {
+        let mut storage = PalettedStorage::new(4096, 0u16);
+        storage.set(0, 1);
+        assert_eq!(storage.bits(), 1);
+        storage.set(1, 2);
+        assert_eq!(storage.bits(), 2);
+        for i in 3..20 {
+            storage.set(i as usize, i);
+        }
+        assert_eq!(storage.bits(), 5);
+
+        assert_eq!(storage.get(0), 1);
+        assert_eq!(storage.get(1), 2);
+        assert_eq!(storage.get(2), 0);
+        for i in 3..20 {
+            assert_eq!(storage.get(i as usize), i);
+        }
+        assert_eq!(storage.get(4095), 0);
+    }
+
+    #[test]
+    pub fn storage_from_slice_roundtrip() {
+        let values = (0..1000u32).map(|i| i % 13).collect::<Vec<_>>();
+        let storage = PalettedStorage::from_slice(&values);
+        assert_eq!(storage.palette().len(), 13);
+        assert_eq!(storage.bits(), 4);
+        assert!(storage.iter().eq(values.iter().copied()));
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn storage_from_empty_slice_panics() {
+        PalettedStorage::<u16>::from_slice(&[]);
+    }
+
+    #[test]
+    pub fn storage_compact_drops_unused_entries() {
+        let mut storage = PalettedStorage::new(64, 0u8);
+        storage.set(3, 1);
+        storage.set(4, 2);
+        storage.set(3, 0);
+        storage.set(4, 0);
+        assert_eq!(storage.palette().len(), 3);
+        storage.compact();
+        assert_eq!(storage.uniform(), Some(0));
+        assert_eq!(storage.bits(), 0);
+    }
+}
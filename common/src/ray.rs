@@ -0,0 +1,342 @@
+use vek::{Aabb, Vec3};
+
+use crate::{block::BlockRegistry, dir::Direction, resources::TerrainMap};
+
+/// The maximum distance at which a player can break or place blocks.
+pub const BLOCK_REACH: f32 = 8.0;
+
+/// The result of a ray hitting a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// The world position of the block that was hit.
+    pub pos: Vec3<i32>,
+    /// The face of the block the ray entered through.
+    pub face: Direction,
+    /// The position in front of the hit face, i.e where a new block would be placed.
+    pub adjacent: Vec3<i32>,
+    /// The distance travelled by the ray until it hit the block.
+    pub distance: f32,
+}
+
+/// Casts a ray through the terrain and returns the first non air block it hits.
+///
+/// Solid blocks are only hit on their
+/// [collision boxes](crate::block::BlockDescriptor::collision_boxes), the others on their
+/// whole volume so that plants can still be targeted.
+/// Unloaded chunks are treated as air. The block containing `origin` is never hit.
+pub fn raycast(
+    terrain: &TerrainMap,
+    registry: &BlockRegistry,
+    origin: Vec3<f32>,
+    dir: Vec3<f32>,
+    max_distance: f32,
+) -> Option<RayHit> {
+    // Where the ray enters the boxes of the last block it hit, if it isn't on the block side
+    let mut entry = None;
+    let unit = dir.normalized();
+    let mut hit = cast(origin, dir, max_distance, |pos| {
+        let Some(block) = terrain.get_block(pos).filter(|block| !block.is_air()) else {
+            return false;
+        };
+        let boxes = registry
+            .get(block.id)
+            .map(|descriptor| descriptor.collision_boxes(block.state))
+            .unwrap_or_default();
+        if boxes.is_empty() {
+            entry = None;
+            return true;
+        }
+        let offset = pos.map(|x| x as f32);
+        entry = boxes
+            .into_iter()
+            .filter_map(|b| {
+                let aabb = Aabb {
+                    min: b.min + offset,
+                    max: b.max + offset,
+                };
+                intersect(origin, unit, aabb)
+            })
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b));
+        entry.is_some()
+    })?;
+    if let Some((distance, face)) = entry {
+        hit.face = face;
+        hit.adjacent = hit.pos + face.vec();
+        hit.distance = distance;
+    }
+    Some(hit)
+}
+
+/// Returns the distance along the ray to where it enters `aabb` and the face it enters
+/// through. `dir` must be normalized, and `origin` outside of the box.
+fn intersect(origin: Vec3<f32>, dir: Vec3<f32>, aabb: Aabb<f32>) -> Option<(f32, Direction)> {
+    let mut near = f32::NEG_INFINITY;
+    let mut far = f32::INFINITY;
+    let mut face = None;
+    for axis in 0..3 {
+        if dir[axis] == 0.0 {
+            if origin[axis] < aabb.min[axis] || origin[axis] > aabb.max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let a = (aabb.min[axis] - origin[axis]) / dir[axis];
+        let b = (aabb.max[axis] - origin[axis]) / dir[axis];
+        let (enter, exit) = if a < b { (a, b) } else { (b, a) };
+        if enter > near {
+            near = enter;
+            let mut normal = Vec3::zero();
+            normal[axis] = if dir[axis] > 0.0 { -1 } else { 1 };
+            face = Direction::from_vec(normal);
+        }
+        far = far.min(exit);
+    }
+    if near > far || near < 0.0 {
+        return None;
+    }
+    face.map(|face| (near, face))
+}
+
+/// Walks the voxel grid along a ray and returns the first voxel for which `is_hit` returns true.
+///
+/// This is the grid traversal algorithm from Amanatides & Woo, so every voxel crossed
+/// by the ray is visited exactly once, in order. When the ray crosses an edge or a corner
+/// exactly, it steps along X first, then Y, then Z.
+pub fn cast(
+    origin: Vec3<f32>,
+    dir: Vec3<f32>,
+    max_distance: f32,
+    mut is_hit: impl FnMut(Vec3<i32>) -> bool,
+) -> Option<RayHit> {
+    if dir.magnitude_squared() == 0.0 || !origin.map(f32::is_finite).reduce_and() {
+        return None;
+    }
+    let dir = dir.normalized().into_array();
+    let origin = origin.into_array();
+
+    let mut pos = origin.map(|x| x.floor() as i32);
+    let step = dir.map(|d| {
+        if d > 0.0 {
+            1
+        } else if d < 0.0 {
+            -1
+        } else {
+            0
+        }
+    });
+    // How far along the ray we have to move to cross a whole voxel on each axis
+    let t_delta = dir.map(|d| {
+        if d != 0.0 {
+            1.0 / d.abs()
+        } else {
+            f32::INFINITY
+        }
+    });
+    // How far along the ray the next voxel boundary is on each axis
+    let mut t_max = [0.0; 3];
+    for axis in 0..3 {
+        t_max[axis] = if dir[axis] > 0.0 {
+            (pos[axis] as f32 + 1.0 - origin[axis]) / dir[axis]
+        } else if dir[axis] < 0.0 {
+            (origin[axis] - pos[axis] as f32) / -dir[axis]
+        } else {
+            f32::INFINITY
+        };
+    }
+
+    loop {
+        // Step along the axis with the closest boundary. Ties go to the first axis.
+        let mut axis = 0;
+        for candidate in 1..3 {
+            if t_max[candidate] < t_max[axis] {
+                axis = candidate;
+            }
+        }
+        let distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+
+        pos[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+
+        let pos = Vec3::from(pos);
+        if is_hit(pos) {
+            let mut normal = Vec3::zero();
+            normal[axis] = -step[axis];
+            let face = Direction::from_vec(normal).unwrap();
+            return Some(RayHit {
+                pos,
+                face,
+                adjacent: pos + normal,
+                distance,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::{Vec2, Vec3};
+
+    use crate::{
+        block::{Block, BlockDescriptor, BlockRegistry},
+        chunk::Chunk,
+        dir::Direction,
+        resources::TerrainMap,
+    };
+
+    use super::{cast, raycast};
+
+    fn registry() -> BlockRegistry {
+        let stone: BlockDescriptor = toml::from_str("name = \"Stone\"").unwrap();
+        let slab: BlockDescriptor = toml::from_str(
+            "name = \"Slab\"\n[model]\ntype = \"boxes\"\n[[model.boxes]]\nfrom = [0, 0, 0]\nto = [16, 8, 16]",
+        )
+        .unwrap();
+        BlockRegistry::from_descriptors(vec![stone, slab]).unwrap()
+    }
+
+    #[test]
+    pub fn ray_hits_block_face() {
+        let target = Vec3::new(3, 0, 0);
+        let hit = cast(Vec3::new(0.5, 0.5, 0.5), Vec3::unit_x(), 10.0, |p| {
+            p == target
+        })
+        .unwrap();
+        assert_eq!(hit.pos, target);
+        assert_eq!(hit.face, Direction::West);
+        assert_eq!(hit.adjacent, Vec3::new(2, 0, 0));
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+
+        let hit = cast(Vec3::new(0.5, 5.5, 0.5), -Vec3::unit_y(), 10.0, |p| {
+            p.y == 0
+        })
+        .unwrap();
+        assert_eq!(hit.face, Direction::Up);
+        assert_eq!(hit.adjacent, Vec3::new(0, 1, 0));
+    }
+
+    #[test]
+    pub fn ray_respects_max_distance() {
+        let hit = cast(Vec3::new(0.5, 0.5, 0.5), Vec3::unit_z(), 2.0, |p| p.z == 3);
+        assert!(hit.is_none());
+        let hit = cast(Vec3::new(0.5, 0.5, 0.5), Vec3::zero(), 2.0, |_| true);
+        assert!(hit.is_none());
+    }
+
+    #[test]
+    pub fn ray_breaks_ties_in_axis_order() {
+        // This ray goes exactly through the corner shared by 8 voxels
+        let mut visited = Vec::new();
+        cast(Vec3::new(0.5, 0.5, 0.5), Vec3::one(), 1.0, |p| {
+            visited.push(p);
+            false
+        });
+        assert_eq!(
+            visited,
+            vec![Vec3::new(1, 0, 0), Vec3::new(1, 1, 0), Vec3::new(1, 1, 1)]
+        );
+
+        let hit = cast(
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(1.0, 1.0, 0.0),
+            5.0,
+            |p| p == Vec3::new(1, 1, 0),
+        )
+        .unwrap();
+        // The ray entered (1, 1, 0) from (1, 0, 0), i.e from below
+        assert_eq!(hit.face, Direction::Down);
+    }
+
+    #[test]
+    pub fn ray_crosses_chunk_borders() {
+        let registry = registry();
+        let stone = registry.id("stone").unwrap();
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::new(0, 0), Chunk::flat(Block::AIR));
+        terrain
+            .chunks
+            .insert(Vec2::new(-1, 0), Chunk::flat(Block::AIR));
+        terrain
+            .chunks
+            .insert(Vec2::new(-1, -1), Chunk::flat(Block::AIR));
+        terrain.set_block(Vec3::new(-3, 10, 4), stone);
+        terrain.set_block(Vec3::new(-1, 10, -2), stone);
+
+        let hit = raycast(
+            &terrain,
+            &registry,
+            Vec3::new(2.5, 10.5, 4.5),
+            -Vec3::unit_x(),
+            10.0,
+        )
+        .unwrap();
+        assert_eq!(hit.pos, Vec3::new(-3, 10, 4));
+        assert_eq!(hit.face, Direction::East);
+        assert_eq!(hit.adjacent, Vec3::new(-2, 10, 4));
+
+        let hit = raycast(
+            &terrain,
+            &registry,
+            Vec3::new(-0.5, 10.5, 0.5),
+            -Vec3::unit_z(),
+            10.0,
+        )
+        .unwrap();
+        assert_eq!(hit.pos, Vec3::new(-1, 10, -2));
+        assert_eq!(hit.face, Direction::North);
+
+        // Unloaded chunks are treated as air
+        assert!(raycast(
+            &terrain,
+            &registry,
+            Vec3::new(0.5, 10.5, 0.5),
+            Vec3::unit_x(),
+            40.0
+        )
+        .is_none());
+    }
+
+    #[test]
+    pub fn ray_only_hits_the_boxes_of_slabs() {
+        let registry = registry();
+        let stone = registry.id("stone").unwrap();
+        let slab = registry.id("slab").unwrap();
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::new(0, 0), Chunk::flat(Block::AIR));
+        terrain.set_block(Vec3::new(4, 10, 4), slab);
+        terrain.set_block(Vec3::new(6, 10, 4), stone);
+
+        // From above, the ray stops on the top of the slab
+        let hit = raycast(
+            &terrain,
+            &registry,
+            Vec3::new(4.5, 12.0, 4.5),
+            -Vec3::unit_y(),
+            5.0,
+        )
+        .unwrap();
+        assert_eq!(hit.pos, Vec3::new(4, 10, 4));
+        assert_eq!(hit.face, Direction::Up);
+        assert_eq!(hit.adjacent, Vec3::new(4, 11, 4));
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+
+        // Above the slab it goes through the empty half
+        let hit = raycast(
+            &terrain,
+            &registry,
+            Vec3::new(2.5, 10.75, 4.5),
+            Vec3::unit_x(),
+            8.0,
+        )
+        .unwrap();
+        assert_eq!(hit.pos, Vec3::new(6, 10, 4));
+        assert_eq!(hit.face, Direction::West);
+    }
+}
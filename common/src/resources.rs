@@ -1,8 +1,8 @@
 use std::collections::{HashMap, HashSet};
 
-use vek::Vec2;
+use vek::{Vec2, Vec3};
 
-use crate::{chunk::Chunk, uid::Uid};
+use crate::{block::Block, chunk::Chunk, light::Light, uid::Uid};
 
 /// This resource stores the time passed since the previous tick
 #[derive(Default)]
@@ -18,6 +18,40 @@ pub struct TerrainMap {
     pub pending_chunks: HashSet<Vec2<i32>>,
 }
 
+impl TerrainMap {
+    /// Returns the block at a world position.
+    ///
+    /// Returns `None` if the chunk is not loaded or the position is above or below the world.
+    pub fn get_block(&self, pos: Vec3<i32>) -> Option<Block> {
+        let (chunk_pos, local) = Chunk::world_to_local(pos);
+        self.chunks.get(&chunk_pos)?.get_block(local)
+    }
+
+    /// Replaces the block at a world position and returns the previous one.
+    ///
+    /// The edit is recorded in the dirty set of the chunk.
+    /// Returns `None` without doing anything if the chunk is not loaded
+    /// or the position is above or below the world.
+    pub fn set_block(&mut self, pos: Vec3<i32>, block: impl Into<Block>) -> Option<Block> {
+        let (chunk_pos, local) = Chunk::world_to_local(pos);
+        self.chunks.get_mut(&chunk_pos)?.set(local, block)
+    }
+
+    /// Returns the light level at a world position.
+    ///
+    /// Returns `None` if the chunk is not loaded or the position is above or below the world.
+    pub fn get_light(&self, pos: Vec3<i32>) -> Option<Light> {
+        let (chunk_pos, local) = Chunk::world_to_local(pos);
+        self.chunks.get(&chunk_pos)?.light(local)
+    }
+
+    /// Replaces the light level at a world position and returns the previous one.
+    pub fn set_light(&mut self, pos: Vec3<i32>, light: Light) -> Option<Light> {
+        let (chunk_pos, local) = Chunk::world_to_local(pos);
+        self.chunks.get_mut(&chunk_pos)?.set_light(local, light)
+    }
+}
+
 #[derive(Default)]
 pub struct Ping(pub f64);
 
@@ -61,11 +95,62 @@ impl EntityMap {
 
 pub struct TerrainConfig {
     pub visible_chunk_radius: u32,
+    /// Whether chunk meshes merge coplanar faces into larger quads.
+    pub greedy_meshing: bool,
 }
 impl Default for TerrainConfig {
     fn default() -> Self {
         Self {
             visible_chunk_radius: 8,
+            greedy_meshing: true,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use vek::{Vec2, Vec3};
+
+    use crate::{
+        block::{Block, BlockId},
+        chunk::Chunk,
+    };
+
+    use super::TerrainMap;
+
+    #[test]
+    pub fn terrain_set_block_across_chunks() {
+        let stone = BlockId(3);
+        let mut terrain = TerrainMap::default();
+        terrain
+            .chunks
+            .insert(Vec2::new(0, 0), Chunk::flat(BlockId::AIR));
+        terrain
+            .chunks
+            .insert(Vec2::new(-1, 0), Chunk::flat(BlockId::AIR));
+
+        assert_eq!(
+            terrain.set_block(Vec3::new(-1, 5, 3), stone),
+            Some(Block::AIR)
+        );
+        assert_eq!(
+            terrain.set_block(Vec3::new(0, 5, 3), stone),
+            Some(Block::AIR)
+        );
+        // Not loaded
+        assert_eq!(terrain.set_block(Vec3::new(0, 5, -1), stone), None);
+        // Above the world
+        assert_eq!(terrain.set_block(Vec3::new(0, 256, 0), stone), None);
+
+        assert_eq!(
+            terrain.get_block(Vec3::new(-1, 5, 3)),
+            Some(Block::new(stone))
+        );
+        assert_eq!(terrain.get_block(Vec3::new(-2, 5, 3)), Some(Block::AIR));
+
+        let west = &terrain.chunks[&Vec2::new(-1, 0)];
+        assert!(west.dirty().contains(&Vec3::new(15, 5, 3)));
+        let east = &terrain.chunks[&Vec2::new(0, 0)];
+        assert!(east.dirty().contains(&Vec3::new(0, 5, 3)));
+    }
+}
@@ -1,8 +1,9 @@
-use vek::{Mat4, Vec2, Vec3};
+use vek::{Aabb, Mat4, Vec2, Vec3, Vec4};
 
 const Z_NEAR: f32 = 0.1;
 const Z_FAR: f32 = 1000.0;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Plane {
     pub normal: Vec3<f32>,
     pub distance: f32,
@@ -10,11 +11,74 @@ pub struct Plane {
 
 impl Plane {
     pub fn new(point: Vec3<f32>, normal: Vec3<f32>) -> Self {
+        let normal = normal.normalized();
         Self {
-            normal: normal.normalized(),
+            normal,
             distance: point.dot(normal),
         }
     }
+
+    /// Creates the plane of the points where `a * x + b * y + c * z + d = 0`,
+    /// with the normal pointing towards the positive side.
+    pub fn from_coefficients(coefficients: Vec4<f32>) -> Self {
+        let length = coefficients.xyz().magnitude();
+        Self {
+            normal: coefficients.xyz() / length,
+            distance: -coefficients.w / length,
+        }
+    }
+
+    /// The distance from the plane to `point`, negative behind the plane.
+    pub fn signed_distance(&self, point: Vec3<f32>) -> f32 {
+        self.normal.dot(point) - self.distance
+    }
+}
+
+/// The volume seen by a camera, bounded by six planes facing inwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// The left, right, bottom, top, near and far planes.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix with a clip space depth from -1 to 1.
+    pub fn from_view_proj(view_proj: Mat4<f32>) -> Self {
+        let rows = view_proj.into_row_arrays().map(Vec4::from);
+        Self {
+            planes: [
+                Plane::from_coefficients(rows[3] + rows[0]),
+                Plane::from_coefficients(rows[3] - rows[0]),
+                Plane::from_coefficients(rows[3] + rows[1]),
+                Plane::from_coefficients(rows[3] - rows[1]),
+                Plane::from_coefficients(rows[3] + rows[2]),
+                Plane::from_coefficients(rows[3] - rows[2]),
+            ],
+        }
+    }
+
+    /// Whether any part of the box may be inside the frustum.
+    ///
+    /// Boxes near the corners of the frustum can be kept even though they are outside,
+    /// but a box that is partly inside is never rejected.
+    pub fn intersects_aabb(&self, aabb: Aabb<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner of the box furthest along the normal
+            let corner =
+                plane.normal.map3(
+                    aabb.min,
+                    aabb.max,
+                    |normal, min, max| {
+                        if normal >= 0.0 {
+                            max
+                        } else {
+                            min
+                        }
+                    },
+                );
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
 }
 
 pub struct Matrices {
@@ -52,13 +116,20 @@ impl Default for Camera {
 }
 impl Camera {
     pub fn compute_matrices(&mut self) -> Matrices {
-        let view = Mat4::look_at_lh(self.pos, self.pos + self.forward(), Vec3::unit_y());
         Matrices {
-            view,
+            view: self.view(),
             proj: self.proj,
         }
     }
 
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(self.proj * self.view())
+    }
+
+    fn view(&self) -> Mat4<f32> {
+        Mat4::look_at_lh(self.pos, self.pos + self.forward(), Vec3::unit_y())
+    }
+
     pub fn move_by(&mut self, dx: f32, dy: f32, dz: f32) {
         self.pos += dz * self.forward_xz() + -dx * self.right() + Vec3::unit_y() * dy;
     }
@@ -128,3 +199,51 @@ impl Camera {
         self.proj = Mat4::perspective_lh_no(self.fov.to_radians(), self.aspect, Z_NEAR, Z_FAR)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use vek::{Aabb, Vec2, Vec3};
+
+    use super::Camera;
+
+    /// A camera at the origin looking along +X.
+    fn camera() -> Camera {
+        let mut camera = Camera {
+            pos: Vec3::zero(),
+            rot: Vec2::zero(),
+            ..Camera::default()
+        };
+        camera.set_aspect_ratio(1.0);
+        camera
+    }
+
+    fn cube(center: Vec3<f32>, size: f32) -> Aabb<f32> {
+        Aabb {
+            min: center - size / 2.0,
+            max: center + size / 2.0,
+        }
+    }
+
+    #[test]
+    pub fn frustum_keeps_boxes_in_front() {
+        let frustum = camera().frustum();
+        assert!(frustum.intersects_aabb(cube(Vec3::new(20.0, 0.0, 0.0), 1.0)));
+        // Right next to the camera, across the near plane
+        assert!(frustum.intersects_aabb(cube(Vec3::zero(), 1.0)));
+        // Mostly outside a side plane, but one of its corners pokes in
+        assert!(frustum.intersects_aabb(cube(Vec3::new(20.0, 0.0, 20.0), 8.0)));
+    }
+
+    #[test]
+    pub fn frustum_culls_boxes_outside() {
+        let frustum = camera().frustum();
+        // Behind the camera
+        assert!(!frustum.intersects_aabb(cube(Vec3::new(-20.0, 0.0, 0.0), 1.0)));
+        // Beside it, outside the 70 degrees field of view
+        assert!(!frustum.intersects_aabb(cube(Vec3::new(10.0, 0.0, 20.0), 1.0)));
+        assert!(!frustum.intersects_aabb(cube(Vec3::new(10.0, 0.0, -20.0), 1.0)));
+        assert!(!frustum.intersects_aabb(cube(Vec3::new(10.0, 20.0, 0.0), 1.0)));
+        // Past the far plane
+        assert!(!frustum.intersects_aabb(cube(Vec3::new(1100.0, 0.0, 0.0), 1.0)));
+    }
+}
@@ -1,5 +1,7 @@
 #[derive(Debug)]
 pub enum Error {
     ServerTimeout,
+    /// The server refused the connection, or closed it.
+    Disconnected(String),
     Other(String),
 }
@@ -1,36 +1,63 @@
 pub mod error;
 
-use std::{io::ErrorKind, net::SocketAddr, time::Duration};
+use std::{collections::HashSet, io::ErrorKind, net::SocketAddr, time::Duration};
 
 use common::{
+    block::{BlockRegistry, BLOCKS_DIR},
+    chunk::CompressedChunk,
     components::Pos,
     net::{
-        connection::Connection,
+        connection::{Connection, PacketCounters},
         error::NetworkError,
-        packet::{ClientPacket, PingPacket, ServerPacket},
+        packet::{ClientPacket, Handshake, PingPacket, ServerPacket},
     },
     resources::{Ping, ProgramTime, TerrainConfig, TerrainMap},
     state::State,
 };
 use log::info;
+use vek::Vec2;
 
 use self::error::Error;
 
+/// Packets queued by the game systems, sent to the server at the end of the tick.
+#[derive(Default)]
+pub struct OutgoingPackets(pub Vec<ClientPacket>);
+
 pub struct Client {
     connection: Connection<ClientPacket, ServerPacket>,
     state: State,
     /// The last time we received a ping packet from the server
     last_ping_time: f64,
     packet_count: usize,
-    last_chunk_request_time: f64,
+    /// The chunks requested from the server that did not arrive yet.
+    requested_chunks: HashSet<Vec2<i32>>,
+    /// The most packets handled in a tick, the others wait for the next ticks.
+    packet_budget: usize,
 }
 
+/// The packet budget of clients unless the player chose another one.
+pub const DEFAULT_PACKET_BUDGET: usize = 1024;
+
 impl Client {
-    pub fn new(host: SocketAddr) -> Result<Self, Error> {
-        let connection: Connection<ClientPacket, ServerPacket> = Connection::connect(host).unwrap();
-        info!("Connecting to {}", host);
-        connection.send(ClientPacket::Connect).unwrap();
+    /// Joins the server at `host`, handling up to `packet_budget` packets every tick.
+    pub fn new(host: SocketAddr, player_name: &str, packet_budget: usize) -> Result<Self, Error> {
+        let registry = BlockRegistry::load(BLOCKS_DIR)
+            .map_err(|e| Error::Other(format!("Failed to load block registry: {:?}", e)))?;
+        Self::with_registry(host, player_name, packet_budget, registry)
+    }
+
+    fn with_registry(
+        host: SocketAddr,
+        player_name: &str,
+        packet_budget: usize,
+        mut registry: BlockRegistry,
+    ) -> Result<Self, Error> {
+        let mut connection: Connection<ClientPacket, ServerPacket> =
+            Connection::connect(host).unwrap();
         let mut state = State::client().expect("Failed to create client state");
+        info!("Connecting to {} as {}", host, player_name);
+        let handshake = Handshake::new(player_name, &registry);
+        connection.send(ClientPacket::Connect(handshake)).unwrap();
         let instant = std::time::Instant::now();
 
         loop {
@@ -38,18 +65,26 @@ impl Client {
                 Ok((packet, addr)) => {
                     log::info!("Received packet from {}: {:?}", addr, packet);
                     match packet {
-                        ServerPacket::ClientSync { uid } => {
+                        ServerPacket::ClientSync { uid, blocks } => {
+                            // Use the same block ids as the server
+                            registry.remap(&blocks).map_err(|e| {
+                                Error::Other(format!("Incompatible block registry: {:?}", e))
+                            })?;
                             log::info!("Joined to game with uid {}", uid);
                             let entity = state.ecs_mut().entity();
                             entity.with_bundle((Pos::default(), uid));
                             break;
                         },
+                        ServerPacket::Disconnect { reason } => {
+                            return Err(Error::Disconnected(reason));
+                        },
                         ServerPacket::Ping(_) => {},
                         _ => (),
                     }
                 },
                 // TODO: return errors instead of panicking
                 Err(NetworkError::IOError(ErrorKind::WouldBlock)) => {
+                    connection.update();
                     if instant.elapsed() > Duration::from_secs(5) {
                         return Err(Error::ServerTimeout);
                     }
@@ -63,18 +98,32 @@ impl Client {
             }
         }
 
+        state
+            .ecs_mut()
+            .with_resource(registry)
+            .and_then(|ecs| ecs.with_default_resource::<OutgoingPackets>())
+            .and_then(|ecs| ecs.with_default_resource::<PacketCounters>())
+            .map_err(|e| Error::Other(e.to_string()))?;
+
         Ok(Self {
             connection,
             state,
             last_ping_time: 0.0,
             packet_count: 0,
-            last_chunk_request_time: 0.0,
+            requested_chunks: HashSet::new(),
+            packet_budget,
         })
     }
 
-    pub fn tick(&mut self, dt: Duration) {
+    /// Runs the game for `dt`, and fails once the server ended the session.
+    pub fn tick(&mut self, dt: Duration) -> Result<(), Error> {
         self.state.tick(dt);
 
+        let packets = std::mem::take(&mut self.state.resource_mut::<OutgoingPackets>().0);
+        for packet in packets {
+            self.send_packet(packet);
+        }
+
         let time = self.state.resource::<ProgramTime>();
 
         if time.0 - self.last_ping_time > 1.0 {
@@ -82,45 +131,70 @@ impl Client {
             self.last_ping_time = self.state.program_time();
         }
 
-        if let Ok((packet, _)) = self.connection.recv() {
-            match packet {
-                ServerPacket::Ping(PingPacket::Ping) => {
-                    // pong
-                },
-                ServerPacket::Ping(PingPacket::Pong) => {
-                    // update ping
-                    self.state_mut().resource_mut::<Ping>().0 =
-                        self.state.program_time() - self.last_ping_time;
-                },
-                ServerPacket::ChunkUpdate { pos, data } => {
-                    let chunk = common::chunk::decompress(&data);
-                    let terrain = self.state.resource_mut::<TerrainMap>();
-                    let old = terrain.chunks.insert(pos, chunk);
-                    if let Some(old) = old {
-                        log::warn!("Overwriting chunk at {:?} with new chunk", pos);
-                    }
-                    terrain.pending_chunks.remove(&pos);
-                },
-                _ => (),
+        for _ in 0..self.packet_budget {
+            match self.connection.recv() {
+                Ok((packet, _)) => self.handle_packet(packet)?,
+                Err(NetworkError::IOError(ErrorKind::WouldBlock)) => break,
+                Err(e) => log::warn!("Failed to receive packet: {:?}", e),
             }
         }
-        // this may run multiple times until the chunk arrives
-        // so we'll throttle the chunk requests.
-        if self.state.program_time() - self.last_chunk_request_time < 0.1 {
-            return;
-        }
 
+        // Requests are reliable, so every chunk is only requested once
         let terrain = self.state.resource::<TerrainMap>();
-        for pending in &terrain.pending_chunks {
-            if !terrain.chunks.contains_key(pending) {
-                self.send_packet(ClientPacket::ChunkRequest(*pending));
-                self.last_chunk_request_time = self.state.program_time();
-                self.packet_count += 1;
-            }
+        let requests: Vec<_> = terrain
+            .pending_chunks
+            .iter()
+            .filter(|pos| !terrain.chunks.contains_key(*pos))
+            .filter(|pos| self.requested_chunks.insert(**pos))
+            .copied()
+            .collect();
+        for pos in requests {
+            self.send_packet(ClientPacket::ChunkRequest(pos));
+            self.packet_count += 1;
+        }
+        self.connection.update();
+        *self.state.resource_mut::<PacketCounters>() = self.connection.take_counters();
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, packet: ServerPacket) -> Result<(), Error> {
+        match packet {
+            ServerPacket::Ping(PingPacket::Ping) => {
+                // The server checks that we are still here
+                self.send_packet(ClientPacket::Ping(PingPacket::Pong));
+            },
+            ServerPacket::Ping(PingPacket::Pong) => {
+                // update ping
+                self.state_mut().resource_mut::<Ping>().0 =
+                    self.state.program_time() - self.last_ping_time;
+            },
+            ServerPacket::ChunkUpdate { pos, data } => self.handle_chunk_update(pos, data),
+            ServerPacket::BlockUpdate { pos, block } => {
+                let terrain = self.state.resource_mut::<TerrainMap>();
+                // The chunk may have been unloaded while the update was in flight
+                if terrain.set_block(pos, block).is_none() {
+                    log::debug!("Ignoring block update for unloaded position {:?}", pos);
+                }
+            },
+            ServerPacket::Disconnect { reason } => return Err(Error::Disconnected(reason)),
+            ServerPacket::ClientSync { .. } => {
+                log::debug!("Ignoring sync packet received after joining");
+            },
         }
+        Ok(())
     }
 
-    pub fn send_packet(&self, packet: ClientPacket) {
+    fn handle_chunk_update(&mut self, pos: Vec2<i32>, data: CompressedChunk) {
+        let chunk = common::chunk::decompress(&data);
+        let terrain = self.state.resource_mut::<TerrainMap>();
+        if terrain.chunks.insert(pos, chunk).is_some() {
+            log::warn!("Overwriting chunk at {:?} with new chunk", pos);
+        }
+        terrain.pending_chunks.remove(&pos);
+        self.requested_chunks.remove(&pos);
+    }
+
+    pub fn send_packet(&mut self, packet: ClientPacket) {
         if let Err(e) = self.connection.send(packet) {
             log::error!("Failed to send packet: {:?}", e);
         }
@@ -140,3 +214,120 @@ impl Drop for Client {
         self.connection.send(ClientPacket::Disconnect).unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::mpsc::{self, TryRecvError},
+        time::{Duration, Instant},
+    };
+
+    use common::{
+        block::{Block, BlockDescriptor, BlockRegistry},
+        net::{
+            connection::{Connection, PacketCounters},
+            packet::{ClientPacket, ServerPacket},
+        },
+        uid::Uid,
+    };
+    use server::events::DisconnectReason;
+    use vek::Vec3;
+
+    use super::{error::Error, Client, DEFAULT_PACKET_BUDGET};
+
+    type ServerConnection = Connection<ServerPacket, ClientPacket>;
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn registry() -> BlockRegistry {
+        let descriptor = toml::from_str::<BlockDescriptor>("name = \"Stone\"").unwrap();
+        BlockRegistry::from_descriptors(vec![descriptor]).unwrap()
+    }
+
+    /// Runs a server accepting every client in another thread. The packets sent to the
+    /// returned sender are sent to the last client, and the server stops once it is dropped.
+    fn serve() -> (SocketAddr, mpsc::Sender<ServerPacket>) {
+        let mut connection: ServerConnection =
+            Connection::listen(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = connection.local_addr().unwrap();
+        let (sender, packets) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut client = None;
+            loop {
+                while let Ok((packet, addr)) = connection.recv() {
+                    if let ClientPacket::Connect(_) = packet {
+                        let sync = ServerPacket::ClientSync {
+                            uid: Uid(1),
+                            blocks: registry().names(),
+                        };
+                        connection.send_to(sync, addr).unwrap();
+                        client = Some(addr);
+                    }
+                }
+                loop {
+                    match packets.try_recv() {
+                        Ok(packet) => connection.send_to(packet, client.unwrap()).unwrap(),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+                connection.update();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+        (addr, sender)
+    }
+
+    #[test]
+    pub fn timed_out_clients_end_their_session() {
+        let (addr, server) = serve();
+        let mut client =
+            Client::with_registry(addr, "Player", DEFAULT_PACKET_BUDGET, registry()).unwrap();
+        let reason = DisconnectReason::TimedOut.to_string();
+        server
+            .send(ServerPacket::Disconnect {
+                reason: reason.clone(),
+            })
+            .unwrap();
+
+        let start = Instant::now();
+        let error = loop {
+            assert!(start.elapsed() < Duration::from_secs(5), "Still connected");
+            if let Err(error) = client.tick(TICK) {
+                break error;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert!(matches!(error, Error::Disconnected(r) if r == reason));
+    }
+
+    #[test]
+    pub fn packets_over_the_budget_wait_for_the_next_tick() {
+        let (addr, server) = serve();
+        let mut client = Client::with_registry(addr, "Player", 2, registry()).unwrap();
+        // Forget the sync packet
+        client.tick(TICK).unwrap();
+        for _ in 0..6 {
+            let packet = ServerPacket::BlockUpdate {
+                pos: Vec3::zero(),
+                block: Block::AIR,
+            };
+            server.send(packet).unwrap();
+        }
+
+        let start = Instant::now();
+        let mut received = 0;
+        while received < 6 {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Packets were lost"
+            );
+            client.tick(TICK).unwrap();
+            let counters = client.state().resource::<PacketCounters>();
+            assert!(counters.received <= 2);
+            received += counters.received;
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -12,6 +12,8 @@ pub enum GameInput {
     Sneak,
     ToggleWireframe,
     ToggleCursor,
+    BreakBlock,
+    PlaceBlock,
 }
 
 /// Input struct that holds the state of the keyboard and mouse.
@@ -19,6 +21,7 @@ pub struct Input {
     pub pressed: [bool; 256],
     pub just_pressed: [bool; 256],
     pub buttons: [bool; 128],
+    pub just_pressed_buttons: [bool; 128],
     pub cursor_delta: Vec2<f32>,
 }
 
@@ -28,12 +31,14 @@ impl Default for Input {
             pressed: [false; 256],
             just_pressed: [false; 256],
             buttons: [false; 128],
+            just_pressed_buttons: [false; 128],
             cursor_delta: Vec2::zero(),
         }
     }
 }
 
 pub type Key = winit::keyboard::KeyCode;
+pub type MouseButton = winit::event::MouseButton;
 
 impl Input {
     pub fn press(&mut self, input: Key) {
@@ -56,14 +61,20 @@ impl Input {
     pub const fn pressed(&self, input: GameInput) -> bool {
         match key_mapping(input) {
             Some(key) => self.pressed[key as usize],
-            None => false,
+            None => match button_mapping(input) {
+                Some(button) => self.is_button_down(button),
+                None => false,
+            },
         }
     }
 
     pub const fn just_pressed(&self, input: GameInput) -> bool {
         match key_mapping(input) {
             Some(key) => self.just_pressed[key as usize],
-            None => false,
+            None => match button_mapping(input) {
+                Some(button) => self.is_button_just_pressed(button),
+                None => false,
+            },
         }
     }
 
@@ -71,19 +82,29 @@ impl Input {
         self.pressed[input as usize] = false;
     }
 
+    pub fn press_button(&mut self, button: MouseButton) {
+        let index = button_index(button);
+        if !self.buttons[index] {
+            self.just_pressed_buttons[index] = true;
+        }
+        self.buttons[index] = true;
+    }
+
+    pub fn release_button(&mut self, button: MouseButton) {
+        self.buttons[button_index(button)] = false;
+    }
+
     pub fn update(&mut self) {
         self.just_pressed = [false; 256];
+        self.just_pressed_buttons = [false; 128];
     }
 
-    pub const fn is_button_down(&self, button: winit::event::MouseButton) -> bool {
-        match button {
-            winit::event::MouseButton::Left => self.buttons[0],
-            winit::event::MouseButton::Right => self.buttons[1],
-            winit::event::MouseButton::Middle => self.buttons[2],
-            winit::event::MouseButton::Back => self.buttons[3],
-            winit::event::MouseButton::Forward => self.buttons[4],
-            winit::event::MouseButton::Other(code) => self.buttons[code as usize],
-        }
+    pub const fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons[button_index(button)]
+    }
+
+    pub const fn is_button_just_pressed(&self, button: MouseButton) -> bool {
+        self.just_pressed_buttons[button_index(button)]
     }
 
     pub fn cursor_delta(&self) -> Vec2<f32> {
@@ -101,6 +122,28 @@ const fn key_mapping(key: GameInput) -> Option<Key> {
         GameInput::Sneak => Some(Key::ShiftLeft),
         GameInput::ToggleCursor => Some(Key::Period),
         GameInput::ToggleWireframe => Some(Key::F12),
+        GameInput::BreakBlock | GameInput::PlaceBlock => None,
+    }
+}
+
+const fn button_mapping(input: GameInput) -> Option<MouseButton> {
+    match input {
+        GameInput::BreakBlock => Some(MouseButton::Left),
+        GameInput::PlaceBlock => Some(MouseButton::Right),
+        _ => None,
+    }
+}
+
+const fn button_index(button: MouseButton) -> usize {
+    match button {
+        MouseButton::Left => 0,
+        MouseButton::Right => 1,
+        MouseButton::Middle => 2,
+        MouseButton::Back => 3,
+        MouseButton::Forward => 4,
+        // Unknown buttons share the last slot
+        MouseButton::Other(code) if (code as usize) < 127 => code as usize,
+        MouseButton::Other(_) => 127,
     }
 }
 
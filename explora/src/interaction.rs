@@ -0,0 +1,69 @@
+use apecs::*;
+use common::{
+    block::{Block, BlockRegistry},
+    net::packet::ClientPacket,
+    ray::{raycast, BLOCK_REACH},
+    resources::TerrainMap,
+    SysResult,
+};
+
+use crate::{
+    camera::Camera,
+    client::OutgoingPackets,
+    input::{GameInput, Input},
+    settings::GameplaySettings,
+    window::Window,
+};
+
+pub const BLOCK_INTERACTION_SYSTEM: &str = "block_interaction";
+
+#[derive(CanFetch)]
+pub struct BlockInteractionSystem {
+    input: Read<Input>,
+    camera: Read<Camera>,
+    window: Read<Window, NoDefault>,
+    terrain: Read<TerrainMap>,
+    registry: Read<BlockRegistry, NoDefault>,
+    gameplay: Read<GameplaySettings>,
+    packets: Write<OutgoingPackets>,
+}
+
+/// Turns mouse clicks into block edit requests.
+///
+/// The server casts the same ray on its own terrain, if it starts close to the last
+/// position we sent it, and broadcasts the result. The local terrain is only used to avoid sending requests
+/// that can't hit anything.
+pub fn block_interaction_system(mut system: BlockInteractionSystem) -> SysResult {
+    if !system.window.cursor_locked() {
+        return ok();
+    }
+    let breaking = system.input.just_pressed(GameInput::BreakBlock);
+    let placing = system.input.just_pressed(GameInput::PlaceBlock);
+    if !breaking && !placing {
+        return ok();
+    }
+
+    let origin = system.camera.pos();
+    let dir = system.camera.forward();
+    if raycast(&system.terrain, &system.registry, origin, dir, BLOCK_REACH).is_none() {
+        return ok();
+    }
+
+    if breaking {
+        system
+            .packets
+            .0
+            .push(ClientPacket::BreakBlock { origin, dir });
+    } else {
+        let Some(id) = system.registry.id(&system.gameplay.selected_block) else {
+            log::warn!("Unknown block {}", system.gameplay.selected_block);
+            return ok();
+        };
+        system.packets.0.push(ClientPacket::PlaceBlock {
+            origin,
+            dir,
+            block: Block::from(id),
+        });
+    }
+    ok()
+}
@@ -3,7 +3,9 @@ pub mod camera;
 pub mod client;
 pub mod error;
 pub mod input;
+pub mod interaction;
 pub mod mesh;
+pub mod mesh_worker;
 pub mod render;
 pub mod run;
 pub mod scene;
@@ -1,12 +1,12 @@
-use common::{clock::Clock, resources::GameMode};
+use common::{block::BlockRegistry, clock::Clock, resources::GameMode};
 use explora::render::Renderer;
 use explora::settings::GameplaySettings;
 use explora::terrain;
 use explora::{
     block::BlockMap,
-    client::Client,
+    client::{Client, DEFAULT_PACKET_BUDGET},
     input::{self, Input},
-    scene,
+    interaction, scene,
     singleplayer::Singleplayer,
     ui::EguiInput,
     window::{Window, WindowEvent},
@@ -19,7 +19,8 @@ fn main() -> apecs::anyhow::Result<()> {
     });
     let singleplayer = Singleplayer::init();
     let addr = singleplayer.wait_for_init();
-    let mut client = match Client::new(addr) {
+    let player_name = std::env::var("EXPLORA_PLAYER_NAME").unwrap_or_else(|_| "Player".to_owned());
+    let mut client = match Client::new(addr, &player_name, DEFAULT_PACKET_BUDGET) {
         Ok(t) => t,
         Err(err) => {
             log::error!("{:?}", err);
@@ -35,7 +36,8 @@ fn main() -> apecs::anyhow::Result<()> {
 }
 
 fn initialize_ecs(client: &mut Client, window: Window) -> apecs::anyhow::Result<()> {
-    let block_map = BlockMap::load_blocks("assets/blocks", "assets/textures/blocks");
+    let registry = client.state().resource::<BlockRegistry>();
+    let block_map = BlockMap::load_blocks(registry, "assets/textures/blocks");
     let render_plugin = Renderer::initialize(window.platform(), block_map.textures()).unwrap();
 
     client
@@ -52,10 +54,16 @@ fn initialize_ecs(client: &mut Client, window: Window) -> apecs::anyhow::Result<
             explora::terrain::CHUNK_LOAD_SYSTEM,
             explora::terrain::chunk_load_system,
         )?
+        .with_system_with_dependencies(
+            explora::terrain::TERRAIN_LIGHT_SYSTEM,
+            explora::terrain::terrain_light_system,
+            &[terrain::CHUNK_LOAD_SYSTEM],
+            &[],
+        )?
         .with_system_with_dependencies(
             explora::terrain::TERRAIN_CHUNK_MESH_SYSTEM,
             explora::terrain::terrain_chunk_mesh,
-            &[terrain::CHUNK_LOAD_SYSTEM],
+            &[terrain::TERRAIN_LIGHT_SYSTEM],
             &[],
         )?
         .with_system_with_dependencies(
@@ -66,6 +74,10 @@ fn initialize_ecs(client: &mut Client, window: Window) -> apecs::anyhow::Result<
         )?
         .with_system_barrier()
         .with_system("scene_update", scene::scene_update_system)?
+        .with_system(
+            interaction::BLOCK_INTERACTION_SYSTEM,
+            interaction::block_interaction_system,
+        )?
         .with_system_barrier()
         .with_system("input", input::input_system)?;
 
@@ -1,197 +1,1091 @@
-use common::{chunk::Chunk, dir::Direction, resources::TerrainMap};
-use vek::{Vec2, Vec3};
+use std::collections::HashMap;
 
-use crate::{
-    block::BlockMap,
-    render::{atlas::BlockAtlas, vertex::TerrainVertex},
+use common::{
+    block::{Block, BlockRegistry, RenderLayer},
+    chunk::{Chunk, ChunkSection},
+    dir::Direction,
+    fluid::{fill_height, fluid_model},
+    light::Light,
+    model::{face_corners, BlockModel, ModelQuad, MODEL_SIZE},
+    resources::TerrainMap,
 };
+use vek::{Vec2, Vec3};
+
+use crate::render::{atlas::BlockAtlas, vertex::TerrainVertex};
+
+/// The offsets of every chunk touching a chunk, including the diagonal ones.
+pub const SURROUNDING: [Vec2<i32>; 8] = [
+    Vec2::new(0, 1),
+    Vec2::new(1, 0),
+    Vec2::new(0, -1),
+    Vec2::new(-1, 0),
+    Vec2::new(1, 1),
+    Vec2::new(1, -1),
+    Vec2::new(-1, -1),
+    Vec2::new(-1, 1),
+];
+
+/// Everything besides the terrain needed to mesh a chunk.
+///
+/// It is shared with the mesh workers, so it owns copies of the registry and the atlas tiles.
+pub struct MeshContext {
+    pub registry: BlockRegistry,
+    tiles: HashMap<String, u16>,
+    /// Whether coplanar faces are merged into larger quads.
+    pub greedy: bool,
+}
 
-pub fn create_chunk_mesh(
-    chunk: &Chunk,
-    chunk_pos: Vec2<i32>,
-    terrain_map: &TerrainMap,
-    block_map: &BlockMap,
-    block_atlas: &BlockAtlas,
-) -> Vec<TerrainVertex> {
-    
-    let mut vertices = Vec::with_capacity(3000);
-
-    for pos in chunk.iter() {
-        let origin = pos.map(|x| x as u32);
-        let render_quad = |direction: Direction| {
-            let dir = direction.vec(); // The direction of the face we are checking for render
-            let adjacent_pos = pos + dir; // The pos of the adjacent block
-
-            if Chunk::out_of_bounds(adjacent_pos) {
-                // If the adjacent block is out of bounds
-                // it means we are at the edge of the chunk
-                if matches!(direction, Direction::Up) || matches!(direction, Direction::Down) {
-                    // If the direction is up or down we can render the quad
-                    // Since we have no chunks above or below
-                    return true;
+impl MeshContext {
+    pub fn new(registry: &BlockRegistry, atlas: &BlockAtlas, greedy: bool) -> Self {
+        Self {
+            registry: registry.clone(),
+            tiles: atlas.tiles.clone(),
+            greedy,
+        }
+    }
+
+    /// Whether a block hides the faces behind it.
+    pub fn is_opaque(&self, block: Block) -> bool {
+        !block.is_air()
+            && self
+                .registry
+                .get(block.id)
+                .is_some_and(|descriptor| !descriptor.is_transparent())
+    }
+
+    pub fn texture_id(&self, texture: &str) -> u16 {
+        match self.tiles.get(texture) {
+            Some(id) => *id,
+            None => {
+                log::error!("Texture {} is not in the block atlas", texture);
+                0
+            },
+        }
+    }
+}
+
+/// The blocks and light of the columns of a chunk touching one of its neighbours.
+pub struct ChunkBorder {
+    /// The first copied column, in the local coordinates of the chunk it comes from.
+    min: Vec2<i32>,
+    /// The number of copied columns on the X and Z axes.
+    size: Vec2<i32>,
+    blocks: Vec<Block>,
+    light: Vec<Light>,
+}
+
+impl ChunkBorder {
+    /// Copies the columns of `chunk` touching the chunk at `-offset` from it.
+    pub fn new(chunk: &Chunk, offset: Vec2<i32>) -> Self {
+        let chunk_size = Vec2::new(Chunk::SIZE.x as i32, Chunk::SIZE.z as i32);
+        // A chunk to the east only touches its west column, and so on
+        let min = offset.map2(chunk_size, |o, size| if o < 0 { size - 1 } else { 0 });
+        let size = offset.map2(chunk_size, |o, size| if o == 0 { size } else { 1 });
+        let mut blocks = Vec::with_capacity((size.product() * Chunk::SIZE.y as i32) as usize);
+        let mut light = Vec::with_capacity(blocks.capacity());
+        for z in min.y..min.y + size.y {
+            for x in min.x..min.x + size.x {
+                for y in 0..Chunk::SIZE.y as i32 {
+                    let pos = Vec3::new(x, y, z);
+                    blocks.push(chunk.get_block(pos).unwrap_or_default());
+                    light.push(chunk.light(pos).unwrap_or_default());
                 }
+            }
+        }
+        Self {
+            min,
+            size,
+            blocks,
+            light,
+        }
+    }
 
-                // Now we have to check if there is a chunk adjacent to this one
-                let neighbor_chunk_dir = Vec2::new(chunk_pos.x + dir.x, chunk_pos.y + dir.z);
-
-                let Some(neighbor_chunk) = terrain_map.chunks.get(&(neighbor_chunk_dir)) else {
-                    // If there is no adjacent chunk we have to render the quad
-                    // because it is a border of the chunk
-                    return true;
-                };
-
-                // map out of bound adj block pos to neighbor local pos
-                let neighbor_block_in_border = Vec3::new(
-                    if adjacent_pos.x < 0 {
-                        Chunk::SIZE.x as i32 - 1
-                    } else if adjacent_pos.x >= Chunk::SIZE.x as i32 {
-                        0
-                    } else {
-                        adjacent_pos.x
-                    },
-                    adjacent_pos.y,
-                    if adjacent_pos.z < 0 {
-                        Chunk::SIZE.z as i32 - 1
-                    } else if adjacent_pos.z >= Chunk::SIZE.z as i32 {
-                        0
-                    } else {
-                        adjacent_pos.z
-                    },
+    /// The index of a local position of the original chunk, if it was copied.
+    fn index_of(&self, pos: Vec3<i32>) -> Option<usize> {
+        let column = Vec2::new(pos.x, pos.z) - self.min;
+        if column.x < 0
+            || column.y < 0
+            || column.x >= self.size.x
+            || column.y >= self.size.y
+            || pos.y < 0
+            || pos.y >= Chunk::SIZE.y as i32
+        {
+            return None;
+        }
+        let column = column.x + column.y * self.size.x;
+        Some((column * Chunk::SIZE.y as i32 + pos.y) as usize)
+    }
+
+    pub fn get_block(&self, pos: Vec3<i32>) -> Option<Block> {
+        self.index_of(pos).map(|index| self.blocks[index])
+    }
+
+    pub fn light(&self, pos: Vec3<i32>) -> Option<Light> {
+        self.index_of(pos).map(|index| self.light[index])
+    }
+}
+
+/// A copy of a chunk and of the borders of its loaded neighbours, so it can be meshed
+/// away from the ECS.
+pub struct ChunkSnapshot {
+    pub pos: Vec2<i32>,
+    pub chunk: Chunk,
+    /// The borders of the surrounding chunks in the same order as [`SURROUNDING`].
+    /// The diagonal ones are needed for the ambient occlusion of the chunk corners.
+    pub neighbours: [Option<ChunkBorder>; 8],
+}
+
+impl ChunkSnapshot {
+    /// Copies the chunk at `pos` and the borders of its neighbours.
+    /// Returns `None` if the chunk is not loaded.
+    pub fn new(terrain: &TerrainMap, pos: Vec2<i32>) -> Option<Self> {
+        let chunk = terrain.chunks.get(&pos)?.clone();
+        let neighbours = SURROUNDING.map(|offset| {
+            terrain
+                .chunks
+                .get(&(pos + offset))
+                .map(|chunk| ChunkBorder::new(chunk, offset))
+        });
+        Some(Self {
+            pos,
+            chunk,
+            neighbours,
+        })
+    }
+
+    pub fn neighbour(&self, offset: Vec2<i32>) -> Option<&ChunkBorder> {
+        let index = SURROUNDING.iter().position(|n| *n == offset)?;
+        self.neighbours[index].as_ref()
+    }
+
+    /// Returns the block at a position relative to the snapshot chunk.
+    ///
+    /// Positions one block away on the X and Z axes are looked up in the neighbours.
+    pub fn get_block(&self, pos: Vec3<i32>) -> Option<Block> {
+        let size = Chunk::SIZE.map(|x| x as i32);
+        let offset = Vec2::new(pos.x.div_euclid(size.x), pos.z.div_euclid(size.z));
+        let local = Vec3::new(pos.x.rem_euclid(size.x), pos.y, pos.z.rem_euclid(size.z));
+        if offset == Vec2::zero() {
+            self.chunk.get_block(local)
+        } else {
+            self.neighbour(offset)?.get_block(local)
+        }
+    }
+
+    /// Returns the light at a position relative to the snapshot chunk.
+    ///
+    /// Above the world and in missing chunks it is full sky light.
+    /// Returns `None` below the world.
+    pub fn light(&self, pos: Vec3<i32>) -> Option<Light> {
+        if pos.y < 0 {
+            return None;
+        }
+        if pos.y >= Chunk::SIZE.y as i32 {
+            return Some(Light::new(Light::MAX_LEVEL, 0));
+        }
+        let size = Chunk::SIZE.map(|x| x as i32);
+        let offset = Vec2::new(pos.x.div_euclid(size.x), pos.z.div_euclid(size.z));
+        let local = Vec3::new(pos.x.rem_euclid(size.x), pos.y, pos.z.rem_euclid(size.z));
+        if offset == Vec2::zero() {
+            return self.chunk.light(local);
+        }
+        match self.neighbour(offset) {
+            Some(border) => border.light(local),
+            None => Some(Light::new(Light::MAX_LEVEL, 0)),
+        }
+    }
+
+    fn is_opaque(&self, context: &MeshContext, pos: Vec3<i32>) -> bool {
+        self.get_block(pos)
+            .is_some_and(|block| context.is_opaque(block))
+    }
+}
+
+/// The vertices of a chunk, split by render layer.
+#[derive(Default)]
+pub struct ChunkMesh {
+    pub opaque: Vec<TerrainVertex>,
+    pub cutout: Vec<TerrainVertex>,
+    /// Sorted with [`sort_back_to_front`] before drawing.
+    pub translucent: Vec<TerrainVertex>,
+    /// The tint of each column of the chunk, indexed by [`Chunk::column_index`].
+    /// The shader looks it up for faces with a tint.
+    pub tints: Vec<[f32; 4]>,
+}
+
+impl ChunkMesh {
+    pub fn layer(&self, layer: RenderLayer) -> &[TerrainVertex] {
+        match layer {
+            RenderLayer::Opaque => &self.opaque,
+            RenderLayer::Cutout => &self.cutout,
+            RenderLayer::Translucent => &self.translucent,
+        }
+    }
+
+    fn layer_mut(&mut self, layer: RenderLayer) -> &mut Vec<TerrainVertex> {
+        match layer {
+            RenderLayer::Opaque => &mut self.opaque,
+            RenderLayer::Cutout => &mut self.cutout,
+            RenderLayer::Translucent => &mut self.translucent,
+        }
+    }
+}
+
+/// Sorts the quads of a mesh from the furthest to the closest to `eye`, so that blending
+/// composes them in the right order. `eye` is relative to the origin of the chunk.
+pub fn sort_back_to_front(vertices: &mut [TerrainVertex], eye: Vec3<f32>) {
+    let mut quads = vertices
+        .chunks_exact(4)
+        .map(|quad| {
+            let center = quad.iter().fold(Vec3::zero(), |sum, vertex| {
+                sum + vertex.position().map(|x| x as f32)
+            }) / 4.0;
+            (
+                center.distance_squared(eye),
+                [quad[0], quad[1], quad[2], quad[3]],
+            )
+        })
+        .collect::<Vec<_>>();
+    quads.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    for (index, (_, quad)) in quads.into_iter().enumerate() {
+        vertices[index * 4..index * 4 + 4].copy_from_slice(&quad);
+    }
+}
+
+/// What a visible block face looks like. Greedy meshing only merges equal faces.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Face {
+    texture: u16,
+    layer: RenderLayer,
+    /// The ambient occlusion of each corner, in the order of [`face_corners`].
+    /// 0 is the darkest and 3 is not occluded at all.
+    ao: [u8; 4],
+    /// The light of each corner, in the same order.
+    light: [Light; 4],
+    /// Whether the face is colored by the biome.
+    tinted: bool,
+}
+
+impl Face {
+    /// Merging faces whose corners differ would stretch the shading across the whole quad.
+    fn is_uniform(&self) -> bool {
+        self.ao.iter().all(|ao| *ao == self.ao[0])
+            && self.light.iter().all(|light| *light == self.light[0])
+    }
+}
+
+pub fn create_chunk_mesh(snapshot: &ChunkSnapshot, context: &MeshContext) -> ChunkMesh {
+    let mut mesh = if context.greedy {
+        create_greedy_mesh(snapshot, context)
+    } else {
+        create_naive_mesh(snapshot, context)
+    };
+    push_block_models(&mut mesh, snapshot, context);
+    mesh.tints = snapshot
+        .chunk
+        .biomes()
+        .iter()
+        .map(|biome| {
+            let [r, g, b] = biome.grass_tint();
+            [r, g, b, 1.0]
+        })
+        .collect();
+    mesh
+}
+
+/// Emits the quads of every block that is not a full cube. They are never merged.
+///
+/// Fluids are boxes filled up to their level, or up to the top when the same fluid is above them.
+fn push_block_models(mesh: &mut ChunkMesh, snapshot: &ChunkSnapshot, context: &MeshContext) {
+    for pos in snapshot.chunk.iter_non_air() {
+        let Some(voxel) = snapshot.chunk.get_block(pos) else {
+            continue;
+        };
+        let Some(block) = context.registry.get(voxel.id) else {
+            continue;
+        };
+        let fluid_box;
+        let model = match block.fluid_level(voxel.state) {
+            Some(level) => {
+                let covered = snapshot
+                    .get_block(pos + Vec3::unit_y())
+                    .is_some_and(|above| above.id == voxel.id);
+                fluid_box = fluid_model(fill_height(level, block.max_fluid_level(), covered));
+                &fluid_box
+            },
+            None if block.model == BlockModel::Cube => continue,
+            None => &block.model,
+        };
+        let (x, y) = block.rotation(voxel.state);
+        for quad in model.quads(x, y) {
+            if let Some(side) = quad.cull {
+                // Like glass, a body of fluid doesn't show the walls between its blocks
+                let same_fluid = block.fluid.is_some()
+                    && snapshot
+                        .get_block(pos + side.vec())
+                        .is_some_and(|neighbour| neighbour.id == voxel.id);
+                if same_fluid || is_covered(snapshot, context, pos, side, quad.rect()) {
+                    continue;
+                }
+            }
+            let texture = match quad
+                .texture
+                .or_else(|| block.face_texture(voxel.state, quad.face))
+            {
+                Some(name) => context.texture_id(name),
+                None => {
+                    log::error!(
+                        "Block {} has no texture for {:?} face",
+                        block.name,
+                        quad.face
+                    );
+                    0
+                },
+            };
+            // Quads on the side of the block are lit by the block in front, like cube faces.
+            // The others are inside the block, which lets light through.
+            let light = snapshot
+                .light(pos + quad.cull.map_or(Vec3::zero(), Direction::vec))
+                .unwrap_or(Light::DARK);
+            push_model_quad(
+                mesh.layer_mut(block.render_layer),
+                pos,
+                &quad,
+                texture,
+                light,
+                block.tint.applies_to(quad.face),
+            );
+        }
+    }
+}
+
+fn push_model_quad(
+    vertices: &mut Vec<TerrainVertex>,
+    pos: Vec3<i32>,
+    quad: &ModelQuad,
+    texture: u16,
+    light: Light,
+    tinted: bool,
+) {
+    let origin = pos.map(|x| x as u32 * MODEL_SIZE as u32);
+    for (corner, uv) in quad.corners.iter().zip(quad.uvs) {
+        let vertex = TerrainVertex::model(
+            origin + corner.map(u32::from),
+            uv,
+            texture,
+            quad.face,
+            light,
+        );
+        vertices.push(vertex.with_tint(tinted));
+    }
+}
+
+/// Whether the side of the block at `pos` is hidden by its neighbour on that side,
+/// for the part of the side inside `rect` in model units.
+///
+/// Opaque cubes cover a whole side. Other opaque blocks cover what their own quads
+/// lying on the touching side cover.
+fn is_covered(
+    snapshot: &ChunkSnapshot,
+    context: &MeshContext,
+    pos: Vec3<i32>,
+    side: Direction,
+    rect: (Vec2<u8>, Vec2<u8>),
+) -> bool {
+    let Some(neighbour) = snapshot.get_block(pos + side.vec()) else {
+        return false;
+    };
+    if context.is_opaque(neighbour) {
+        return true;
+    }
+    let Some(block) = context.registry.get(neighbour.id) else {
+        return false;
+    };
+    if neighbour.is_air() || block.transparent || block.render_layer != RenderLayer::Opaque {
+        return false;
+    }
+    let (x, y) = block.rotation(neighbour.state);
+    block.model.quads(x, y).iter().any(|quad| {
+        let (min, max) = quad.rect();
+        quad.cull == Some(side.opposite())
+            && min.x <= rect.0.x
+            && min.y <= rect.0.y
+            && max.x >= rect.1.x
+            && max.y >= rect.1.y
+    })
+}
+
+/// Emits one quad for every visible block face.
+fn create_naive_mesh(snapshot: &ChunkSnapshot, context: &MeshContext) -> ChunkMesh {
+    let chunk = &snapshot.chunk;
+    let mut mesh = ChunkMesh::default();
+
+    // Sections made entirely of air have nothing to mesh, so they are skipped.
+    for pos in chunk.iter_non_air() {
+        if is_uniform_section_interior(context, chunk, pos) {
+            // Every neighbour of this block is the same opaque block,
+            // so none of its faces can be visible.
+            continue;
+        }
+        for face in Direction::ALL {
+            if let Some(visible) = visible_face(snapshot, context, pos, face) {
+                push_quad(
+                    mesh.layer_mut(visible.layer),
+                    pos.map(|x| x as u32),
+                    Vec3::one(),
+                    face,
+                    visible,
                 );
-                // Check if the adjacent block is air or not in the map
-                return match neighbor_chunk.get(neighbor_block_in_border) {
-                    Some(id) => id.is_air(),
-                    None => true,
-                };
             }
-            // The adjacent block is within the bounds of this chunk
-            // render only if the adjacent block is not there e.g air or not in the map
-            match chunk.get(adjacent_pos) {
-                Some(id) => id.is_air(),
-                None => true,
+        }
+    }
+    mesh
+}
+
+/// Merges coplanar faces sharing a texture, ambient occlusion and light into larger quads.
+///
+/// Each section is meshed on its own, so quads never cross a section border.
+/// The shader repeats the texture once per block across a merged quad.
+fn create_greedy_mesh(snapshot: &ChunkSnapshot, context: &MeshContext) -> ChunkMesh {
+    let size = ChunkSection::SIZE.map(|x| x as i32);
+    let mut mesh = ChunkMesh::default();
+
+    for (index, section) in snapshot.chunk.sections().iter().enumerate() {
+        if section.is_air() {
+            continue;
+        }
+        // Only the outer faces of a section made of a single opaque block can be visible
+        let uniform = section
+            .uniform()
+            .is_some_and(|block| context.is_opaque(block));
+        let section_y = index as i32 * size.y;
+
+        for face in Direction::ALL {
+            let (axis, u_axis, v_axis) = face_axes(face);
+            let (width, height) = (size[u_axis], size[v_axis]);
+            let mut mask = vec![None; (width * height) as usize];
+            let mask_index = |u: i32, v: i32| (v * width + u) as usize;
+            let local_pos = |depth: i32, u: i32, v: i32| {
+                let mut pos = Vec3::zero();
+                pos[axis] = depth;
+                pos[u_axis] = u;
+                pos[v_axis] = v;
+                pos.y += section_y;
+                pos
+            };
+            // Inside a uniform section only the outermost layer can have visible faces
+            let outer_layer = if face.vec()[axis] > 0 {
+                size[axis] - 1
+            } else {
+                0
+            };
+
+            for depth in 0..size[axis] {
+                if uniform && depth != outer_layer {
+                    continue;
+                }
+                for v in 0..height {
+                    for u in 0..width {
+                        let pos = local_pos(depth, u, v);
+                        mask[mask_index(u, v)] = visible_face(snapshot, context, pos, face);
+                    }
+                }
+
+                for v in 0..height {
+                    let mut u = 0;
+                    while u < width {
+                        let Some(visible) = mask[mask_index(u, v)] else {
+                            u += 1;
+                            continue;
+                        };
+                        let mergeable = visible.is_uniform();
+                        // Grow the quad along u first, then along v while whole rows match
+                        let mut quad_width = 1;
+                        while mergeable
+                            && u + quad_width < width
+                            && mask[mask_index(u + quad_width, v)] == Some(visible)
+                        {
+                            quad_width += 1;
+                        }
+                        let mut quad_height = 1;
+                        while mergeable
+                            && v + quad_height < height
+                            && (0..quad_width).all(|du| {
+                                mask[mask_index(u + du, v + quad_height)] == Some(visible)
+                            })
+                        {
+                            quad_height += 1;
+                        }
+                        for dv in 0..quad_height {
+                            for du in 0..quad_width {
+                                mask[mask_index(u + du, v + dv)] = None;
+                            }
+                        }
+
+                        let mut scale = Vec3::one();
+                        scale[u_axis] = quad_width as u32;
+                        scale[v_axis] = quad_height as u32;
+                        let origin = local_pos(depth, u, v).map(|x| x as u32);
+                        push_quad(mesh.layer_mut(visible.layer), origin, scale, face, visible);
+                        u += quad_width;
+                    }
+                }
+            }
+        }
+    }
+    mesh
+}
+
+/// Returns the axis a face points along and the two axes spanning its plane.
+fn face_axes(face: Direction) -> (usize, usize, usize) {
+    match face {
+        Direction::East | Direction::West => (0, 2, 1),
+        Direction::Up | Direction::Down => (1, 0, 2),
+        Direction::North | Direction::South => (2, 0, 1),
+    }
+}
+
+/// Pushes a quad starting at `origin` and stretched by `scale` along the plane of the face.
+fn push_quad(
+    vertices: &mut Vec<TerrainVertex>,
+    origin: Vec3<u32>,
+    scale: Vec3<u32>,
+    direction: Direction,
+    face: Face,
+) {
+    let corners = face_corners(direction);
+    // The quad is split along the diagonal from the first to the third vertex. Splitting it
+    // along the brighter diagonal instead keeps the shading of a dark corner symmetric.
+    let ao = face.ao;
+    let order = if ao[0] + ao[2] < ao[1] + ao[3] {
+        [1, 2, 3, 0]
+    } else {
+        [0, 1, 2, 3]
+    };
+    for i in order {
+        let vertex = TerrainVertex::new(
+            origin + corners[i].map(u32::from) * scale,
+            face.texture,
+            direction,
+            ao[i],
+            face.light[i],
+        );
+        vertices.push(vertex.with_tint(face.tinted));
+    }
+}
+
+/// Returns what a block face looks like, or `None` if the face can't be seen.
+fn visible_face(
+    snapshot: &ChunkSnapshot,
+    context: &MeshContext,
+    pos: Vec3<i32>,
+    face: Direction,
+) -> Option<Face> {
+    let voxel = snapshot.chunk.get_block(pos)?;
+    if voxel.is_air() {
+        return None;
+    }
+    let Some(block) = context.registry.get(voxel.id) else {
+        log::error!("Block with id: {:?} not found", voxel.id);
+        return None;
+    };
+    // Other models and fluids are meshed by `push_block_models`
+    if block.model != BlockModel::Cube || block.fluid.is_some() {
+        return None;
+    }
+    if !is_face_visible(snapshot, context, voxel, block.render_layer, pos, face) {
+        return None;
+    }
+    // The block state decides which texture ends up on each face
+    let texture = match block.face_texture(voxel.state, face) {
+        Some(name) => context.texture_id(name),
+        None => {
+            log::error!("Block {} has no texture for {:?} face", block.name, face);
+            0
+        },
+    };
+    Some(Face {
+        texture,
+        layer: block.render_layer,
+        ao: face_ao(snapshot, context, pos, face),
+        light: face_light(snapshot, context, pos, face),
+        tinted: block.tint.applies_to(face),
+    })
+}
+
+/// Faces next to transparent blocks, unloaded chunks or the top and bottom of the world
+/// are visible.
+///
+/// Faces between two blocks of the same translucent type are skipped,
+/// otherwise glass and water would show every inner wall. So are faces hidden
+/// behind a side of a block model that covers the whole face, like the bottom of a slab.
+fn is_face_visible(
+    snapshot: &ChunkSnapshot,
+    context: &MeshContext,
+    block: Block,
+    layer: RenderLayer,
+    pos: Vec3<i32>,
+    face: Direction,
+) -> bool {
+    let Some(neighbour) = snapshot.get_block(pos + face.vec()) else {
+        return true;
+    };
+    let whole = (Vec2::zero(), Vec2::broadcast(MODEL_SIZE));
+    if is_covered(snapshot, context, pos, face, whole) {
+        return false;
+    }
+    !(layer == RenderLayer::Translucent && neighbour.id == block.id)
+}
+
+/// Computes the ambient occlusion of each corner of a block face.
+///
+/// Each corner looks at the two blocks sharing an edge with it and the block
+/// sharing only the corner, all in the layer in front of the face.
+fn face_ao(
+    snapshot: &ChunkSnapshot,
+    context: &MeshContext,
+    pos: Vec3<i32>,
+    face: Direction,
+) -> [u8; 4] {
+    let (_, u_axis, v_axis) = face_axes(face);
+    let front = pos + face.vec();
+    face_corners(face).map(|corner| {
+        let mut side1 = Vec3::zero();
+        side1[u_axis] = corner[u_axis] as i32 * 2 - 1;
+        let mut side2 = Vec3::zero();
+        side2[v_axis] = corner[v_axis] as i32 * 2 - 1;
+        vertex_ao(
+            snapshot.is_opaque(context, front + side1),
+            snapshot.is_opaque(context, front + side2),
+            snapshot.is_opaque(context, front + side1 + side2),
+        )
+    })
+}
+
+/// Computes the light of each corner of a block face.
+///
+/// Each corner averages the light of the same blocks as [`face_ao`], skipping the opaque
+/// ones, so the light fades smoothly across faces instead of changing block by block.
+fn face_light(
+    snapshot: &ChunkSnapshot,
+    context: &MeshContext,
+    pos: Vec3<i32>,
+    face: Direction,
+) -> [Light; 4] {
+    let (_, u_axis, v_axis) = face_axes(face);
+    let front = pos + face.vec();
+    face_corners(face).map(|corner| {
+        let mut side1 = Vec3::zero();
+        side1[u_axis] = corner[u_axis] as i32 * 2 - 1;
+        let mut side2 = Vec3::zero();
+        side2[v_axis] = corner[v_axis] as i32 * 2 - 1;
+        let open1 = !snapshot.is_opaque(context, front + side1);
+        let open2 = !snapshot.is_opaque(context, front + side2);
+        // Light can't reach the corner block through two opaque sides
+        let open_corner = (open1 || open2) && !snapshot.is_opaque(context, front + side1 + side2);
+
+        let samples = [
+            (front, true),
+            (front + side1, open1),
+            (front + side2, open2),
+            (front + side1 + side2, open_corner),
+        ];
+        let (mut sky, mut block, mut count) = (0, 0, 0);
+        for (pos, open) in samples {
+            if let Some(light) = snapshot.light(pos).filter(|_| open) {
+                sky += light.sky() as u32;
+                block += light.block() as u32;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return Light::DARK;
+        }
+        let average = |total: u32| ((total + count / 2) / count) as u8;
+        Light::new(average(sky), average(block))
+    })
+}
+
+fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        // The corner block can't be seen, it is fully occluded either way
+        return 0;
+    }
+    3 - (side1 as u8 + side2 as u8 + corner as u8)
+}
+
+/// Whether `pos` is surrounded by blocks of its own uniform section, made of an opaque block.
+fn is_uniform_section_interior(context: &MeshContext, chunk: &Chunk, pos: Vec3<i32>) -> bool {
+    let size = ChunkSection::SIZE.map(|x| x as i32);
+    let section = chunk.section((pos.y / size.y) as usize);
+    if !section
+        .and_then(ChunkSection::uniform)
+        .is_some_and(|block| context.is_opaque(block))
+    {
+        return false;
+    }
+    let local = Vec3::new(pos.x, pos.y % size.y, pos.z);
+    local.x > 0
+        && local.y > 0
+        && local.z > 0
+        && local.x < size.x - 1
+        && local.y < size.y - 1
+        && local.z < size.z - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use common::dir::Direction;
+    use common::{
+        biome::Biome,
+        block::{Block, BlockDescriptor, BlockRegistry},
+        chunk::Chunk,
+        light::Light,
+    };
+    use vek::{Vec2, Vec3};
+
+    use super::{
+        create_chunk_mesh, face_ao, face_light, sort_back_to_front, ChunkBorder, ChunkSnapshot,
+        MeshContext,
+    };
+
+    fn context(greedy: bool) -> MeshContext {
+        let stone: BlockDescriptor =
+            toml::from_str("name = \"Stone\"\n[textures]\nall = \"stone\"").unwrap();
+        let glass: BlockDescriptor = toml::from_str(
+            "name = \"Glass\"\nrender_layer = \"translucent\"\n[textures]\nall = \"glass\"",
+        )
+        .unwrap();
+        let slab: BlockDescriptor = toml::from_str(
+            r#"
+            name = "Slab"
+            [textures]
+            all = "stone"
+            [model]
+            type = "boxes"
+            [[model.boxes]]
+            from = [0, 0, 0]
+            to = [16, 8, 16]
+            "#,
+        )
+        .unwrap();
+        let flower: BlockDescriptor = toml::from_str(
+            r#"
+            name = "Flower"
+            solid = false
+            render_layer = "cutout"
+            model = { type = "cross" }
+            [textures]
+            all = "flower"
+            "#,
+        )
+        .unwrap();
+        let water: BlockDescriptor = toml::from_str(
+            r#"
+            name = "Water"
+            solid = false
+            render_layer = "translucent"
+            [fluid]
+            [states]
+            level = ["0", "1", "2", "3"]
+            [textures]
+            all = "water"
+            "#,
+        )
+        .unwrap();
+        let grass: BlockDescriptor =
+            toml::from_str("name = \"Grass\"\ntint = \"top\"\n[textures]\nall = \"grass\"")
+                .unwrap();
+        MeshContext {
+            registry: BlockRegistry::from_descriptors(vec![
+                stone, glass, slab, flower, water, grass,
+            ])
+            .unwrap(),
+            tiles: HashMap::from([
+                ("stone".to_string(), 0),
+                ("glass".to_string(), 1),
+                ("flower".to_string(), 2),
+                ("water".to_string(), 3),
+                ("grass".to_string(), 4),
+            ]),
+            greedy,
+        }
+    }
+
+    #[test]
+    pub fn greedy_mesh_merges_flat_terrain() {
+        let registry = context(true).registry;
+        let stone = registry.id("stone").unwrap();
+        let mut chunk = Chunk::flat(Block::AIR);
+        for pos in chunk.iter().collect::<Vec<_>>() {
+            if pos.y < 64 {
+                chunk.set(pos, stone);
+            } else {
+                chunk.set_light(pos, Light::new(15, 0));
             }
+        }
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk,
+            neighbours: Default::default(),
         };
 
-        let id = match chunk.get(pos) {
-            Some(id) => id,
-            None => continue,
+        let naive = create_chunk_mesh(&snapshot, &context(false));
+        let greedy = create_chunk_mesh(&snapshot, &context(true));
+        // Top and bottom are one quad each, every side is one quad per section
+        assert_eq!(greedy.opaque.len(), (2 + 4 * 4) * 4);
+        assert!(greedy.opaque.len() * 10 <= naive.opaque.len());
+    }
+
+    #[test]
+    pub fn only_tinted_faces_use_the_biome_tint() {
+        let context = context(true);
+        let grass = context.registry.id("grass").unwrap();
+        let mut chunk = Chunk::flat(Block::AIR);
+        chunk.set(Vec3::new(3, 10, 3), grass);
+        chunk.set_biome(Vec2::new(3, 3), Biome::Desert);
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk,
+            neighbours: Default::default(),
         };
 
-        if id.is_air() {
-            continue;
+        let mesh = create_chunk_mesh(&snapshot, &context);
+        assert_eq!(mesh.opaque.len(), 6 * 4);
+        for vertex in &mesh.opaque {
+            let up = (vertex.data >> 10) & 0x7 == 4;
+            assert_eq!(vertex.light >> 31 == 1, up);
         }
+        let [r, g, b] = Biome::Desert.grass_tint();
+        assert_eq!(mesh.tints.len(), Chunk::COLUMNS);
+        assert_eq!(mesh.tints[3 + 3 * 16], [r, g, b, 1.0]);
+    }
 
-        let Some(block) = block_map.get(id) else {
-            log::error!("Block with id: {:?} not found", id);
-            continue;
+    #[test]
+    pub fn ambient_occlusion_darkens_corners() {
+        let context = context(true);
+        let stone = context.registry.id("stone").unwrap();
+        let mut chunk = Chunk::flat(Block::AIR);
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set(Vec3::new(x, 10, z), stone);
+            }
+        }
+        // A wall along the west side of (8, 10, 8), one block above the ground,
+        // and a single block on the ground touching its north east corner
+        chunk.set(Vec3::new(7, 11, 7), stone);
+        chunk.set(Vec3::new(7, 11, 8), stone);
+        chunk.set(Vec3::new(7, 11, 9), stone);
+        chunk.set(Vec3::new(9, 11, 9), stone);
+        let mut east = Chunk::flat(Block::AIR);
+        east.set(Vec3::new(0, 11, 4), stone);
+        let mut neighbours: [Option<ChunkBorder>; 8] = Default::default();
+        neighbours[1] = Some(ChunkBorder::new(&east, Vec2::new(1, 0)));
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk,
+            neighbours,
+        };
+
+        // Top face corners: (0, 0), (1, 0), (1, 1), (0, 1) on the X and Z axes
+        let ao = face_ao(&snapshot, &context, Vec3::new(8, 10, 8), Direction::Up);
+        assert_eq!(ao, [1, 3, 2, 1]);
+        // Nothing around this one
+        let ao = face_ao(&snapshot, &context, Vec3::new(12, 10, 12), Direction::Up);
+        assert_eq!(ao, [3, 3, 3, 3]);
+        // The block in the east chunk occludes the border
+        let ao = face_ao(&snapshot, &context, Vec3::new(15, 10, 4), Direction::Up);
+        assert_eq!(ao, [3, 2, 2, 3]);
+    }
+
+    #[test]
+    pub fn borders_only_copy_the_touching_columns() {
+        let context = context(true);
+        let stone = context.registry.id("stone").unwrap();
+        let mut north_west = Chunk::flat(Block::AIR);
+        north_west.set(Vec3::new(15, 5, 0), stone);
+        north_west.set(Vec3::new(14, 5, 0), stone);
+        north_west.set_light(Vec3::new(15, 6, 0), Light::new(0, 9));
+        let border = ChunkBorder::new(&north_west, Vec2::new(-1, 1));
+        assert_eq!(border.get_block(Vec3::new(14, 5, 0)), None);
+
+        let mut neighbours: [Option<ChunkBorder>; 8] = Default::default();
+        neighbours[7] = Some(border);
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk: Chunk::flat(Block::AIR),
+            neighbours,
+        };
+        assert_eq!(
+            snapshot.get_block(Vec3::new(-1, 5, 16)),
+            Some(Block::from(stone))
+        );
+        assert_eq!(snapshot.light(Vec3::new(-1, 6, 16)), Some(Light::new(0, 9)));
+    }
+
+    #[test]
+    pub fn light_is_averaged_around_corners() {
+        let context = context(true);
+        let stone = context.registry.id("stone").unwrap();
+        let mut chunk = Chunk::flat(Block::AIR);
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set(Vec3::new(x, 10, z), stone);
+            }
+        }
+        // A wall on the west side of (8, 10, 8), which only its front block lights
+        chunk.set(Vec3::new(7, 11, 8), stone);
+        chunk.set_light(Vec3::new(8, 11, 8), Light::new(0, 12));
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk,
+            neighbours: Default::default(),
+        };
+
+        let light = face_light(&snapshot, &context, Vec3::new(8, 10, 8), Direction::Up);
+        // The corners on the wall side don't average the wall in
+        assert_eq!(
+            light,
+            [
+                Light::new(0, 4),
+                Light::new(0, 3),
+                Light::new(0, 3),
+                Light::new(0, 4)
+            ]
+        );
+        // Missing neighbours and the space above the world are lit by the sky
+        assert_eq!(
+            snapshot.light(Vec3::new(-1, 11, 8)),
+            Some(Light::new(15, 0))
+        );
+        assert_eq!(
+            snapshot.light(Vec3::new(8, 256, 8)),
+            Some(Light::new(15, 0))
+        );
+    }
+
+    #[test]
+    pub fn faces_next_to_glass_are_visible() {
+        let context = context(false);
+        let stone = context.registry.id("stone").unwrap();
+        let glass = context.registry.id("glass").unwrap();
+        let mut chunk = Chunk::flat(Block::AIR);
+        chunk.set(Vec3::new(4, 10, 4), stone);
+        chunk.set(Vec3::new(5, 10, 4), glass);
+        chunk.set(Vec3::new(6, 10, 4), glass);
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk,
+            neighbours: Default::default(),
+        };
+
+        let mesh = create_chunk_mesh(&snapshot, &context);
+        // The stone face touching the glass is kept
+        assert_eq!(mesh.opaque.len(), 6 * 4);
+        // The glass hides its faces against the stone and between the two glass blocks
+        assert_eq!(mesh.translucent.len(), 9 * 4);
+        assert!(mesh.cutout.is_empty());
+    }
+
+    #[test]
+    pub fn block_models_hide_only_covered_faces() {
+        let context = context(false);
+        let stone = context.registry.id("stone").unwrap();
+        let slab = context.registry.id("slab").unwrap();
+        let flower = context.registry.id("flower").unwrap();
+        let mut chunk = Chunk::flat(Block::AIR);
+        // Two slabs side by side on top of two stone blocks
+        for x in [4, 5] {
+            chunk.set(Vec3::new(x, 10, 4), stone);
+            chunk.set(Vec3::new(x, 11, 4), slab);
+        }
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk: chunk.clone(),
+            neighbours: Default::default(),
+        };
+        let mesh = create_chunk_mesh(&snapshot, &context);
+        // The stone tops are under the slab bottoms and the slabs hide each other's inner side
+        assert_eq!(mesh.opaque.len(), (8 + 8) * 4);
+
+        // A stone block next to a slab only hides the side of the slab
+        chunk.set(Vec3::new(6, 11, 4), stone);
+        chunk.set(Vec3::new(8, 11, 4), flower);
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk,
+            neighbours: Default::default(),
         };
+        let mesh = create_chunk_mesh(&snapshot, &context);
+        assert_eq!(mesh.opaque.len(), (8 + 7 + 6) * 4);
+        // The flower is two quads seen from both sides
+        assert_eq!(mesh.cutout.len(), 4 * 4);
+        // Half of the slab side is at the top of the block
+        let top = mesh
+            .opaque
+            .iter()
+            .filter(|vertex| vertex.light >> 30 == 1)
+            .map(|vertex| (vertex.position().y * 16) + ((vertex.light >> 12) & 0xF))
+            .max();
+        assert_eq!(top, Some(11 * 16 + 8));
+    }
 
-        let (top, side, bottom) = block.textures();
-        let top = block_atlas.get_texture_id(top);
-        let side = block_atlas.get_texture_id(side);
-        let bottom = block_atlas.get_texture_id(bottom);
-
-        // North
-        if render_quad(Direction::North) {
-            let normal = Direction::North.vec();
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_z(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_z(), side, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_z() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_z() + Vec3::unit_x() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-        }
-
-        // South
-        if render_quad(Direction::South) {
-            let normal = Direction::South.vec();
-
-            vertices.push(TerrainVertex::new(origin, side, normal));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_x(), side, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_y(), side, normal));
-        }
-
-        // East
-        if render_quad(Direction::East) {
-            let normal = Direction::East.vec();
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_x(), side, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_z(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_z() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-        }
-
-        // West
-        if render_quad(Direction::West) {
-            let normal = Direction::West.vec();
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_z(), side, normal));
-            vertices.push(TerrainVertex::new(origin, side, normal));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_y(), side, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_z() + Vec3::unit_y(),
-                side,
-                normal,
-            ));
-        }
-        // Bottom
-        if render_quad(Direction::Down) {
-            let normal = Direction::Down.vec();
-            vertices.push(TerrainVertex::new(origin, bottom, normal));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_z(), bottom, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_x() + Vec3::unit_z(),
-                bottom,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_x(), bottom, normal));
-        }
-
-        // Top
-        if render_quad(Direction::Up) {
-            let normal = Direction::Up.vec();
-            vertices.push(TerrainVertex::new(origin + Vec3::unit_y(), top, normal));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_y() + Vec3::unit_x(),
-                top,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_y() + Vec3::unit_x() + Vec3::unit_z(),
-                top,
-                normal,
-            ));
-            vertices.push(TerrainVertex::new(
-                origin + Vec3::unit_y() + Vec3::unit_z(),
-                top,
-                normal,
-            ));
-        }
-    }
-    vertices
+    #[test]
+    pub fn fluids_are_filled_up_to_their_level() {
+        let context = context(true);
+        let water = context.registry.id("water").unwrap();
+        let mut chunk = Chunk::flat(Block::AIR);
+        chunk.set(Vec3::new(4, 10, 4), water);
+        chunk.set(Vec3::new(4, 11, 4), water);
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk,
+            neighbours: Default::default(),
+        };
+        let mesh = create_chunk_mesh(&snapshot, &context);
+        assert!(mesh.opaque.is_empty());
+        // Four sides for each block, the bottom of the lower one and the surface of the upper one
+        assert_eq!(mesh.translucent.len(), 10 * 4);
+        let heights = mesh
+            .translucent
+            .iter()
+            .map(|vertex| (vertex.position().y * 16) + ((vertex.light >> 12) & 0xF))
+            .collect::<Vec<_>>();
+        // The lower block is full and the source on top is a bit lower than a block
+        assert!(heights.contains(&(11 * 16)));
+        assert_eq!(heights.iter().max(), Some(&(11 * 16 + 14)));
+    }
+
+    #[test]
+    pub fn translucent_quads_are_sorted_back_to_front() {
+        let context = context(false);
+        let glass = context.registry.id("glass").unwrap();
+        let mut chunk = Chunk::flat(Block::AIR);
+        for x in [1, 5, 9] {
+            chunk.set(Vec3::new(x, 0, 0), glass);
+        }
+        let snapshot = ChunkSnapshot {
+            pos: Vec2::zero(),
+            chunk,
+            neighbours: Default::default(),
+        };
+        let mut vertices = create_chunk_mesh(&snapshot, &context).translucent;
+
+        let eye = Vec3::new(12.0, 0.5, 0.5);
+        sort_back_to_front(&mut vertices, eye);
+        let distances = vertices
+            .chunks_exact(4)
+            .map(|quad| {
+                let center = quad.iter().fold(Vec3::zero(), |sum, vertex| {
+                    sum + vertex.position().map(|x| x as f32)
+                }) / 4.0;
+                center.distance(eye)
+            })
+            .collect::<Vec<_>>();
+        assert!(distances.windows(2).all(|pair| pair[0] >= pair[1]));
+        // The west face of the furthest block comes first
+        assert_eq!(vertices[0].position().x, 1);
+    }
 }
@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use vek::Vec2;
+
+use crate::mesh::{self, ChunkMesh, ChunkSnapshot, MeshContext};
+
+/// A chunk mesh built by a worker, waiting to be uploaded to the GPU.
+pub struct FinishedMesh {
+    pub pos: Vec2<i32>,
+    pub mesh: ChunkMesh,
+}
+
+struct MeshJob {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Builds chunk meshes on a rayon thread pool.
+///
+/// Only the latest job of each chunk is kept. Submitting a chunk again or cancelling it
+/// skips the older job if it hasn't started yet, and discards its result otherwise.
+pub struct MeshWorkerPool {
+    pool: rayon::ThreadPool,
+    context: Option<Arc<MeshContext>>,
+    sender: Sender<(u64, FinishedMesh)>,
+    // Resources must be `Sync`, which the receiver alone is not
+    receiver: Mutex<Receiver<(u64, FinishedMesh)>>,
+    jobs: HashMap<Vec2<i32>, MeshJob>,
+    next_id: u64,
+}
+
+impl Default for MeshWorkerPool {
+    fn default() -> Self {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .thread_name(|index| format!("mesh-worker-{}", index))
+            .build()
+            .expect("Failed to create the mesh worker pool");
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            pool,
+            context: None,
+            sender,
+            receiver: Mutex::new(receiver),
+            jobs: HashMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl MeshWorkerPool {
+    /// Whether the current context uses greedy meshing, or `None` if it wasn't set yet.
+    pub fn greedy(&self) -> Option<bool> {
+        self.context.as_ref().map(|context| context.greedy)
+    }
+
+    pub fn set_context(&mut self, context: MeshContext) {
+        self.context = Some(Arc::new(context));
+    }
+
+    /// Queues a mesh job for the snapshot, replacing any job for the same chunk.
+    ///
+    /// # Panics
+    /// If the context wasn't set with [`MeshWorkerPool::set_context`].
+    pub fn submit(&mut self, snapshot: ChunkSnapshot) {
+        let context = self
+            .context
+            .clone()
+            .expect("The mesh context must be set before submitting jobs");
+        self.cancel(snapshot.pos);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.jobs.insert(
+            snapshot.pos,
+            MeshJob {
+                id,
+                cancelled: cancelled.clone(),
+            },
+        );
+
+        let sender = self.sender.clone();
+        self.pool.spawn(move || {
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            let mesh = FinishedMesh {
+                pos: snapshot.pos,
+                mesh: mesh::create_chunk_mesh(&snapshot, &context),
+            };
+            // The receiver only goes away when the pool is dropped
+            let _ = sender.send((id, mesh));
+        });
+    }
+
+    /// Cancels the job of a chunk, if it has one.
+    pub fn cancel(&mut self, pos: Vec2<i32>) {
+        if let Some(job) = self.jobs.remove(&pos) {
+            job.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Cancels the jobs of every chunk for which `keep` returns false.
+    pub fn retain(&mut self, mut keep: impl FnMut(Vec2<i32>) -> bool) {
+        self.jobs.retain(|pos, job| {
+            let keep = keep(*pos);
+            if !keep {
+                job.cancelled.store(true, Ordering::Relaxed);
+            }
+            keep
+        });
+    }
+
+    pub fn is_pending(&self, pos: Vec2<i32>) -> bool {
+        self.jobs.contains_key(&pos)
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Returns at most `budget` finished meshes. The rest stay queued for the next call.
+    pub fn finished(&mut self, budget: usize) -> Vec<FinishedMesh> {
+        let receiver = self
+            .receiver
+            .get_mut()
+            .expect("Mesh receiver lock poisoned");
+        let mut meshes = Vec::with_capacity(budget);
+        while meshes.len() < budget {
+            let Ok((id, mesh)) = receiver.try_recv() else {
+                break;
+            };
+            // Results of cancelled or replaced jobs are stale
+            match self.jobs.get(&mesh.pos) {
+                Some(job) if job.id == id => {
+                    self.jobs.remove(&mesh.pos);
+                    meshes.push(mesh);
+                },
+                _ => {},
+            }
+        }
+        meshes
+    }
+}
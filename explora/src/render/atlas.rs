@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use image::{GenericImage, RgbaImage};
 
-use super::texture::Texture;
+use super::{texture::Texture, vertex::MAX_TEXTURES};
 
 pub struct BlockAtlas {
     pub buffer: RgbaImage,
@@ -12,7 +12,18 @@ pub struct BlockAtlas {
 }
 
 impl BlockAtlas {
+    /// Fails if there are more textures than the terrain vertices can address.
     pub fn create(textures: &[String]) -> std::io::Result<Self> {
+        if textures.len() > MAX_TEXTURES {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} block textures found, at most {} are supported",
+                    textures.len(),
+                    MAX_TEXTURES
+                ),
+            ));
+        }
         let mut texture_data = Vec::new();
         let (mut last_width, mut last_height) = (0, 0);
         for path in textures {
@@ -80,3 +91,16 @@ impl BlockAtlas {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn too_many_textures_are_refused() {
+        let textures: Vec<_> = (0..=MAX_TEXTURES)
+            .map(|i| format!("assets/textures/block/{}.png", i))
+            .collect();
+        assert!(BlockAtlas::create(&textures).is_err());
+    }
+}
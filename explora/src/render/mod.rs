@@ -9,9 +9,10 @@ pub mod vertex;
 
 use atlas::BlockAtlas;
 use buffer::Buffer;
-use resources::{EguiContext, TerrainRender};
+use common::{block::RenderLayer, chunk::Chunk};
+use resources::{EguiContext, TerrainRender, TerrainRenderStats};
 use texture::Texture;
-use vek::{Mat4, Vec3};
+use vek::{Aabb, Mat4, Vec2, Vec3};
 
 pub const SYSTEM_STAGE_PRE_RENDER: &str = "pre_render";
 pub const SYSTEM_STAGE_RENDER: &str = "render";
@@ -79,9 +80,21 @@ impl Default for Uniforms {
 
 pub struct Pipelines {
     pub terrain: pipeline::TerrainPipeline,
+    pub terrain_cutout: pipeline::TerrainPipeline,
+    pub terrain_translucent: pipeline::TerrainPipeline,
     pub terrain_wireframe: pipeline::TerrainPipeline,
 }
 
+impl Pipelines {
+    pub fn terrain(&self, layer: RenderLayer) -> &pipeline::TerrainPipeline {
+        match layer {
+            RenderLayer::Opaque => &self.terrain,
+            RenderLayer::Cutout => &self.terrain_cutout,
+            RenderLayer::Translucent => &self.terrain_translucent,
+        }
+    }
+}
+
 pub struct Renderer {
     surface: wgpu::Surface,
     device: wgpu::Device,
@@ -250,33 +263,46 @@ impl Renderer {
         let chunk_pos_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Chunk Pos Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    // The biome tints of the columns
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
 
-        let pipelines = Pipelines {
-            terrain: pipeline::TerrainPipeline::new(
+        let terrain_pipeline = |layer, wireframe| {
+            pipeline::TerrainPipeline::new(
                 &device,
                 &[&common_bind_group_layout, &chunk_pos_bind_group_layout],
                 &shader,
                 &config,
-                false,
-            ),
-            terrain_wireframe: pipeline::TerrainPipeline::new(
-                &device,
-                &[&common_bind_group_layout, &chunk_pos_bind_group_layout],
-                &shader,
-                &config,
-                true,
-            ),
+                layer,
+                wireframe,
+            )
+        };
+        let pipelines = Pipelines {
+            terrain: terrain_pipeline(RenderLayer::Opaque, false),
+            terrain_cutout: terrain_pipeline(RenderLayer::Cutout, false),
+            terrain_translucent: terrain_pipeline(RenderLayer::Translucent, false),
+            terrain_wireframe: terrain_pipeline(RenderLayer::Opaque, true),
         };
 
         let depth_texture = Texture::depth(&device, config.width, config.height);
@@ -360,13 +386,16 @@ impl Renderer {
     pub fn create_terrain_chunk_mesh(
         &mut self,
         chunk_pos: ChunkPos,
-        buf: Buffer<TerrainVertex>,
+        mesh: ChunkMesh,
     ) -> TerrainChunkMesh {
+        for layer in RenderLayer::ALL {
+            self.check_index_buffer::<TerrainVertex>(mesh.layer(layer).len());
+        }
         TerrainChunkMesh::new(
             &self.device,
             &self.chunk_pos_bind_group_layout,
             chunk_pos,
-            buf,
+            mesh,
         )
     }
 
@@ -426,6 +455,10 @@ impl Renderer {
 use apecs::*;
 
 use self::{resources::TerrainChunkMesh, vertex::TerrainVertex};
+use crate::{
+    camera::Camera,
+    mesh::{self, ChunkMesh},
+};
 
 struct RenderTexture {
     surface_tex: wgpu::SurfaceTexture,
@@ -491,18 +524,63 @@ fn pre_render_system(mut system: PreRenderSystem) -> apecs::anyhow::Result<Shoul
 #[derive(CanFetch)]
 struct RenderSystem {
     renderer: Read<Renderer, NoDefault>,
+    camera: Read<Camera>,
     terrain: Write<TerrainRender>,
     texture: Write<Option<RenderTexture>>,
     encoder: Write<Option<CommandEncoder>>,
 }
 
-/// Sets up the main render pass and draws the terrain
+/// The box containing everything a chunk mesh can draw.
+fn chunk_bounds(pos: Vec2<i32>) -> Aabb<f32> {
+    let size = Chunk::SIZE.map(|x| x as f32);
+    let min = Vec3::new(pos.x as f32 * size.x, 0.0, pos.y as f32 * size.z);
+    Aabb {
+        min,
+        max: min + size,
+    }
+}
+
+/// Draws one layer of the given chunks with the pipeline currently set.
+fn draw_chunks<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    chunks: &[(&Vec2<i32>, &'a TerrainChunkMesh)],
+    layer: RenderLayer,
+) {
+    for (_, chunk) in chunks {
+        let Some(buffer) = chunk.buffer(layer) else {
+            continue;
+        };
+        render_pass.set_bind_group(1, &chunk.chunk_pos_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, buffer.slice());
+        render_pass.draw_indexed(0..buffer.len() / 4 * 6, 0, 0..1);
+    }
+}
+
+/// Sets up the main render pass and draws the terrain chunks in view of the camera
 fn render_system(mut system: RenderSystem) -> apecs::anyhow::Result<ShouldContinue> {
     let renderer = &system.renderer;
     // borrow inner option T mutably
     let texture = system.texture.inner_mut().as_mut().unwrap();
     let encoder = &mut system.encoder.inner_mut().as_mut().unwrap().encoder;
 
+    let frustum = system.camera.frustum();
+    let eye = system.camera.pos();
+    let eye_block = eye.map(|x| x.floor() as i32);
+    // Translucent faces are blended in the order they are drawn,
+    // so they are sorted again every time the camera enters another block.
+    for (pos, chunk) in system.terrain.chunks.iter_mut() {
+        let bounds = chunk_bounds(*pos);
+        let Some(buffer) = &chunk.translucent_buffer else {
+            continue;
+        };
+        if chunk.sorted_from == Some(eye_block) || !frustum.intersects_aabb(bounds) {
+            continue;
+        }
+        mesh::sort_back_to_front(&mut chunk.translucent_vertices, eye - bounds.min);
+        buffer.write(&renderer.queue, &chunk.translucent_vertices);
+        chunk.sorted_from = Some(eye_block);
+    }
+
     let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         label: Some("Render Pass"),
         color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -530,24 +608,40 @@ fn render_system(mut system: RenderSystem) -> apecs::anyhow::Result<ShouldContin
         timestamp_writes: None,
     });
 
-    if !system.terrain.chunks.is_empty() {
-        if system.terrain.wireframe {
-            render_pass.set_pipeline(&renderer.pipelines.terrain_wireframe.pipeline);
-        } else {
-            render_pass.set_pipeline(&renderer.pipelines.terrain.pipeline);
-        }
+    let mut visible = system
+        .terrain
+        .chunks
+        .iter()
+        .filter(|(pos, _)| frustum.intersects_aabb(chunk_bounds(**pos)))
+        .collect::<Vec<_>>();
+    let drawn = visible.len();
+    if !visible.is_empty() {
         render_pass.set_bind_group(0, &renderer.core_bind_group, &[]);
         render_pass.set_index_buffer(
             renderer.terrain_index_buffer.slice(),
             wgpu::IndexFormat::Uint32,
         );
-
-        for terrain_data in system.terrain.chunks.values() {
-            render_pass.set_bind_group(1, &terrain_data.chunk_pos_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, terrain_data.vertex_buffer.slice());
-            render_pass.draw_indexed(0..terrain_data.vertex_buffer.len() / 4 * 6, 0, 0..1);
+        for layer in RenderLayer::ALL {
+            if layer == RenderLayer::Translucent {
+                // Blending also needs the furthest chunks to be drawn first
+                let distance = |pos: &Vec2<i32>| chunk_bounds(*pos).center().distance_squared(eye);
+                visible.sort_by(|(a, _), (b, _)| distance(b).total_cmp(&distance(a)));
+            }
+            let pipeline = if system.terrain.wireframe {
+                &renderer.pipelines.terrain_wireframe
+            } else {
+                renderer.pipelines.terrain(layer)
+            };
+            render_pass.set_pipeline(&pipeline.pipeline);
+            draw_chunks(&mut render_pass, &visible, layer);
         }
     }
+    // The pass borrows the chunk meshes until it is dropped
+    drop(render_pass);
+    system.terrain.stats = TerrainRenderStats {
+        drawn_chunks: drawn,
+        culled_chunks: system.terrain.chunks.len() - drawn,
+    };
     ok()
 }
 
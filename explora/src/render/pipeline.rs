@@ -1,3 +1,5 @@
+use common::block::RenderLayer;
+
 use crate::render::{texture, vertex::TerrainVertex, Vertex};
 
 pub struct TerrainPipeline {
@@ -10,8 +12,16 @@ impl TerrainPipeline {
         bind_group_layouts: &[&wgpu::BindGroupLayout],
         shader: &wgpu::ShaderModule,
         config: &wgpu::SurfaceConfiguration,
+        layer: RenderLayer,
         wireframe: bool,
     ) -> Self {
+        // Cutout faces discard their transparent pixels, translucent ones are blended
+        // and don't hide what is drawn after them.
+        let (entry_point, blend, depth_write) = match layer {
+            RenderLayer::Opaque => ("fs_main", wgpu::BlendState::REPLACE, true),
+            RenderLayer::Cutout => ("fs_cutout", wgpu::BlendState::REPLACE, true),
+            RenderLayer::Translucent => ("fs_main", wgpu::BlendState::ALPHA_BLENDING, false),
+        };
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
@@ -29,10 +39,10 @@ impl TerrainPipeline {
             },
             fragment: Some(wgpu::FragmentState {
                 module: shader,
-                entry_point: "fs_main",
+                entry_point,
                 targets: &[Some(wgpu::ColorTargetState {
                     format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend: Some(blend),
                     write_mask: wgpu::ColorWrites::all(),
                 })],
             }),
@@ -51,7 +61,7 @@ impl TerrainPipeline {
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
+                depth_write_enabled: depth_write,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 
-use vek::Vec2;
+use common::{block::RenderLayer, chunk::Chunk};
+use vek::{Vec2, Vec3};
 
-use crate::render::{buffer::Buffer, vertex::TerrainVertex};
+use crate::{
+    mesh::ChunkMesh,
+    render::{buffer::Buffer, vertex::TerrainVertex},
+};
 
 use super::ChunkPos;
 
@@ -10,11 +14,29 @@ use super::ChunkPos;
 pub struct TerrainRender {
     pub chunks: HashMap<Vec2<i32>, TerrainChunkMesh>,
     pub wireframe: bool,
+    /// The chunks drawn during the last frame.
+    pub stats: TerrainRenderStats,
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TerrainRenderStats {
+    pub drawn_chunks: usize,
+    /// Chunks with a mesh that were outside of the camera frustum.
+    pub culled_chunks: usize,
+}
+
+/// The GPU buffers of a chunk, one per render layer. Empty layers have no buffer.
 pub struct TerrainChunkMesh {
-    pub vertex_buffer: Buffer<TerrainVertex>,
+    pub opaque_buffer: Option<Buffer<TerrainVertex>>,
+    pub cutout_buffer: Option<Buffer<TerrainVertex>>,
+    pub translucent_buffer: Option<Buffer<TerrainVertex>>,
+    /// A copy of the translucent vertices, sorted again when the camera moves.
+    pub translucent_vertices: Vec<TerrainVertex>,
+    /// The block the camera was in when the translucent vertices were last sorted.
+    pub sorted_from: Option<Vec3<i32>>,
     pub chunk_pos_buffer: Buffer<ChunkPos>,
+    /// The biome tint of each column, see [`ChunkMesh::tints`].
+    pub tints_buffer: Buffer<[f32; 4]>,
     pub chunk_pos_bind_group: wgpu::BindGroup,
 }
 
@@ -23,29 +45,72 @@ impl TerrainChunkMesh {
         device: &wgpu::Device,
         layout: &wgpu::BindGroupLayout,
         chunk_pos: ChunkPos,
-        vertex_buffer: Buffer<TerrainVertex>,
+        mesh: ChunkMesh,
     ) -> Self {
+        let buffer = |vertices: &[TerrainVertex], usage| {
+            (!vertices.is_empty()).then(|| Buffer::new(device, usage, vertices))
+        };
+        let opaque_buffer = buffer(&mesh.opaque, wgpu::BufferUsages::VERTEX);
+        let cutout_buffer = buffer(&mesh.cutout, wgpu::BufferUsages::VERTEX);
+        let translucent_buffer = buffer(
+            &mesh.translucent,
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        );
+
         let chunk_pos_buffer = Buffer::new(
             device,
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             &[chunk_pos],
         );
 
+        // The shader reads a tint for every column, even if the mesh has none
+        let mut tints = mesh.tints;
+        tints.resize(Chunk::COLUMNS, [1.0; 4]);
+        let tints_buffer = Buffer::new(device, wgpu::BufferUsages::UNIFORM, &tints);
+
         let chunk_pos_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Chunk Pos Bind Group"),
             layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: chunk_pos_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: chunk_pos_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: tints_buffer.as_entire_binding(),
+                },
+            ],
         });
 
         Self {
-            vertex_buffer,
+            opaque_buffer,
+            cutout_buffer,
+            translucent_buffer,
+            translucent_vertices: mesh.translucent,
+            sorted_from: None,
             chunk_pos_buffer,
+            tints_buffer,
             chunk_pos_bind_group,
         }
     }
+
+    pub fn buffer(&self, layer: RenderLayer) -> Option<&Buffer<TerrainVertex>> {
+        match layer {
+            RenderLayer::Opaque => self.opaque_buffer.as_ref(),
+            RenderLayer::Cutout => self.cutout_buffer.as_ref(),
+            RenderLayer::Translucent => self.translucent_buffer.as_ref(),
+        }
+    }
+
+    /// The number of vertices in every layer.
+    pub fn vertex_count(&self) -> u32 {
+        RenderLayer::ALL
+            .into_iter()
+            .filter_map(|layer| self.buffer(layer))
+            .map(Buffer::len)
+            .sum()
+    }
 }
 
 #[derive(Debug, Clone, Default)]
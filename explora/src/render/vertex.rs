@@ -1,4 +1,5 @@
-use vek::Vec3;
+use common::{dir::Direction, light::Light};
+use vek::{Vec2, Vec3};
 
 use crate::render::Vertex;
 
@@ -6,32 +7,101 @@ use crate::render::Vertex;
 #[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct TerrainVertex {
     pub data: u32,
+    /// The sky light in bits 4 to 7 and the block light in bits 0 to 3.
+    ///
+    /// Vertices of block models also store their offset inside the block in 1/16
+    /// of a block in bits 8 to 19, their texture coordinates in bits 20 to 29
+    /// and set bit 30.
+    ///
+    /// Bit 31 is set on faces colored by the biome of their column.
+    pub light: u32,
 }
 
+/// The number of textures the vertices can address, their id is packed in 8 bits.
+/// [`BlockAtlas::create`](super::atlas::BlockAtlas::create) refuses to hold more.
+pub const MAX_TEXTURES: usize = 1 << 8;
+
 impl TerrainVertex {
-    pub fn new(position: vek::Vec3<u32>, texture_id: u16, normal: Vec3<i32>) -> Self {
-        // pack normals
-        // since normals are in the range [-1, 1], we can map it to [0, 1] by adding 1 and dividing by 2
-        let normal = normal.map(|x| (x + 1) / 2).map(|x| x as u8);
+    /// `ao` is the ambient occlusion of the vertex, from 0 (darkest) to 3.
+    /// `texture_id` must be lower than [`MAX_TEXTURES`].
+    pub fn new(
+        position: Vec3<u32>,
+        texture_id: u16,
+        face: Direction,
+        ao: u8,
+        light: Light,
+    ) -> Self {
+        debug_assert!(
+            (texture_id as usize) < MAX_TEXTURES,
+            "Texture id {} doesn't fit in 8 bits",
+            texture_id
+        );
+        // The face the vertex belongs to, the shader derives the normal and the
+        // texture coordinates from it. Must match `face_normal` in terrain.wgsl.
+        let face = match face {
+            Direction::North => 0,
+            Direction::South => 1,
+            Direction::East => 2,
+            Direction::West => 3,
+            Direction::Up => 4,
+            Direction::Down => 5,
+        };
         Self {
             data: (position.x << 27)
                 | (position.y << 18)
                 | (position.z << 13)
-                // pack normals. each one is 1 bits, where 1 means positive and 0 means negative
-                | ((normal.x as u32) << 12)
-                | ((normal.y as u32) << 11)
-                | ((normal.z as u32) << 10)
-                | (texture_id as u32),
+                // pack the face in 3 bits
+                | (face << 10)
+                // pack the ambient occlusion in 2 bits
+                | ((ao as u32 & 0x3) << 8)
+                | (texture_id as u32 & 0xFF),
+            light: light.0 as u32,
         }
     }
+
+    /// Creates a vertex of a block model. `position` is relative to the chunk in 1/16 of a block,
+    /// and `uv` goes from 0 to 16 across the texture.
+    pub fn model(
+        position: Vec3<u32>,
+        uv: Vec2<u8>,
+        texture_id: u16,
+        face: Direction,
+        light: Light,
+    ) -> Self {
+        let fine = position.map(|x| x % 16);
+        let mut vertex = Self::new(position.map(|x| x / 16), texture_id, face, 3, light);
+        vertex.light |= (fine.x << 8)
+            | (fine.y << 12)
+            | (fine.z << 16)
+            | ((uv.x as u32 & 0x1F) << 20)
+            | ((uv.y as u32 & 0x1F) << 25)
+            | (1 << 30);
+        vertex
+    }
+
+    /// Colors the vertex with the tint of the biome it stands in.
+    pub fn with_tint(mut self, tinted: bool) -> Self {
+        self.light |= (tinted as u32) << 31;
+        self
+    }
+
+    /// The position of the vertex relative to its chunk, rounded down for vertices of block models.
+    pub fn position(&self) -> Vec3<u32> {
+        Vec3::new(
+            (self.data >> 27) & 0x1F,
+            (self.data >> 18) & 0x1FF,
+            (self.data >> 13) & 0x1F,
+        )
+    }
 }
 
 impl Vertex for TerrainVertex {
     const INDEX_BUFFER: Option<wgpu::IndexFormat> = Some(wgpu::IndexFormat::Uint32);
 
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        const ATTRS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![
+        const ATTRS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
             0 => Uint32,
+            1 => Uint32,
         ];
         wgpu::VertexBufferLayout {
             array_stride: Self::STRIDE,
@@ -70,6 +70,17 @@ pub fn run(event_loop: EventLoop<()>, mut client: Client) {
                                     }
                                 }
                             },
+                            winit::event::WindowEvent::MouseInput { state, button, .. } => {
+                                let input = client.state_mut().resource_mut::<Input>();
+                                match state {
+                                    winit::event::ElementState::Pressed => {
+                                        input.press_button(button);
+                                    },
+                                    winit::event::ElementState::Released => {
+                                        input.release_button(button);
+                                    },
+                                }
+                            },
                             winit::event::WindowEvent::RedrawRequested => {
                                 let clock = client.state_mut().resource_mut::<Clock>();
                                 clock.tick();
@@ -82,7 +93,10 @@ pub fn run(event_loop: EventLoop<()>, mut client: Client) {
                                     .set(raw_input);
 
                                 let clock = client.state().resource::<Clock>();
-                                client.tick(clock.dt());
+                                if let Err(error) = client.tick(clock.dt()) {
+                                    log::error!("The game session ended: {:?}", error);
+                                    elwt.exit();
+                                }
                             },
                             _ => (),
                         }
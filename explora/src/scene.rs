@@ -1,4 +1,4 @@
-use common::{event::Events, resources::DeltaTime, SysResult};
+use common::{event::Events, net::packet::ClientPacket, resources::DeltaTime, SysResult};
 
 use apecs::*;
 
@@ -11,10 +11,18 @@ use vek::Vec3;
 
 use crate::{
     camera::Camera,
+    client::OutgoingPackets,
     input::GameInput,
     window::{Window, WindowEvent},
 };
 
+/// The last position of the camera sent to the server.
+#[derive(Default)]
+pub struct SentPosition {
+    pos: Option<Vec3<f32>>,
+    sequence: u64,
+}
+
 #[derive(CanFetch)]
 pub struct SceneSystem {
     camera: Write<Camera>,
@@ -27,6 +35,8 @@ pub struct SceneSystem {
     input: Read<Input>,
     block_atlas: Read<BlockAtlas, NoDefault>,
     gameplay_settings: Read<GameplaySettings>,
+    packets: Write<OutgoingPackets>,
+    sent_position: Write<SentPosition>,
 }
 
 pub fn scene_update_system(mut scene: SceneSystem) -> SysResult {
@@ -60,6 +70,14 @@ pub fn scene_update_system(mut scene: SceneSystem) -> SysResult {
     let dz = dir.z * scene.gameplay_settings.free_camera_speed * scene.delta.0;
 
     scene.camera.move_by(dx, dy, dz);
+    // The server checks that the rays of our block edits start from there
+    let pos = scene.camera.pos();
+    if scene.sent_position.pos != Some(pos) {
+        scene.sent_position.pos = Some(pos);
+        scene.sent_position.sequence += 1;
+        let sequence = scene.sent_position.sequence;
+        scene.packets.0.push(ClientPacket::Move { sequence, pos });
+    }
     let matrices = scene.camera.compute_matrices();
     let sun_pos = Vec3::new(15.0, 300.0, 15.0);
 
@@ -1,6 +1,8 @@
 pub struct GameplaySettings {
     pub mouse_sensitivity: u32,
     pub free_camera_speed: f32,
+    /// The name of the block placed with right click.
+    pub selected_block: String,
 }
 
 impl Default for GameplaySettings {
@@ -9,6 +11,7 @@ impl Default for GameplaySettings {
             // 100% means default sensitivity
             mouse_sensitivity: 100,
             free_camera_speed: 50.0,
+            selected_block: "Stone".to_string(),
         }
     }
 }
@@ -1,53 +1,232 @@
+use std::collections::HashSet;
+
 use common::{
+    block::BlockRegistry,
+    chunk::Chunk,
+    light::LightEngine,
+    net::packet::ClientPacket,
     resources::{TerrainConfig, TerrainMap},
     SysResult,
 };
 
 use crate::{
     camera::Camera,
+    client::OutgoingPackets,
     render::{atlas::BlockAtlas, resources::TerrainRender, ChunkPos, Renderer},
 };
 
 use apecs::*;
 use vek::Vec2;
 
-use crate::{block::BlockMap, mesh};
+use crate::{
+    mesh::{ChunkSnapshot, MeshContext, SURROUNDING},
+    mesh_worker::MeshWorkerPool,
+};
+
+/// Keeps track of the chunks whose mesh no longer matches the terrain.
+#[derive(Default)]
+pub struct ChunkMeshInvalidation {
+    /// The chunks that were loaded the last time the terrain was checked.
+    loaded: HashSet<Vec2<i32>>,
+    /// The chunks whose mesh must be rebuilt.
+    dirty: HashSet<Vec2<i32>>,
+}
+
+impl ChunkMeshInvalidation {
+    pub fn invalidate(&mut self, pos: Vec2<i32>) {
+        self.dirty.insert(pos);
+    }
+
+    /// Invalidates every loaded chunk.
+    pub fn invalidate_all(&mut self) {
+        self.dirty.extend(self.loaded.iter().copied());
+    }
+
+    /// Invalidates a chunk and the chunks around it, whose border faces and
+    /// ambient occlusion depend on it.
+    pub fn invalidate_with_neighbours(&mut self, pos: Vec2<i32>) {
+        self.invalidate(pos);
+        for offset in SURROUNDING {
+            self.invalidate(pos + offset);
+        }
+    }
+
+    /// Collects the chunks that were loaded, unloaded or edited since the last call.
+    pub fn update(&mut self, terrain: &mut TerrainMap) {
+        let mut changed = Vec::new();
+        self.loaded.retain(|pos| {
+            let loaded = terrain.chunks.contains_key(pos);
+            if !loaded {
+                changed.push(*pos);
+            }
+            loaded
+        });
+        for pos in terrain.chunks.keys() {
+            if self.loaded.insert(*pos) {
+                changed.push(*pos);
+            }
+        }
+        for pos in changed {
+            self.invalidate_with_neighbours(pos);
+        }
+
+        // Edits on a chunk border also change the visible faces of the neighbouring chunk,
+        // and edits on a corner the ambient occlusion of the diagonal one.
+        for (pos, chunk) in terrain.chunks.iter_mut() {
+            for local in chunk.take_dirty() {
+                self.dirty.insert(*pos);
+                self.dirty
+                    .extend(Chunk::touching_neighbours(local).map(|offset| pos + offset));
+            }
+        }
+        // Unloaded chunks have no mesh to rebuild, they are invalidated again once loaded.
+        self.dirty.retain(|pos| terrain.chunks.contains_key(pos));
+    }
+
+    pub fn is_dirty(&self, pos: Vec2<i32>) -> bool {
+        self.dirty.contains(&pos)
+    }
+
+    pub fn dirty(&self) -> impl Iterator<Item = Vec2<i32>> + '_ {
+        self.dirty.iter().copied()
+    }
+
+    pub fn mark_clean(&mut self, pos: Vec2<i32>) {
+        self.dirty.remove(&pos);
+    }
+}
+
+/// The chunks whose light was computed since they were loaded.
+#[derive(Default)]
+pub struct LitChunks(HashSet<Vec2<i32>>);
+
+pub const TERRAIN_LIGHT_SYSTEM: &str = "terrain_light";
+
+/// The maximum number of loaded chunks lit each frame, the others wait for the next frames.
+const LIGHT_BUDGET: usize = 4;
+
+#[derive(CanFetch)]
+pub struct TerrainLightSystem {
+    terrain: Write<TerrainMap>,
+    registry: Read<BlockRegistry, NoDefault>,
+    lit: Write<LitChunks>,
+    invalidation: Write<ChunkMeshInvalidation>,
+}
+
+/// Lights the chunks that were just loaded, up to [`LIGHT_BUDGET`] of them,
+/// and updates the light around edited blocks.
+///
+/// It must run before the meshing system, which clears the edited blocks of each chunk.
+pub fn terrain_light_system(mut system: TerrainLightSystem) -> SysResult {
+    let terrain = system.terrain.inner_mut();
+    system.lit.0.retain(|pos| terrain.chunks.contains_key(pos));
+
+    let new_chunks = terrain
+        .chunks
+        .keys()
+        .filter(|pos| !system.lit.0.contains(*pos))
+        .take(LIGHT_BUDGET)
+        .copied()
+        .collect::<Vec<_>>();
+    // The light of new chunks already accounts for their edits
+    let edits = terrain
+        .chunks
+        .iter()
+        .filter(|(pos, chunk)| chunk.is_dirty() && system.lit.0.contains(*pos))
+        .flat_map(|(pos, chunk)| {
+            chunk
+                .dirty()
+                .iter()
+                .map(move |local| Chunk::local_to_world(*pos, *local))
+        })
+        .collect::<Vec<_>>();
+    if new_chunks.is_empty() && edits.is_empty() {
+        return ok();
+    }
+
+    let mut engine = LightEngine::new(terrain, &system.registry);
+    for pos in new_chunks {
+        engine.light_chunk(pos);
+        system.lit.0.insert(pos);
+    }
+    for pos in edits {
+        engine.update_block(pos);
+    }
+    for pos in engine.into_changed() {
+        system.invalidation.invalidate(pos);
+    }
+    ok()
+}
+
+/// The maximum number of chunk meshes uploaded to the GPU each frame.
+const MESH_UPLOAD_BUDGET: usize = 8;
 
 #[derive(CanFetch)]
 pub struct TerrainSystem {
     renderer: Write<Renderer, NoDefault>,
     terrain_map: Write<TerrainMap>,
-    block_map: Read<BlockMap, NoDefault>,
+    registry: Read<BlockRegistry, NoDefault>,
     atlas: Read<BlockAtlas, NoDefault>,
     terrain_render_data: Write<TerrainRender, NoDefault>,
+    invalidation: Write<ChunkMeshInvalidation>,
+    mesh_workers: Write<MeshWorkerPool>,
+    terrain_config: Read<TerrainConfig>,
+    lit: Read<LitChunks>,
 }
 
 pub const TERRAIN_CHUNK_MESH_SYSTEM: &str = "terrain_chunk_mesh";
 
+/// Sends the outdated chunks to the mesh workers and uploads the meshes they finished.
 pub fn terrain_chunk_mesh(mut system: TerrainSystem) -> SysResult {
-    let blocks = system.block_map.inner();
+    system.invalidation.update(&mut system.terrain_map);
+    let greedy = system.terrain_config.greedy_meshing;
+    if system.mesh_workers.greedy() != Some(greedy) {
+        let context = MeshContext::new(&system.registry, &system.atlas, greedy);
+        system.mesh_workers.set_context(context);
+        system.invalidation.invalidate_all();
+    }
 
     let terrain = system.terrain_map.inner();
+    // Nobody is waiting for the meshes of unloaded chunks
+    system
+        .mesh_workers
+        .retain(|pos| terrain.chunks.contains_key(&pos));
 
-    for (pos, chunk) in terrain.chunks.iter() {
-        let neighbors = [
-            terrain.chunks.get(&(pos + Vec2::new(0, 1))),
-            terrain.chunks.get(&(pos + Vec2::new(1, 0))),
-            terrain.chunks.get(&(pos + Vec2::new(0, -1))),
-            terrain.chunks.get(&(pos + Vec2::new(-1, 0))),
-        ];
-        if neighbors.iter().any(|n| n.is_none()) {
+    let mut submitted = Vec::new();
+    for pos in system.invalidation.dirty() {
+        // Chunks waiting for their light would be meshed in the dark
+        if !system.lit.0.contains(&pos) {
             continue;
         }
-        if system.terrain_render_data.chunks.get(pos).is_none() {
-            let vertices =
-                mesh::create_chunk_mesh(chunk, *pos, &system.terrain_map, blocks, &system.atlas);
-            let buffer = system.renderer.create_vertex_buffer(&vertices);
-            let chunk_pos = ChunkPos::new(pos.x, pos.y);
-            let terrain_mesh = system.renderer.create_terrain_chunk_mesh(chunk_pos, buffer);
-            system.terrain_render_data.chunks.insert(*pos, terrain_mesh);
+        // The first mesh waits for every neighbour so it isn't built again as they
+        // stream in. Existing meshes are always rebuilt, even if a neighbour went away.
+        let has_mesh = system.terrain_render_data.chunks.contains_key(&pos)
+            || system.mesh_workers.is_pending(pos);
+        let has_neighbours = SURROUNDING
+            .into_iter()
+            .all(|offset| terrain.chunks.contains_key(&(pos + offset)));
+        if !has_mesh && !has_neighbours {
+            continue;
+        }
+        if let Some(snapshot) = ChunkSnapshot::new(terrain, pos) {
+            submitted.push(snapshot);
         }
     }
+    for snapshot in submitted {
+        system.invalidation.mark_clean(snapshot.pos);
+        system.mesh_workers.submit(snapshot);
+    }
+
+    for finished in system.mesh_workers.finished(MESH_UPLOAD_BUDGET) {
+        let chunk_pos = ChunkPos::new(finished.pos.x, finished.pos.y);
+        let terrain_mesh = system
+            .renderer
+            .create_terrain_chunk_mesh(chunk_pos, finished.mesh);
+        system
+            .terrain_render_data
+            .chunks
+            .insert(finished.pos, terrain_mesh);
+    }
     ok()
 }
 
@@ -59,6 +238,7 @@ pub struct ChunkLoadSystem {
     camera: Read<Camera>,
     terrain_render: Write<TerrainRender>,
     terrain_config: Read<TerrainConfig>,
+    packets: Write<OutgoingPackets>,
 }
 
 pub fn chunk_load_system(mut system: ChunkLoadSystem) -> apecs::anyhow::Result<ShouldContinue> {
@@ -85,7 +265,10 @@ pub fn chunk_load_system(mut system: ChunkLoadSystem) -> apecs::anyhow::Result<S
 
     for chunk_pos in chunks_to_remove {
         system.terrain.pending_chunks.remove(&chunk_pos);
-        system.terrain.chunks.remove(&chunk_pos);
+        if system.terrain.chunks.remove(&chunk_pos).is_some() {
+            // Stop receiving block updates for this chunk
+            system.packets.0.push(ClientPacket::ChunkUnload(chunk_pos));
+        }
         system.terrain_render.chunks.remove(&chunk_pos);
     }
 
@@ -1,5 +1,8 @@
 use common::{
+    block::BlockRegistry,
+    chunk::Chunk,
     clock::Clock,
+    net::connection::PacketCounters,
     resources::{GameMode, Ping, TerrainConfig, TerrainMap},
     SysResult,
 };
@@ -7,7 +10,7 @@ use common::{
 use apecs::{NoDefault, Read};
 
 use crate::{
-    render::resources::{EguiContext, EguiSettings},
+    render::resources::{EguiContext, EguiSettings, TerrainChunkMesh, TerrainRender},
     settings::GameplaySettings,
 };
 use vek::Vec2;
@@ -53,10 +56,13 @@ pub struct EguiRenderSystem {
     window: Read<Window, NoDefault>,
     globals: Write<Uniforms>,
     ping: Read<Ping>,
+    packets: Read<PacketCounters>,
     mode: Read<GameMode, NoDefault>,
     terrain_config: Write<TerrainConfig>,
     terrain: Read<TerrainMap>,
+    terrain_render: Read<TerrainRender, NoDefault>,
     gameplay: Write<GameplaySettings>,
+    registry: Read<BlockRegistry, NoDefault>,
 }
 
 // This system must run before the render system
@@ -78,6 +84,10 @@ pub fn ui_debug_render_system(mut system: EguiRenderSystem) -> SysResult {
             ui.heading(format!("Game Mode: {:?}", *system.mode));
             ui.separator();
             ui.label(format!("Ping: {:.2}ms", system.ping.0 * 1000.0));
+            ui.label(format!(
+                "Packets: {} received, {} sent",
+                system.packets.received, system.packets.sent
+            ));
             ui.label(format!("FPS: {}", system.clock.fps()));
             ui.label(format!("Facing: {}", orientation));
             let pos = player_camera.pos();
@@ -91,6 +101,16 @@ pub fn ui_debug_render_system(mut system: EguiRenderSystem) -> SysResult {
                 "Chunk Position: (X: {}, Z: {})",
                 chunk_pos.x, chunk_pos.y
             ));
+            let (chunk_pos, local) = Chunk::world_to_local(pos.map(|x| x.floor() as i32));
+            let biome = system
+                .terrain
+                .chunks
+                .get(&chunk_pos)
+                .and_then(|chunk| chunk.biome(Vec2::new(local.x, local.z)));
+            ui.label(format!(
+                "Biome: {}",
+                biome.map_or("Unknown", |biome| biome.name())
+            ));
             ui.separator();
             ui.label(format!(
                 "Graphics backend: {}",
@@ -109,6 +129,20 @@ pub fn ui_debug_render_system(mut system: EguiRenderSystem) -> SysResult {
                 &mut system.gameplay.mouse_sensitivity,
                 1..=200,
             ));
+            ui.label("Selected block");
+            egui::ComboBox::from_id_source("selected_block")
+                .selected_text(system.gameplay.selected_block.clone())
+                .show_ui(ui, |ui| {
+                    for (id, block) in system.registry.iter() {
+                        if !id.is_air() {
+                            ui.selectable_value(
+                                &mut system.gameplay.selected_block,
+                                block.name.clone(),
+                                &block.name,
+                            );
+                        }
+                    }
+                });
             ui.label("Camera Field of View");
             ui.add(egui::Slider::new(&mut camera_fov, 0.0..=180.0));
             ui.separator();
@@ -122,8 +156,20 @@ pub fn ui_debug_render_system(mut system: EguiRenderSystem) -> SysResult {
                 egui::Slider::new(&mut system.terrain_config.visible_chunk_radius, 1..=32)
                     .text("Visible Chunk Radius"),
             );
+            ui.checkbox(&mut system.terrain_config.greedy_meshing, "Greedy Meshing");
             // loaded chunks
             ui.label(format!("Loaded Chunks: {}", system.terrain.chunks.len()));
+            let vertices: u32 = system
+                .terrain_render
+                .chunks
+                .values()
+                .map(TerrainChunkMesh::vertex_count)
+                .sum();
+            ui.label(format!("Terrain Vertices: {}", vertices));
+            // chunks kept and skipped by frustum culling in the last frame
+            let stats = system.terrain_render.stats;
+            ui.label(format!("Drawn Chunks: {}", stats.drawn_chunks));
+            ui.label(format!("Culled Chunks: {}", stats.culled_chunks));
         });
     player_camera.set_fov(camera_fov);
     system.globals.enable_lighting = lighting as u32;
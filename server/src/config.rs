@@ -1,10 +1,21 @@
 use serde::{Deserialize, Serialize};
 
+use crate::world::WorldConfig;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub port: u16,
     pub host: String,
     pub timeout: u64,
+    /// The most packets handled in a tick, the others wait for the next ticks.
+    #[serde(default = "default_packet_budget")]
+    pub packet_budget: usize,
+    #[serde(default)]
+    pub world: WorldConfig,
+}
+
+const fn default_packet_budget() -> usize {
+    1024
 }
 
 const CONFIG_PATH: &str = "server_config.toml";
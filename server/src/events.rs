@@ -3,7 +3,25 @@ use common::{event::Events, resources::EntityMap, uid::Uid, SysResult};
 use apecs::{ok, Write, *};
 
 pub enum ServerEvent {
-    ClientDisconnect(Uid),
+    ClientDisconnect { uid: Uid, reason: DisconnectReason },
+}
+
+/// Why a client left the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client sent a disconnect packet.
+    Left,
+    /// Nothing was received from the client for longer than the timeout.
+    TimedOut,
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisconnectReason::Left => write!(f, "left the game"),
+            DisconnectReason::TimedOut => write!(f, "timed out"),
+        }
+    }
 }
 
 #[derive(CanFetch)]
@@ -16,11 +34,11 @@ pub struct HandleServerEvents {
 pub fn handle_server_events(mut system: HandleServerEvents) -> SysResult {
     for event in &system.events.events {
         match event {
-            ServerEvent::ClientDisconnect(uid) => {
+            ServerEvent::ClientDisconnect { uid, reason } => {
                 if let Some(entity) = system.entity_map.entity(*uid) {
                     system.entities.destroy(entity);
                     system.entity_map.remove(*uid);
-                    log::info!("Client {} disconnected.", uid);
+                    log::info!("Client {} disconnected: {}.", uid, reason);
                 } else {
                     log::error!(
                         "Entity with uid: {} was not found in the entity map. this is a bug",
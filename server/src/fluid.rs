@@ -0,0 +1,41 @@
+use apecs::*;
+use common::{
+    block::BlockRegistry,
+    chunk::Chunk,
+    fluid::FluidSimulation,
+    resources::{DeltaTime, TerrainMap},
+    SysResult,
+};
+
+#[derive(CanFetch)]
+pub struct FluidTickSystem {
+    simulation: Write<FluidSimulation>,
+    terrain: Write<TerrainMap>,
+    registry: Read<BlockRegistry, NoDefault>,
+    dt: Read<DeltaTime>,
+}
+
+/// Wakes up the fluids next to the blocks edited since the last tick, then runs the fluid ticks.
+///
+/// It runs before the block updates are broadcast, so the dirty sets only hold the edits
+/// made by the clients. The blocks changed by the fluids are broadcast like those edits.
+pub fn fluid_tick_system(mut sys: FluidTickSystem) -> SysResult {
+    let edited = sys
+        .terrain
+        .chunks
+        .iter()
+        .flat_map(|(chunk_pos, chunk)| {
+            chunk
+                .dirty()
+                .iter()
+                .map(|local| Chunk::local_to_world(*chunk_pos, *local))
+        })
+        .collect::<Vec<_>>();
+    for pos in edited {
+        sys.simulation
+            .block_changed(&sys.terrain, &sys.registry, pos);
+    }
+    sys.simulation
+        .advance(&mut sys.terrain, &sys.registry, sys.dt.0);
+    ok()
+}
@@ -1,17 +1,27 @@
 pub mod config;
 pub mod events;
+pub mod fluid;
 pub mod world;
 
 use std::{
+    collections::{HashSet, VecDeque},
+    io::ErrorKind,
     net::SocketAddr,
     time::{Duration, Instant},
 };
 
 use apecs::CanFetch;
 use common::{
+    block::{Block, BlockRegistry, BLOCKS_DIR},
+    chunk::{Chunk, GenerationStatus},
     event::Events,
-    net::connection::Connection,
-    net::packet::{ClientPacket, PingPacket, ServerPacket},
+    fluid::FluidSimulation,
+    net::connection::{Connection, PacketCounters},
+    net::error::NetworkError,
+    net::packet::{
+        ClientPacket, Handshake, PingPacket, ServerPacket, GAME_VERSION, PROTOCOL_VERSION,
+    },
+    ray::{raycast, BLOCK_REACH},
     resources::{EntityMap, ProgramTime, TerrainMap},
     state::State,
     uid::Uid,
@@ -19,14 +29,46 @@ use common::{
 };
 use config::ServerConfig;
 use log::info;
+use vek::{Vec2, Vec3};
 
 type ServerConnection = Connection<ServerPacket, ClientPacket>;
 
+/// The longest player name accepted, in characters.
+const MAX_PLAYER_NAME_LEN: usize = 32;
+/// How often an idle client is pinged once half of its timeout has passed, in seconds.
+const KEEPALIVE_INTERVAL: f64 = 1.0;
+/// How far the origin of a block edit can be from the last position sent by its client.
+/// Moves are unreliable, so the last ones may be lost or arrive after the edit.
+const MAX_EDIT_ORIGIN_DRIFT: f32 = 4.0;
+
 pub struct RemoteClient {
     addr: SocketAddr,
-    last_ping: f64,
+    name: String,
+    /// The last time a packet was received from this client.
+    last_activity: f64,
+    /// The last time the server pinged this client to keep it alive.
+    last_keepalive: f64,
+    /// Set once the disconnect event of this client has been sent.
+    departed: bool,
+    /// The last position of the player's eyes sent by the client.
+    pos: Vec3<f32>,
+    /// The sequence of the move that set `pos`, older moves are dropped.
+    last_move: u64,
+    /// The chunks sent to this client that it has not unloaded yet.
+    loaded_chunks: HashSet<Vec2<i32>>,
+}
+
+impl RemoteClient {
+    /// Marks the client as gone, and returns whether it was still connected.
+    fn depart(&mut self) -> bool {
+        !std::mem::replace(&mut self.departed, true)
+    }
 }
 
+/// Packets received but not handled yet, they are handled before the next ones received.
+#[derive(Default)]
+struct PendingPackets(VecDeque<(ClientPacket, SocketAddr)>);
+
 pub struct Server {
     state: State,
 }
@@ -34,34 +76,60 @@ pub struct Server {
 #[allow(clippy::new_without_default)]
 impl Server {
     pub fn new(config: ServerConfig) -> anyhow::Result<Self> {
+        let registry = BlockRegistry::load(BLOCKS_DIR)
+            .map_err(|e| anyhow::anyhow!("Failed to load block registry: {:?}", e))?;
+        Self::with_registry(config, registry)
+    }
+
+    fn with_registry(config: ServerConfig, registry: BlockRegistry) -> anyhow::Result<Self> {
         let addr = format!("{}:{}", config.host, config.port)
             .parse::<SocketAddr>()
             .expect("Failed to parse server address");
         let con: ServerConnection = Connection::listen(addr).unwrap();
         log::info!("Server listening on {}", addr);
         let mut state = State::server().unwrap();
+        let world_generator = WorldGenerator::new(&config.world, &registry)
+            .map_err(|e| anyhow::anyhow!("Failed to create world generator: {:?}", e))?;
 
+        // Every system is ordered, the scheduler fails to find batches for some
+        // partial orders of systems writing the same resources
         state
             .ecs_mut()
             .with_resource(con)?
             .with_resource(config)?
-            .with_resource(WorldGenerator::new())?
+            .with_resource(world_generator)?
+            .with_resource(registry)?
+            .with_default_resource::<FluidSimulation>()?
+            .with_default_resource::<PacketCounters>()?
+            .with_default_resource::<PendingPackets>()?
             .with_system_with_dependencies(
                 "handle_incoming_packets",
                 handle_incoming_packets,
                 &[],
                 &[],
             )?
+            .with_system_with_dependencies(
+                "fluid_tick",
+                fluid::fluid_tick_system,
+                &["handle_incoming_packets"],
+                &[],
+            )?
+            .with_system_with_dependencies(
+                "broadcast_block_updates",
+                broadcast_block_updates,
+                &["fluid_tick"],
+                &[],
+            )?
             .with_system_with_dependencies(
                 "handle_client_ping",
                 handle_client_ping,
+                &["broadcast_block_updates"],
                 &[],
-                &["handle_server_events"],
             )?
             .with_system_with_dependencies(
                 "handle_server_events",
                 events::handle_server_events,
-                &[],
+                &["handle_client_ping"],
                 &["server_events-update"],
             )?;
 
@@ -73,102 +141,714 @@ impl Server {
 
     pub fn tick(&mut self, dt: Duration) {
         self.state.tick(dt);
+        let connection = self.state.resource_mut::<ServerConnection>();
+        connection.update();
+        let counters = connection.take_counters();
+        *self.state.resource_mut::<PacketCounters>() = counters;
+    }
+
+    /// The packets sent and received during the last tick.
+    pub fn packet_counters(&self) -> PacketCounters {
+        *self.state.resource::<PacketCounters>()
     }
 }
 
 use apecs::*;
 
-use crate::{events::ServerEvent, world::WorldGenerator};
+use crate::{
+    events::{DisconnectReason, ServerEvent},
+    world::WorldGenerator,
+};
 
 #[derive(CanFetch)]
 pub struct HandleIncomingPacketsSystem {
-    connection: Read<ServerConnection, NoDefault>,
+    connection: Write<ServerConnection, NoDefault>,
+    pending: Write<PendingPackets>,
     entities: Write<Entities>,
     entity_map: Write<EntityMap>,
     global_time: Read<ProgramTime>,
     terrain: Write<TerrainMap>,
-    terrain_generator: Read<WorldGenerator, NoDefault>,
+    terrain_generator: Write<WorldGenerator, NoDefault>,
+    registry: Read<BlockRegistry, NoDefault>,
+    events: Write<Events<ServerEvent>>,
+    config: Read<ServerConfig, NoDefault>,
+    clients: Query<(&'static Uid, &'static mut RemoteClient)>,
 }
 
+/// Handles the packets received since the last tick, up to [`ServerConfig::packet_budget`].
 pub fn handle_incoming_packets(mut sys: HandleIncomingPacketsSystem) -> SysResult {
-    if let Ok((packet, addr)) = sys.connection.recv() {
+    let mut pending = std::mem::take(&mut sys.pending.0);
+    // The entities of new clients only exist once the system has run, so their next
+    // packets wait for the next tick
+    let mut joined = HashSet::new();
+    for _ in 0..sys.config.packet_budget {
+        let received = match pending.pop_front() {
+            Some(received) => Ok(received),
+            None => sys.connection.recv(),
+        };
+        let (packet, addr) = match received {
+            Ok(received) => received,
+            Err(NetworkError::IOError(ErrorKind::WouldBlock)) => break,
+            Err(e) => {
+                log::warn!("Failed to receive packet: {:?}", e);
+                continue;
+            },
+        };
+        if joined.contains(&addr) {
+            sys.pending.0.push_back((packet, addr));
+            continue;
+        }
+        refresh_activity(&mut sys.clients, addr, sys.global_time.0);
         match packet {
-            ClientPacket::Connect => {
-                let mut client = sys.entities.create();
-                let uid = sys.entity_map.insert_entity(client.clone());
-
-                let remote = RemoteClient {
-                    addr,
-                    last_ping: sys.global_time.0,
-                };
-
-                client.insert_bundle((uid, remote));
-
-                let sync_packet = ServerPacket::ClientSync { uid };
-
-                if let Err(e) = sys.connection.send_to(sync_packet, addr) {
-                    log::error!("Failed to send sync packet to client: {:?}", e);
+            ClientPacket::Connect(handshake) => {
+                if handle_connect(&mut sys, addr, handshake) {
+                    joined.insert(addr);
                 }
-                info!("New client connected.");
             },
-            ClientPacket::Disconnect => {
-                // TODO: send server event
+            ClientPacket::Disconnect => handle_disconnect(&mut sys, addr),
+            ClientPacket::Ping(packet) => handle_ping(&mut sys, addr, packet),
+            ClientPacket::ChunkRequest(pos) => handle_chunk_request(&mut sys, addr, pos),
+            ClientPacket::ChunkUnload(pos) => {
+                let mut query = sys.clients.query();
+                let client = query
+                    .iter_mut()
+                    .find(|(_, c)| c.addr == addr && !c.departed);
+                match client {
+                    Some((_, client)) => {
+                        client.loaded_chunks.remove(&pos);
+                    },
+                    None => log::debug!("Ignoring chunk unload from unknown client {}", addr),
+                }
             },
-            ClientPacket::Ping(packet) => match packet {
-                PingPacket::Ping => {
-                    if let Err(error) = sys
-                        .connection
-                        .send_to(ServerPacket::Ping(PingPacket::Pong), addr)
-                    {
-                        log::error!("Failed to send ping packet to client: {:?}", error);
+            ClientPacket::Move { sequence, pos } => {
+                let mut query = sys.clients.query();
+                let client = query
+                    .iter_mut()
+                    .find(|(_, c)| c.addr == addr && !c.departed);
+                if let Some((_, client)) = client {
+                    if sequence > client.last_move && pos.map(f32::is_finite).reduce_and() {
+                        client.pos = pos;
+                        client.last_move = sequence;
                     }
-                },
-                PingPacket::Pong => {},
+                }
             },
-
-            ClientPacket::ChunkRequest(pos) => match sys.terrain.chunks.get(&pos) {
-                Some(t) => {
-                    let c = common::chunk::compress(t);
-                    let packet = ServerPacket::ChunkUpdate { pos, data: c };
-                    if let Err(e) = sys.connection.send_to(packet, addr) {
-                        log::error!("Failed to send chunk update packet to client: {:?}", e);
-                    }
-                },
-                None => {
-                    let chunk = sys.terrain_generator.generate_chunk(pos);
-                    let c = common::chunk::compress(&chunk);
-                    let packet = ServerPacket::ChunkUpdate { pos, data: c };
-                    sys.terrain.chunks.insert(pos, chunk);
-                    if let Err(e) = sys.connection.send_to(packet, addr) {
-                        log::error!("Failed to send chunk update packet to client: {:?}", e);
-                    }
-                },
+            ClientPacket::BreakBlock { origin, dir } => {
+                handle_break_block(&mut sys, addr, origin, dir)
+            },
+            ClientPacket::PlaceBlock { origin, dir, block } => {
+                handle_place_block(&mut sys, addr, origin, dir, block)
             },
         }
     }
+    // Packets left over the budget were received before the ones put back
+    pending.append(&mut sys.pending.0);
+    sys.pending.0 = pending;
+
+    ok()
+}
+
+/// Returns whether the client joined the game.
+fn handle_connect(
+    sys: &mut HandleIncomingPacketsSystem,
+    addr: SocketAddr,
+    handshake: Handshake,
+) -> bool {
+    // The client may not have received our sync packet yet
+    if is_connected(&mut sys.clients, addr) {
+        log::debug!("Ignoring connect packet from connected client {}", addr);
+        return false;
+    }
+    if let Err(reason) = check_handshake(&handshake, &sys.registry) {
+        log::info!("Refusing client {}: {}", addr, reason);
+        let packet = ServerPacket::Disconnect { reason };
+        if let Err(e) = sys.connection.send_to(packet, addr) {
+            log::error!("Failed to send disconnect packet to client: {:?}", e);
+        }
+        // The client sends its connect packet again if the refusal is lost
+        sys.connection.forget(addr);
+        return false;
+    }
+    if handshake.game_version != GAME_VERSION {
+        log::warn!(
+            "Client {} runs version {} of the game, the server runs {}",
+            addr,
+            handshake.game_version,
+            GAME_VERSION
+        );
+    }
+
+    let mut client = sys.entities.create();
+    let uid = sys.entity_map.insert_entity(client.clone());
+
+    let remote = RemoteClient {
+        addr,
+        name: handshake.player_name.trim().to_owned(),
+        last_activity: sys.global_time.0,
+        last_keepalive: sys.global_time.0,
+        departed: false,
+        pos: Vec3::zero(),
+        last_move: 0,
+        loaded_chunks: HashSet::new(),
+    };
+
+    client.insert_bundle((uid, remote));
+
+    let sync_packet = ServerPacket::ClientSync {
+        uid,
+        blocks: sys.registry.names(),
+    };
+
+    if let Err(e) = sys.connection.send_to(sync_packet, addr) {
+        log::error!("Failed to send sync packet to client: {:?}", e);
+    }
+    info!("{} connected from {}.", handshake.player_name.trim(), addr);
+    true
+}
+
+fn handle_disconnect(sys: &mut HandleIncomingPacketsSystem, addr: SocketAddr) {
+    let mut query = sys.clients.query();
+    if let Some((uid, client)) = query.iter_mut().find(|(_, c)| c.addr == addr) {
+        if client.depart() {
+            sys.events.send(ServerEvent::ClientDisconnect {
+                uid: **uid,
+                reason: DisconnectReason::Left,
+            });
+        }
+    }
+    sys.connection.forget(addr);
+}
+
+fn handle_ping(sys: &mut HandleIncomingPacketsSystem, addr: SocketAddr, packet: PingPacket) {
+    match packet {
+        PingPacket::Ping => {
+            if let Err(error) = sys
+                .connection
+                .send_to(ServerPacket::Ping(PingPacket::Pong), addr)
+            {
+                log::error!("Failed to send ping packet to client: {:?}", error);
+            }
+        },
+        PingPacket::Pong => {},
+    }
+}
+
+fn handle_chunk_request(sys: &mut HandleIncomingPacketsSystem, addr: SocketAddr, pos: Vec2<i32>) {
+    // Chunks are only generated for players
+    if !is_connected(&mut sys.clients, addr) {
+        log::debug!("Ignoring chunk request from unknown client {}", addr);
+        return;
+    }
+    if !sys.terrain.chunks.contains_key(&pos) {
+        let chunk = sys.terrain_generator.generate_chunk(pos);
+        sys.terrain.chunks.insert(pos, chunk);
+    }
+    let chunk = &sys.terrain.chunks[&pos];
+    // Decorations could still be added to a partially generated chunk
+    if chunk.status() != GenerationStatus::Full {
+        log::error!("Chunk {:?} is not fully generated, not sending it", pos);
+        return;
+    }
+    let c = common::chunk::compress(chunk);
+    let packet = ServerPacket::ChunkUpdate { pos, data: c };
+    if let Err(e) = sys.connection.send_to(packet, addr) {
+        log::error!("Failed to send chunk update packet to client: {:?}", e);
+    }
+    let mut query = sys.clients.query();
+    if let Some((_, client)) = query.iter_mut().find(|(_, c)| c.addr == addr) {
+        client.loaded_chunks.insert(pos);
+    }
+}
+
+fn handle_break_block(
+    sys: &mut HandleIncomingPacketsSystem,
+    addr: SocketAddr,
+    origin: Vec3<f32>,
+    dir: Vec3<f32>,
+) {
+    if !check_edit_origin(&mut sys.clients, addr, origin) {
+        return;
+    }
+    // The ray is cast from the player on the server's terrain, so the reach is validated here.
+    match raycast(&sys.terrain, &sys.registry, origin, dir, BLOCK_REACH) {
+        Some(hit) => {
+            sys.terrain.set_block(hit.pos, Block::AIR);
+        },
+        None => log::debug!("Ignoring block break out of reach from {}", addr),
+    }
+}
+
+fn handle_place_block(
+    sys: &mut HandleIncomingPacketsSystem,
+    addr: SocketAddr,
+    origin: Vec3<f32>,
+    dir: Vec3<f32>,
+    block: Block,
+) {
+    if !check_edit_origin(&mut sys.clients, addr, origin) {
+        return;
+    }
+    let valid = sys
+        .registry
+        .get(block.id)
+        .is_some_and(|d| (block.state.0 as usize) < d.state_count());
+    if block.is_air() || !valid {
+        log::warn!(
+            "Client {} tried to place an invalid block {:?}",
+            addr,
+            block
+        );
+        return;
+    }
+    let target = raycast(&sys.terrain, &sys.registry, origin, dir, BLOCK_REACH)
+        .map(|hit| hit.adjacent)
+        .filter(|pos| sys.terrain.get_block(*pos).is_some_and(Block::is_air));
+    match target {
+        Some(pos) => {
+            sys.terrain.set_block(pos, block);
+        },
+        None => log::debug!("Ignoring block placement out of reach from {}", addr),
+    }
+}
+
+/// Returns why a client can't join the game, if it can't.
+fn check_handshake(handshake: &Handshake, registry: &BlockRegistry) -> Result<(), String> {
+    if handshake.protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "Incompatible protocol version {}, the server uses version {}",
+            handshake.protocol_version, PROTOCOL_VERSION
+        ));
+    }
+    let name = handshake.player_name.trim();
+    if name.is_empty() || name.chars().count() > MAX_PLAYER_NAME_LEN {
+        return Err(format!(
+            "Player names must have between 1 and {} characters",
+            MAX_PLAYER_NAME_LEN
+        ));
+    }
+    if handshake.registry_hash != registry.hash() {
+        return Err("The client and the server don't have the same blocks".to_owned());
+    }
+    Ok(())
+}
+
+fn is_connected(
+    clients: &mut Query<(&'static Uid, &'static mut RemoteClient)>,
+    addr: SocketAddr,
+) -> bool {
+    clients
+        .query()
+        .iter_mut()
+        .any(|(_, client)| client.addr == addr && !client.departed)
+}
+
+/// Whether a connected client can cast the ray of a block edit from `origin`.
+fn check_edit_origin(
+    clients: &mut Query<(&'static Uid, &'static mut RemoteClient)>,
+    addr: SocketAddr,
+    origin: Vec3<f32>,
+) -> bool {
+    let mut query = clients.query();
+    let Some((_, client)) = query
+        .iter_mut()
+        .find(|(_, client)| client.addr == addr && !client.departed)
+    else {
+        return false;
+    };
+    if !origin.map(f32::is_finite).reduce_and()
+        || origin.distance(client.pos) > MAX_EDIT_ORIGIN_DRIFT
+    {
+        log::debug!(
+            "Ignoring block edit from {} cast from {:?}, away from {:?}",
+            addr,
+            origin,
+            client.pos
+        );
+        return false;
+    }
+    true
+}
+
+/// Any packet keeps a client connected, not only pings.
+fn refresh_activity(
+    clients: &mut Query<(&'static Uid, &'static mut RemoteClient)>,
+    addr: SocketAddr,
+    time: f64,
+) {
+    let mut query = clients.query();
+    if let Some((_, client)) = query.iter_mut().find(|(_, c)| c.addr == addr) {
+        client.last_activity = time;
+    }
+}
 
+#[derive(CanFetch)]
+pub struct BroadcastBlockUpdates {
+    connection: Write<ServerConnection, NoDefault>,
+    terrain: Write<TerrainMap>,
+    clients: Query<(&'static Uid, &'static mut RemoteClient)>,
+}
+
+/// Sends every block edited since the last tick to the clients that have its chunk loaded.
+pub fn broadcast_block_updates(mut sys: BroadcastBlockUpdates) -> SysResult {
+    let mut query = sys.clients.query();
+    for (chunk_pos, chunk) in sys.terrain.chunks.iter_mut() {
+        if !chunk.is_dirty() {
+            continue;
+        }
+        for local in chunk.take_dirty() {
+            let block = chunk.get_block(local).unwrap_or_default();
+            let pos = Chunk::local_to_world(*chunk_pos, local);
+            for (_, client) in query.iter_mut() {
+                if !client.loaded_chunks.contains(chunk_pos) {
+                    continue;
+                }
+                let packet = ServerPacket::BlockUpdate { pos, block };
+                if let Err(e) = sys.connection.send_to(packet, client.addr) {
+                    log::error!("Failed to send block update packet to client: {:?}", e);
+                }
+            }
+        }
+    }
     ok()
 }
 
 #[derive(CanFetch)]
 pub struct HandleClientPing {
-    clients: Query<(&'static mut Uid, &'static mut RemoteClient)>,
+    connection: Write<ServerConnection, NoDefault>,
+    clients: Query<(&'static Uid, &'static mut RemoteClient)>,
     global_time: Read<ProgramTime>,
     events: Write<Events<ServerEvent>>,
     config: Read<ServerConfig, NoDefault>,
 }
 
+/// Disconnects the clients that have been silent for longer than the timeout, and pings
+/// the ones getting close to it.
 pub fn handle_client_ping(mut sys: HandleClientPing) -> SysResult {
     let mut query = sys.clients.query();
+    let time = sys.global_time.0;
+    let timeout = sys.config.timeout as f64;
 
     for (uid, client) in query.iter_mut() {
-        let delta = sys.global_time.0 - client.last_ping;
+        let idle = time - client.last_activity;
 
-        if delta > sys.config.timeout as f64 {
-            log::info!("Client {} timed out.", uid.0);
-            sys.events.send(ServerEvent::ClientDisconnect(**uid));
+        if idle > timeout {
+            if client.depart() {
+                log::info!("Client {} ({}) timed out.", uid.0, client.name);
+                sys.events.send(ServerEvent::ClientDisconnect {
+                    uid: **uid,
+                    reason: DisconnectReason::TimedOut,
+                });
+                // In case the client is still there but can't reach the server
+                let packet = ServerPacket::Disconnect {
+                    reason: DisconnectReason::TimedOut.to_string(),
+                };
+                if let Err(e) = sys.connection.send_to(packet, client.addr) {
+                    log::error!("Failed to send disconnect packet to client: {:?}", e);
+                }
+                sys.connection.forget(client.addr);
+            }
+        } else if idle > timeout / 2.0 && time - client.last_keepalive >= KEEPALIVE_INTERVAL {
+            client.last_keepalive = time;
+            let packet = ServerPacket::Ping(PingPacket::Ping);
+            if let Err(e) = sys.connection.send_to(packet, client.addr) {
+                log::error!("Failed to send keepalive packet to client: {:?}", e);
+            }
         }
-        // TODO: maybe try pinging if timeout is getting close ?
     }
     ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        time::{Duration, Instant},
+    };
+
+    use apecs::*;
+    use common::{
+        block::{Block, BlockDescriptor, BlockRegistry},
+        event::Events,
+        net::{
+            connection::Connection,
+            packet::{ClientPacket, Handshake, PingPacket, ServerPacket},
+        },
+        resources::TerrainMap,
+        uid::Uid,
+        SysResult,
+    };
+    use vek::{Vec2, Vec3};
+
+    use super::{PendingPackets, Server, ServerConnection};
+    use crate::{
+        config::ServerConfig,
+        events::{DisconnectReason, ServerEvent},
+        world::{FlatLayer, GeneratorConfig, WorldConfig},
+    };
+
+    type ClientConnection = Connection<ClientPacket, ServerPacket>;
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn registry() -> BlockRegistry {
+        let descriptor = toml::from_str::<BlockDescriptor>("name = \"Stone\"").unwrap();
+        BlockRegistry::from_descriptors(vec![descriptor]).unwrap()
+    }
+
+    /// The config of a server generating an empty world.
+    fn config() -> ServerConfig {
+        ServerConfig {
+            port: 0,
+            host: "127.0.0.1".to_owned(),
+            timeout: 10,
+            packet_budget: 1024,
+            world: WorldConfig {
+                generator: GeneratorConfig::Void {},
+                ..WorldConfig::default()
+            },
+        }
+    }
+
+    fn server_and_client() -> (Server, ClientConnection) {
+        server_and_client_with(config())
+    }
+
+    /// A server and a client connected to it.
+    fn server_and_client_with(config: ServerConfig) -> (Server, ClientConnection) {
+        let server = Server::with_registry(config, registry()).unwrap();
+        let port = server
+            .state
+            .resource::<ServerConnection>()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = Connection::connect(SocketAddr::from(([127, 0, 0, 1], port))).unwrap();
+        (server, client)
+    }
+
+    /// The address of a client on the server.
+    fn client_addr(client: &ClientConnection) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], client.local_addr().unwrap().port()))
+    }
+
+    /// Ticks the server until the client has received `count` packets, or a few seconds passed.
+    fn receive(
+        server: &mut Server,
+        client: &mut ClientConnection,
+        count: usize,
+    ) -> Vec<ServerPacket> {
+        let mut received = Vec::new();
+        let start = Instant::now();
+        while received.len() < count && start.elapsed() < Duration::from_secs(5) {
+            server.tick(TICK);
+            client.update();
+            while let Ok((packet, _)) = client.recv() {
+                received.push(packet);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        received
+    }
+
+    /// The clients disconnected since the server started.
+    #[derive(Default)]
+    struct Disconnects(Vec<(Uid, DisconnectReason)>);
+
+    #[derive(CanFetch)]
+    struct RecordDisconnects {
+        events: Read<Events<ServerEvent>>,
+        disconnects: Write<Disconnects>,
+    }
+
+    fn record_disconnects(mut sys: RecordDisconnects) -> SysResult {
+        for event in &sys.events.events {
+            let ServerEvent::ClientDisconnect { uid, reason } = event;
+            sys.disconnects.0.push((*uid, *reason));
+        }
+        ok()
+    }
+
+    #[test]
+    pub fn incompatible_clients_are_refused() {
+        let (mut server, mut client) = server_and_client();
+        let mut handshake = Handshake::new("Player", &registry());
+        handshake.protocol_version += 1;
+        client.send(ClientPacket::Connect(handshake)).unwrap();
+
+        let received = receive(&mut server, &mut client, 1);
+        assert!(matches!(
+            received.as_slice(),
+            [ServerPacket::Disconnect { reason }] if reason.contains("protocol version")
+        ));
+        // The refused client can't ask for chunks
+        let addr = client_addr(&client);
+        let request = (ClientPacket::ChunkRequest(Vec2::zero()), addr);
+        server
+            .state
+            .resource_mut::<PendingPackets>()
+            .0
+            .push_back(request);
+        server.tick(TICK);
+        assert!(server.state.resource::<PendingPackets>().0.is_empty());
+        assert!(server.state.resource::<TerrainMap>().chunks.is_empty());
+    }
+
+    #[test]
+    pub fn clients_join_once() {
+        let (mut server, mut client) = server_and_client();
+        for _ in 0..2 {
+            let handshake = Handshake::new("Player", &registry());
+            client.send(ClientPacket::Connect(handshake)).unwrap();
+        }
+        client
+            .send(ClientPacket::ChunkRequest(Vec2::zero()))
+            .unwrap();
+
+        // The chunk is sent after both connect packets were handled
+        let received = receive(&mut server, &mut client, 2);
+        assert!(matches!(
+            received.as_slice(),
+            [
+                ServerPacket::ClientSync { .. },
+                ServerPacket::ChunkUpdate { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    pub fn joining_clients_do_not_hold_back_others() {
+        let (mut server, mut client) = server_and_client();
+        let handshake = Handshake::new("Player", &registry());
+        client.send(ClientPacket::Connect(handshake)).unwrap();
+        assert_eq!(receive(&mut server, &mut client, 1).len(), 1);
+        let addr = client_addr(&client);
+
+        let joining = SocketAddr::from(([127, 0, 0, 1], 1));
+        let handshake = Handshake::new("Other", &registry());
+        server.state.resource_mut::<PendingPackets>().0.extend([
+            (ClientPacket::Connect(handshake), joining),
+            (ClientPacket::ChunkRequest(Vec2::unit_x()), joining),
+            (ClientPacket::ChunkRequest(Vec2::zero()), addr),
+        ]);
+        server.tick(TICK);
+        let chunks = &server.state.resource::<TerrainMap>().chunks;
+        assert!(chunks.contains_key(&Vec2::zero()));
+        assert!(!chunks.contains_key(&Vec2::unit_x()));
+
+        server.tick(TICK);
+        let chunks = &server.state.resource::<TerrainMap>().chunks;
+        assert!(chunks.contains_key(&Vec2::unit_x()));
+    }
+
+    #[test]
+    pub fn silent_clients_are_pinged_then_time_out() {
+        let (mut server, mut client) = server_and_client();
+        server
+            .state
+            .ecs_mut()
+            .with_system_with_dependencies(
+                "record_disconnects",
+                record_disconnects,
+                &["handle_server_events"],
+                &["server_events-update"],
+            )
+            .unwrap();
+        let handshake = Handshake::new("Player", &registry());
+        client.send(ClientPacket::Connect(handshake)).unwrap();
+        let Some(ServerPacket::ClientSync { uid, .. }) = receive(&mut server, &mut client, 1).pop()
+        else {
+            panic!("The client didn't join");
+        };
+
+        // Past half of the timeout, the client is asked to answer
+        server.tick(Duration::from_secs(6));
+        assert!(matches!(
+            receive(&mut server, &mut client, 1).as_slice(),
+            [ServerPacket::Ping(PingPacket::Ping)]
+        ));
+        assert!(server.state.resource::<Disconnects>().0.is_empty());
+
+        server.tick(Duration::from_secs(5));
+        assert!(matches!(
+            receive(&mut server, &mut client, 1).as_slice(),
+            [ServerPacket::Disconnect { .. }]
+        ));
+        assert_eq!(
+            server.state.resource::<Disconnects>().0,
+            vec![(uid, DisconnectReason::TimedOut)]
+        );
+    }
+
+    #[test]
+    pub fn packets_over_the_budget_wait_for_the_next_tick() {
+        let (mut server, client) = server_and_client_with(ServerConfig {
+            packet_budget: 4,
+            ..config()
+        });
+        let addr = client_addr(&client);
+        let pings = (0..6).map(|_| (ClientPacket::Ping(PingPacket::Ping), addr));
+        server
+            .state
+            .resource_mut::<PendingPackets>()
+            .0
+            .extend(pings);
+
+        server.tick(TICK);
+        assert_eq!(server.state.resource::<PendingPackets>().0.len(), 2);
+        server.tick(TICK);
+        assert!(server.state.resource::<PendingPackets>().0.is_empty());
+    }
+
+    #[test]
+    pub fn block_edits_are_cast_from_the_last_position() {
+        let layers = vec![FlatLayer {
+            block: "Stone".to_owned(),
+            height: 10,
+        }];
+        let (mut server, mut client) = server_and_client_with(ServerConfig {
+            world: WorldConfig {
+                generator: GeneratorConfig::Flat { layers },
+                ..WorldConfig::default()
+            },
+            ..config()
+        });
+        let handshake = Handshake::new("Player", &registry());
+        client.send(ClientPacket::Connect(handshake)).unwrap();
+        client
+            .send(ClientPacket::ChunkRequest(Vec2::zero()))
+            .unwrap();
+        assert_eq!(receive(&mut server, &mut client, 2).len(), 2);
+
+        let pos = Vec3::new(8.5, 12.5, 8.5);
+        let away = Vec3::new(2.5, 12.5, 2.5);
+        let down = -Vec3::unit_y();
+        let packets = [
+            ClientPacket::Move { sequence: 2, pos },
+            // Arrives after a newer move
+            ClientPacket::Move {
+                sequence: 1,
+                pos: away,
+            },
+            ClientPacket::BreakBlock {
+                origin: away,
+                dir: down,
+            },
+            ClientPacket::BreakBlock {
+                origin: pos,
+                dir: down,
+            },
+        ];
+        for packet in packets {
+            client.send(packet).unwrap();
+        }
+
+        let received = receive(&mut server, &mut client, 1);
+        assert!(matches!(
+            received.as_slice(),
+            [ServerPacket::BlockUpdate { pos, block }]
+                if *pos == Vec3::new(8, 9, 8) && block.is_air()
+        ));
+        let stone = Block::from(registry().id("stone").unwrap());
+        let terrain = server.state.resource::<TerrainMap>();
+        assert_eq!(terrain.get_block(Vec3::new(2, 9, 2)), Some(stone));
+    }
+}
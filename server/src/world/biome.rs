@@ -0,0 +1,96 @@
+use common::biome::Biome;
+use noise::{NoiseFn, Perlin};
+use vek::Vec2;
+
+/// How a biome shapes the terrain, and the climate it grows in.
+#[derive(Debug, Clone, Copy)]
+pub struct BiomeProfile {
+    /// The temperature and humidity the biome is chosen for, both from -1 to 1.
+    pub climate: Vec2<f64>,
+    /// The height of the surface where the terrain noise is 0.
+    pub base_height: f64,
+    /// How far the surface rises and sinks with the terrain noise.
+    pub amplitude: f64,
+}
+
+impl BiomeProfile {
+    pub const fn of(biome: Biome) -> Self {
+        let (climate, base_height, amplitude) = match biome {
+            Biome::Plains => ((0.0, 0.0), 64.0, 12.0),
+            Biome::Forest => ((0.2, 0.6), 68.0, 20.0),
+            Biome::Desert => ((0.7, -0.6), 62.0, 8.0),
+            Biome::Tundra => ((-0.7, 0.0), 66.0, 16.0),
+            Biome::Mountains => ((-0.3, -0.6), 90.0, 60.0),
+        };
+        Self {
+            climate: Vec2::new(climate.0, climate.1),
+            base_height,
+            amplitude,
+        }
+    }
+}
+
+/// The biome of a column, and the height profile blended from the biomes around it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColumnClimate {
+    pub biome: Biome,
+    pub base_height: f64,
+    pub amplitude: f64,
+}
+
+/// Picks biomes from a temperature and a humidity noise.
+///
+/// A column belongs to the biome with the closest climate. Height profiles are blended
+/// with the other biomes by how much further their climate is, so the terrain doesn't
+/// jump where two biomes meet.
+pub struct BiomeMap {
+    temperature: Perlin,
+    humidity: Perlin,
+    scale: f64,
+    blend: f64,
+}
+
+impl BiomeMap {
+    pub fn new(seed: u32, scale: f64, blend: f64) -> Self {
+        Self {
+            temperature: Perlin::new(seed.wrapping_add(1)),
+            humidity: Perlin::new(seed.wrapping_add(2)),
+            scale,
+            blend: blend.max(f64::EPSILON),
+        }
+    }
+
+    /// The temperature and humidity at a world column.
+    pub fn climate(&self, column: Vec2<f64>) -> Vec2<f64> {
+        let point = [column.x / self.scale, column.y / self.scale];
+        Vec2::new(self.temperature.get(point), self.humidity.get(point))
+    }
+
+    pub fn column(&self, column: Vec2<f64>) -> ColumnClimate {
+        let climate = self.climate(column);
+        let distances = Biome::ALL.map(|biome| {
+            let profile = BiomeProfile::of(biome);
+            (biome, profile, profile.climate.distance(climate))
+        });
+        let (biome, _, closest) = distances
+            .iter()
+            .copied()
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .unwrap();
+
+        let mut total = 0.0;
+        let mut base_height = 0.0;
+        let mut amplitude = 0.0;
+        for (_, profile, distance) in distances {
+            let weight = (-(distance - closest) / self.blend).exp();
+            total += weight;
+            base_height += profile.base_height * weight;
+            amplitude += profile.amplitude * weight;
+        }
+        ColumnClimate {
+            biome,
+            base_height: base_height / total,
+            amplitude: amplitude / total,
+        }
+    }
+}
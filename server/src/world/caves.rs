@@ -0,0 +1,154 @@
+use noise::{NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
+use vek::Vec3;
+
+/// The caves carved out of the noise terrain.
+///
+/// ```toml
+/// [world.generator.caves]
+/// cheese_threshold = 0.85
+/// ravine_depth = 0
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaveConfig {
+    pub enabled: bool,
+    /// Nothing is carved below this height, so the world keeps a floor.
+    pub floor: i32,
+    /// The scale of the large open caverns.
+    pub cheese_scale: f64,
+    /// Caverns are carved where their noise is above this value. 0 carves half of
+    /// the underground, 1 and above disables them.
+    pub cheese_threshold: f64,
+    /// The scale of the long winding tunnels.
+    pub spaghetti_scale: f64,
+    /// The width of the tunnels in noise units. 0 disables them.
+    pub spaghetti_width: f64,
+    /// The scale of the ravines along the X and Z axes.
+    pub ravine_scale: f64,
+    /// The width of the ravines at the surface, in noise units.
+    pub ravine_width: f64,
+    /// How many blocks ravines cut down from the surface. 0 disables them.
+    pub ravine_depth: i32,
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            floor: 4,
+            cheese_scale: 64.0,
+            cheese_threshold: 0.75,
+            spaghetti_scale: 80.0,
+            spaghetti_width: 0.05,
+            ravine_scale: 300.0,
+            ravine_width: 0.02,
+            ravine_depth: 40,
+        }
+    }
+}
+
+/// Caverns stay this many blocks under the surface, only tunnels and ravines break through.
+const CHEESE_ROOF: i32 = 8;
+
+/// Decides which underground blocks are carved out, from 3D noise.
+///
+/// Caverns ("cheese") are the blobs where a single noise is high. Tunnels ("spaghetti")
+/// follow the lines where two noises are both close to 0, and ravines are the same idea
+/// with a single 2D noise, which gives long and deep cuts from the surface.
+pub struct CaveCarver {
+    cheese: Perlin,
+    spaghetti: [Perlin; 2],
+    ravine: Perlin,
+    config: CaveConfig,
+}
+
+impl CaveCarver {
+    pub fn new(seed: u32, config: CaveConfig) -> Self {
+        Self {
+            cheese: Perlin::new(seed.wrapping_add(3)),
+            spaghetti: [
+                Perlin::new(seed.wrapping_add(4)),
+                Perlin::new(seed.wrapping_add(5)),
+            ],
+            ravine: Perlin::new(seed.wrapping_add(6)),
+            config,
+        }
+    }
+
+    /// The carvings of a column at `x` and `z`, whose terrain surface is at `surface`.
+    pub fn column(&self, x: f64, z: f64, surface: i32) -> CaveColumn<'_> {
+        let config = &self.config;
+        // How far the column is from the middle of a ravine, 0 being the middle
+        let ravine = self
+            .ravine
+            .get([x / config.ravine_scale, z / config.ravine_scale])
+            .abs();
+        CaveColumn {
+            carver: self,
+            x,
+            z,
+            surface,
+            ravine,
+        }
+    }
+}
+
+/// The carvings of a single column, see [`CaveCarver::column`].
+pub struct CaveColumn<'a> {
+    carver: &'a CaveCarver,
+    x: f64,
+    z: f64,
+    surface: i32,
+    ravine: f64,
+}
+
+impl CaveColumn<'_> {
+    pub fn is_carved(&self, y: i32) -> bool {
+        let config = &self.carver.config;
+        if !config.enabled || y < config.floor {
+            return false;
+        }
+        self.is_ravine(y) || self.is_cheese(y) || self.is_spaghetti(y)
+    }
+
+    /// Ravines narrow down towards their bottom.
+    fn is_ravine(&self, y: i32) -> bool {
+        let config = &self.carver.config;
+        let bottom = self.surface - config.ravine_depth;
+        if config.ravine_depth <= 0 || y <= bottom {
+            return false;
+        }
+        let ratio = (y - bottom) as f64 / config.ravine_depth as f64;
+        self.ravine < config.ravine_width * ratio.min(1.0)
+    }
+
+    fn is_cheese(&self, y: i32) -> bool {
+        if y > self.surface - CHEESE_ROOF {
+            return false;
+        }
+        let config = &self.carver.config;
+        let point = self.point(y) / config.cheese_scale;
+        self.carver.cheese.get(point.into_array()) > config.cheese_threshold
+    }
+
+    fn is_spaghetti(&self, y: i32) -> bool {
+        let config = &self.carver.config;
+        if config.spaghetti_width <= 0.0 {
+            return false;
+        }
+        let point = (self.point(y) / config.spaghetti_scale).into_array();
+        let [first, second] = &self.carver.spaghetti;
+        // The second noise is only needed close to the first one's zero
+        let a = first.get(point);
+        if a.abs() >= config.spaghetti_width {
+            return false;
+        }
+        let b = second.get(point);
+        a * a + b * b < config.spaghetti_width * config.spaghetti_width
+    }
+
+    fn point(&self, y: i32) -> Vec3<f64> {
+        Vec3::new(self.x, y as f64, self.z)
+    }
+}
@@ -0,0 +1,299 @@
+use common::{
+    biome::Biome,
+    block::{BlockId, BlockRegistry, RegistryError},
+    chunk::Chunk,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use vek::{Vec2, Vec3};
+
+/// The blocks a [`Placement`] is allowed to replace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replace {
+    /// Only air, so decorations don't cut into the terrain or into each other.
+    Air,
+    /// Only this block, like ores replacing stone.
+    Only(BlockId),
+    Any,
+}
+
+impl Replace {
+    pub fn allows(self, block: BlockId) -> bool {
+        match self {
+            Replace::Air => block == BlockId::AIR,
+            Replace::Only(id) => block == id,
+            Replace::Any => true,
+        }
+    }
+}
+
+/// A block of a decoration, in world coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub pos: Vec3<i32>,
+    pub block: BlockId,
+    pub replace: Replace,
+}
+
+/// The random number generator of a chunk's decorations.
+///
+/// It only depends on the seed and the position of the chunk, and [`ChaCha8Rng`] gives the
+/// same numbers on every platform and version, so worlds look the same everywhere.
+pub fn chunk_rng(seed: u32, pos: Vec2<i32>) -> ChaCha8Rng {
+    // splitmix64, to spread neighbouring positions over unrelated streams
+    let mut x = ((seed as u64) << 32) ^ ((pos.x as u32 as u64) << 16) ^ (pos.y as u32 as u64);
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    ChaCha8Rng::seed_from_u64(x ^ (x >> 31))
+}
+
+/// How many decorations the noise terrain gets.
+///
+/// ```toml
+/// [world.generator.decorations]
+/// trees = 12
+/// ruin_chance = 0.0
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecorationConfig {
+    pub enabled: bool,
+    /// Attempts to grow a tree in a chunk. Every attempt succeeds in forests,
+    /// a few of them do in plains and tundras.
+    pub trees: u32,
+    /// Attempts to grow a flower in a chunk of plains or forest.
+    pub flowers: u32,
+    pub coal_veins: u32,
+    pub iron_veins: u32,
+    /// The chance of a ruin in a chunk of plains or desert, from 0 to 1.
+    pub ruin_chance: f64,
+}
+
+impl Default for DecorationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            trees: 8,
+            flowers: 4,
+            coal_veins: 16,
+            iron_veins: 8,
+            ruin_chance: 0.03,
+        }
+    }
+}
+
+/// The blocks used by [`Decorator`].
+#[derive(Debug, Clone, Copy)]
+pub struct DecorationBlocks {
+    pub log: BlockId,
+    pub leaves: BlockId,
+    pub flower: BlockId,
+    pub coal_ore: BlockId,
+    pub iron_ore: BlockId,
+    pub stone: BlockId,
+    pub grass: BlockId,
+    pub snow: BlockId,
+}
+
+impl DecorationBlocks {
+    /// Looks up the decoration blocks in the registry.
+    pub fn from_registry(registry: &BlockRegistry) -> Result<Self, RegistryError> {
+        let id = |name: &str| {
+            registry
+                .id(name)
+                .ok_or_else(|| RegistryError::MissingBlock(name.to_owned()))
+        };
+        Ok(Self {
+            log: id("log")?,
+            leaves: id("leaves")?,
+            flower: id("flower")?,
+            coal_ore: id("coal ore")?,
+            iron_ore: id("iron ore")?,
+            stone: id("stone")?,
+            grass: id("grass")?,
+            snow: id("snow")?,
+        })
+    }
+}
+
+/// Plans trees, flowers, ore veins and ruins on the terrain of a chunk.
+///
+/// Decorations start in the chunk they are planned for, and reach at most a few blocks
+/// into its neighbours.
+pub struct Decorator {
+    config: DecorationConfig,
+    blocks: DecorationBlocks,
+}
+
+impl Decorator {
+    pub fn new(config: DecorationConfig, blocks: DecorationBlocks) -> Self {
+        Self { config, blocks }
+    }
+
+    pub fn decorate(
+        &self,
+        pos: Vec2<i32>,
+        terrain: &Chunk,
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<Placement> {
+        let mut placements = Vec::new();
+        if !self.config.enabled {
+            return placements;
+        }
+        let origin = Vec3::new(
+            pos.x * Chunk::SIZE.x as i32,
+            0,
+            pos.y * Chunk::SIZE.z as i32,
+        );
+        let config = &self.config;
+        let blocks = self.blocks;
+
+        for (ore, veins, max_height) in [
+            (blocks.coal_ore, config.coal_veins, 128),
+            (blocks.iron_ore, config.iron_veins, 64),
+        ] {
+            for _ in 0..veins {
+                let start = Vec3::new(
+                    rng.gen_range(0..16),
+                    rng.gen_range(1..max_height),
+                    rng.gen_range(0..16),
+                );
+                self.ore_vein(origin + start, ore, rng, &mut placements);
+            }
+        }
+
+        for _ in 0..config.trees {
+            let column = Vec2::new(rng.gen_range(0..16), rng.gen_range(0..16));
+            let density = match terrain.biome(column) {
+                Some(Biome::Forest) => 1.0,
+                Some(Biome::Tundra) => 0.2,
+                Some(Biome::Plains) => 0.1,
+                _ => 0.0,
+            };
+            let Some((height, top)) = surface(terrain, column) else {
+                continue;
+            };
+            if rng.gen_bool(density) && (top == blocks.grass || top == blocks.snow) {
+                let base = origin + Vec3::new(column.x, height + 1, column.y);
+                self.tree(base, rng, &mut placements);
+            }
+        }
+
+        for _ in 0..config.flowers {
+            let column = Vec2::new(rng.gen_range(0..16), rng.gen_range(0..16));
+            let fertile = matches!(terrain.biome(column), Some(Biome::Plains | Biome::Forest));
+            match surface(terrain, column) {
+                Some((height, top)) if fertile && top == blocks.grass => {
+                    placements.push(Placement {
+                        pos: origin + Vec3::new(column.x, height + 1, column.y),
+                        block: blocks.flower,
+                        replace: Replace::Air,
+                    })
+                },
+                _ => {},
+            }
+        }
+
+        if rng.gen_bool(config.ruin_chance.clamp(0.0, 1.0)) {
+            let column = Vec2::new(rng.gen_range(0..16), rng.gen_range(0..16));
+            let dry = matches!(terrain.biome(column), Some(Biome::Plains | Biome::Desert));
+            if let Some((height, _)) = surface(terrain, column).filter(|_| dry) {
+                let corner = origin + Vec3::new(column.x, height, column.y);
+                self.ruin(corner, rng, &mut placements);
+            }
+        }
+        placements
+    }
+
+    /// A random walk through the stone.
+    fn ore_vein(
+        &self,
+        start: Vec3<i32>,
+        ore: BlockId,
+        rng: &mut ChaCha8Rng,
+        placements: &mut Vec<Placement>,
+    ) {
+        let mut pos = start;
+        for _ in 0..rng.gen_range(3..=8) {
+            placements.push(Placement {
+                pos,
+                block: ore,
+                replace: Replace::Only(self.blocks.stone),
+            });
+            let mut step = Vec3::zero();
+            step[rng.gen_range(0..3)] = if rng.gen_bool(0.5) { 1 } else { -1 };
+            pos += step;
+        }
+    }
+
+    /// A trunk with two wide layers of leaves and two narrow ones on top.
+    fn tree(&self, base: Vec3<i32>, rng: &mut ChaCha8Rng, placements: &mut Vec<Placement>) {
+        let height: i32 = rng.gen_range(4..=6);
+        for y in 0..height {
+            placements.push(Placement {
+                pos: base + Vec3::unit_y() * y,
+                block: self.blocks.log,
+                replace: Replace::Air,
+            });
+        }
+        for (y, radius) in [
+            (height - 2, 2_i32),
+            (height - 1, 2),
+            (height, 1),
+            (height + 1, 1),
+        ] {
+            for x in -radius..=radius {
+                for z in -radius..=radius {
+                    // Round off some of the corners
+                    let corner = x.abs() == radius && z.abs() == radius;
+                    if corner && (radius == 1 || rng.gen_bool(0.5)) {
+                        continue;
+                    }
+                    placements.push(Placement {
+                        pos: base + Vec3::new(x, y, z),
+                        block: self.blocks.leaves,
+                        replace: Replace::Air,
+                    });
+                }
+            }
+        }
+    }
+
+    /// A 5x5 stone floor with broken walls around it.
+    fn ruin(&self, corner: Vec3<i32>, rng: &mut ChaCha8Rng, placements: &mut Vec<Placement>) {
+        for x in 0..5 {
+            for z in 0..5 {
+                let floor = corner + Vec3::new(x, 0, z);
+                placements.push(Placement {
+                    pos: floor,
+                    block: self.blocks.stone,
+                    replace: Replace::Any,
+                });
+                if x % 4 != 0 && z % 4 != 0 {
+                    continue;
+                }
+                for y in 1..=rng.gen_range(0..=3) {
+                    placements.push(Placement {
+                        pos: floor + Vec3::unit_y() * y,
+                        block: self.blocks.stone,
+                        replace: Replace::Air,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// The height and the block of the highest block of a column that isn't air.
+fn surface(terrain: &Chunk, column: Vec2<i32>) -> Option<(i32, BlockId)> {
+    (0..Chunk::SIZE.y as i32)
+        .rev()
+        .map(|y| (y, terrain.get(Vec3::new(column.x, y, column.y))))
+        .find_map(|(y, block)| {
+            block
+                .filter(|block| *block != BlockId::AIR)
+                .map(|block| (y, block))
+        })
+}
@@ -0,0 +1,72 @@
+use common::{
+    biome::Biome,
+    block::{Block, BlockRegistry, RegistryError},
+    chunk::{Chunk, ChunkSection},
+};
+use serde::{Deserialize, Serialize};
+use vek::Vec2;
+
+use super::TerrainGenerator;
+
+/// A layer of a flat world.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlatLayer {
+    pub block: String,
+    /// The thickness of the layer in blocks.
+    pub height: u32,
+}
+
+/// Generates the same column of layers everywhere.
+pub struct FlatGenerator {
+    /// The block of every height of the column. Above it is air.
+    column: Vec<Block>,
+}
+
+impl FlatGenerator {
+    /// Layers going past the top of the world are cut off.
+    pub fn new(layers: &[FlatLayer], registry: &BlockRegistry) -> Result<Self, RegistryError> {
+        let mut column = Vec::new();
+        for layer in layers {
+            let id = registry
+                .id(&layer.block)
+                .ok_or_else(|| RegistryError::MissingBlock(layer.block.clone()))?;
+            column.resize(column.len() + layer.height as usize, Block::new(id));
+        }
+        if column.len() > Chunk::SIZE.y {
+            log::warn!(
+                "The flat world layers are {} blocks high, only the bottom {} are used",
+                column.len(),
+                Chunk::SIZE.y
+            );
+            column.truncate(Chunk::SIZE.y);
+        }
+        Ok(Self { column })
+    }
+}
+
+impl TerrainGenerator for FlatGenerator {
+    fn generate_chunk(&self, _pos: Vec2<i32>) -> Chunk {
+        let sections = (0..Chunk::SECTION_COUNT)
+            .map(|section| {
+                let base_y = section * ChunkSection::SIZE.y;
+                let mut blocks = vec![Block::AIR; ChunkSection::VOLUME];
+                for (index, block) in blocks.iter_mut().enumerate() {
+                    let y = (index / ChunkSection::SIZE.x) % ChunkSection::SIZE.y;
+                    *block = self.column.get(base_y + y).copied().unwrap_or(Block::AIR);
+                }
+                ChunkSection::from_blocks(&blocks)
+            })
+            .collect();
+        Chunk::from_sections(sections, vec![Biome::default(); Chunk::COLUMNS])
+    }
+}
+
+/// Generates empty chunks.
+pub struct VoidGenerator;
+
+impl TerrainGenerator for VoidGenerator {
+    fn generate_chunk(&self, _pos: Vec2<i32>) -> Chunk {
+        Chunk::flat(Block::AIR)
+    }
+}
@@ -0,0 +1,543 @@
+mod biome;
+mod caves;
+mod decoration;
+mod flat;
+mod noise_terrain;
+
+pub use self::biome::{BiomeMap, BiomeProfile, ColumnClimate};
+pub use self::caves::{CaveCarver, CaveColumn, CaveConfig};
+pub use self::decoration::{
+    chunk_rng, DecorationBlocks, DecorationConfig, Decorator, Placement, Replace,
+};
+pub use self::flat::{FlatGenerator, FlatLayer, VoidGenerator};
+pub use self::noise_terrain::{NoiseConfig, NoiseGenerator, TerrainBlocks};
+
+use std::collections::{HashMap, HashSet};
+
+use common::{
+    block::{BlockRegistry, RegistryError},
+    chunk::{Chunk, GenerationStatus},
+};
+use rand_chacha::ChaCha8Rng;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use vek::Vec2;
+
+/// Builds the chunks of the world the first time they are requested.
+///
+/// Generators must be deterministic: the same generator always returns the same chunk
+/// for a position, whatever order the chunks are generated in.
+pub trait TerrainGenerator: Send + Sync {
+    /// Generates the terrain of a chunk, without its decorations.
+    fn generate_chunk(&self, pos: Vec2<i32>) -> Chunk;
+
+    /// Plans the decorations of a chunk from its terrain, like trees and ores.
+    /// They can reach into the neighbouring chunks, but no further.
+    fn decorate(&self, _pos: Vec2<i32>, _terrain: &Chunk, _rng: &mut ChaCha8Rng) -> Vec<Placement> {
+        Vec::new()
+    }
+}
+
+/// The chunks around a chunk whose decorations can reach into it, itself included.
+/// Decorations are placed in this order, so they overlap the same way every time.
+const NEIGHBOURHOOD: [Vec2<i32>; 9] = [
+    Vec2::new(-1, -1),
+    Vec2::new(0, -1),
+    Vec2::new(1, -1),
+    Vec2::new(-1, 0),
+    Vec2::new(0, 0),
+    Vec2::new(1, 0),
+    Vec2::new(-1, 1),
+    Vec2::new(0, 1),
+    Vec2::new(1, 1),
+];
+
+/// The `[world]` section of the server config. Unknown keys are refused in every table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorldConfig {
+    #[serde(default = "default_seed")]
+    pub seed: u32,
+    #[serde(default)]
+    pub generator: GeneratorConfig,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            seed: default_seed(),
+            generator: GeneratorConfig::default(),
+        }
+    }
+}
+
+const fn default_seed() -> u32 {
+    88
+}
+
+/// The built-in generators.
+///
+/// ```toml
+/// [world.generator]
+/// type = "flat"
+/// layers = [
+///     { block = "stone", height = 60 },
+///     { block = "dirt", height = 3 },
+///     { block = "grass", height = 1 },
+/// ]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum GeneratorConfig {
+    /// Hills shaped by biomes picked from temperature and humidity, with caves and overhangs.
+    Noise(NoiseConfig),
+    /// Layers of blocks, listed from the bottom up.
+    Flat { layers: Vec<FlatLayer> },
+    /// Nothing but air. A struct variant, since unit variants would accept any key.
+    Void {},
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self::Noise(NoiseConfig::default())
+    }
+}
+
+/// The resource holding the generator the server was configured with.
+///
+/// Chunks are generated in two stages. First the terrain of a chunk is generated and its
+/// decorations are planned. Then, once the chunks around it are planned too, every
+/// decoration reaching into it is placed and it becomes [`GenerationStatus::Full`].
+/// Decorations are only planned from the terrain, never from other decorations, so a
+/// chunk comes out the same whatever order the chunks are requested in.
+pub struct WorldGenerator {
+    generator: Box<dyn TerrainGenerator>,
+    seed: u32,
+    /// The chunks whose decorations are planned, until the chunks around them are finished.
+    planned: HashMap<Vec2<i32>, PlannedChunk>,
+    /// The chunks that were finished at least once.
+    finished: HashSet<Vec2<i32>>,
+}
+
+struct PlannedChunk {
+    /// The terrain of the chunk, until it is finished.
+    terrain: Option<Chunk>,
+    placements: Vec<Placement>,
+}
+
+impl WorldGenerator {
+    pub fn new(config: &WorldConfig, registry: &BlockRegistry) -> Result<Self, RegistryError> {
+        let generator: Box<dyn TerrainGenerator> = match &config.generator {
+            GeneratorConfig::Noise(noise) => Box::new(NoiseGenerator::new(
+                config.seed,
+                noise.clone(),
+                TerrainBlocks::from_registry(registry)?,
+                // The decoration blocks are only needed to decorate
+                noise
+                    .decorations
+                    .enabled
+                    .then(|| DecorationBlocks::from_registry(registry))
+                    .transpose()?,
+            )),
+            GeneratorConfig::Flat { layers } => Box::new(FlatGenerator::new(layers, registry)?),
+            GeneratorConfig::Void {} => Box::new(VoidGenerator),
+        };
+        Ok(Self::with_boxed(config.seed, generator))
+    }
+
+    /// Uses a generator that isn't one of the built-in presets.
+    /// The seed is only used for the decorations.
+    pub fn from_generator(seed: u32, generator: impl TerrainGenerator + 'static) -> Self {
+        Self::with_boxed(seed, Box::new(generator))
+    }
+
+    fn with_boxed(seed: u32, generator: Box<dyn TerrainGenerator>) -> Self {
+        Self {
+            generator,
+            seed,
+            planned: HashMap::new(),
+            finished: HashSet::new(),
+        }
+    }
+
+    /// Generates the terrain of a chunk, without any decoration.
+    pub fn generate_terrain(&self, pos: Vec2<i32>) -> Chunk {
+        self.generator.generate_chunk(pos)
+    }
+
+    /// Generates a chunk with every decoration reaching into it.
+    ///
+    /// The terrain of the chunks around it is generated as well to plan their decorations,
+    /// and kept until it is their turn to be finished.
+    pub fn generate_chunk(&mut self, pos: Vec2<i32>) -> Chunk {
+        self.plan_around(pos);
+        let mut chunk = self
+            .planned
+            .get_mut(&pos)
+            .and_then(|planned| planned.terrain.take())
+            // Only when the chunk is generated a second time
+            .unwrap_or_else(|| self.generate_terrain(pos));
+
+        for offset in NEIGHBOURHOOD {
+            for placement in &self.planned[&(pos + offset)].placements {
+                let (chunk_pos, local) = Chunk::world_to_local(placement.pos);
+                if chunk_pos != pos {
+                    continue;
+                }
+                if chunk
+                    .get(local)
+                    .is_some_and(|block| placement.replace.allows(block))
+                {
+                    chunk.set(local, placement.block);
+                }
+            }
+        }
+        chunk.take_dirty();
+        chunk.set_status(GenerationStatus::Full);
+
+        // The planned chunks aren't needed anymore once their whole neighbourhood is finished.
+        // A chunk generated again doesn't count twice.
+        self.finished.insert(pos);
+        for offset in NEIGHBOURHOOD {
+            let around = pos + offset;
+            let done = NEIGHBOURHOOD
+                .iter()
+                .all(|offset| self.finished.contains(&(around + *offset)));
+            if done {
+                self.planned.remove(&around);
+            }
+        }
+        chunk
+    }
+
+    /// Generates the terrain and plans the decorations of the chunks around `pos`
+    /// that aren't planned yet, in parallel.
+    fn plan_around(&mut self, pos: Vec2<i32>) {
+        let missing = NEIGHBOURHOOD
+            .iter()
+            .map(|offset| pos + *offset)
+            .filter(|pos| !self.planned.contains_key(pos))
+            .collect::<Vec<_>>();
+        let generator = &self.generator;
+        let seed = self.seed;
+        let planned = missing
+            .into_par_iter()
+            .map(|pos| {
+                let terrain = generator.generate_chunk(pos);
+                let placements = generator.decorate(pos, &terrain, &mut chunk_rng(seed, pos));
+                let planned = PlannedChunk {
+                    terrain: Some(terrain),
+                    placements,
+                };
+                (pos, planned)
+            })
+            .collect::<Vec<_>>();
+        self.planned.extend(planned);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, ops::Range};
+
+    use common::{
+        biome::Biome,
+        block::{BlockDescriptor, BlockId, BlockRegistry},
+        chunk::{Chunk, GenerationStatus},
+    };
+    use vek::{Vec2, Vec3};
+
+    use super::{CaveConfig, GeneratorConfig, WorldConfig, WorldGenerator};
+
+    fn registry() -> BlockRegistry {
+        let descriptors = [
+            "Dirt", "Grass", "Stone", "Sand", "Snow", "Log", "Leaves", "Flower", "Coal Ore",
+            "Iron Ore",
+        ]
+        .map(|name| toml::from_str::<BlockDescriptor>(&format!("name = \"{}\"", name)).unwrap())
+        .to_vec();
+        BlockRegistry::from_descriptors(descriptors).unwrap()
+    }
+
+    /// A FNV-1a hash of every block and biome of a chunk.
+    fn checksum(chunk: &Chunk) -> u64 {
+        let mut hash = 0xcbf29ce484222325_u64;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        for pos in chunk.iter() {
+            let block = chunk.get_block(pos).unwrap();
+            write(&block.id.0.to_le_bytes());
+            write(&block.state.0.to_le_bytes());
+        }
+        for biome in chunk.biomes() {
+            write(&[*biome as u8]);
+        }
+        hash
+    }
+
+    #[test]
+    pub fn noise_terrain_is_pinned_for_a_seed() {
+        let registry = registry();
+        let mut generator = WorldGenerator::new(&WorldConfig::default(), &registry).unwrap();
+        // Changing these values changes every world generated with the default config
+        assert_eq!(
+            checksum(&generator.generate_chunk(Vec2::new(0, 0))),
+            13773632047271774688
+        );
+        assert_eq!(
+            checksum(&generator.generate_chunk(Vec2::new(-3, 7))),
+            5618635583371889720
+        );
+
+        let other_seed = WorldConfig {
+            seed: 89,
+            ..WorldConfig::default()
+        };
+        let mut other = WorldGenerator::new(&other_seed, &registry).unwrap();
+        assert_ne!(
+            checksum(&other.generate_chunk(Vec2::new(0, 0))),
+            checksum(&generator.generate_chunk(Vec2::new(0, 0)))
+        );
+    }
+
+    /// The biome, the top block and its height for every column along a line of chunks.
+    fn surface_line(generator: &WorldGenerator, chunks: Range<i32>) -> Vec<(Biome, BlockId, i32)> {
+        chunks
+            .flat_map(|x| {
+                let chunk = generator.generate_terrain(Vec2::new(x, 0));
+                (0..16).map(move |local_x| {
+                    let (height, block) = (0..256)
+                        .rev()
+                        .map(|y| (y, chunk.get(Vec3::new(local_x, y, 0)).unwrap()))
+                        .find(|(_, block)| *block != BlockId::AIR)
+                        .unwrap();
+                    (chunk.biome(Vec2::new(local_x, 0)).unwrap(), block, height)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn biomes_blend_at_their_borders() {
+        let registry = registry();
+        let generator = WorldGenerator::new(&noise_config(false), &registry).unwrap();
+        let line = surface_line(&generator, -64..64);
+
+        let biomes = line
+            .iter()
+            .map(|(biome, _, _)| *biome)
+            .collect::<HashSet<_>>();
+        assert_eq!(biomes.len(), Biome::ALL.len());
+        for (biome, block, _) in &line {
+            let expected = match biome {
+                Biome::Plains | Biome::Forest => "grass",
+                Biome::Desert => "sand",
+                Biome::Tundra => "snow",
+                Biome::Mountains => "stone",
+            };
+            assert_eq!(*block, registry.id(expected).unwrap());
+        }
+        // The heights of the biomes are blended, so even mountains rise gradually
+        let steepest = line.windows(2).map(|w| (w[0].2 - w[1].2).abs()).max();
+        assert!(steepest <= Some(2));
+    }
+
+    /// The noise terrain with only the heightmap, or with the caves too.
+    fn noise_config(caves: bool) -> WorldConfig {
+        toml::from_str(&format!(
+            "[generator]\ntype = \"noise\"\noverhang_height = 0.0\ncaves = {{ enabled = {} }}",
+            caves
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    pub fn caves_are_carved_out_of_the_heightmap() {
+        let registry = registry();
+        let heightmap = WorldGenerator::new(&noise_config(false), &registry).unwrap();
+        let caves = WorldGenerator::new(&noise_config(true), &registry).unwrap();
+        let floor = CaveConfig::default().floor;
+
+        let mut carved = 0;
+        for pos in [Vec2::new(0, 0), Vec2::new(-5, 2), Vec2::new(3, -6)] {
+            let solid = heightmap.generate_terrain(pos);
+            let carved_chunk = caves.generate_terrain(pos);
+            for pos in solid.iter() {
+                let (before, after) = (solid.get(pos).unwrap(), carved_chunk.get(pos).unwrap());
+                if before != after {
+                    assert_eq!(after, BlockId::AIR);
+                    assert!(pos.y >= floor);
+                    carved += 1;
+                }
+            }
+        }
+        assert!(carved > 0);
+    }
+
+    #[test]
+    pub fn overhangs_leave_air_under_the_ground() {
+        let registry = registry();
+        let config: WorldConfig =
+            toml::from_str("[generator]\ntype = \"noise\"\ncaves = { enabled = false }").unwrap();
+        let generator = WorldGenerator::new(&config, &registry).unwrap();
+        // Air with ground right above it
+        let overhangs = (-4..4)
+            .map(|x| generator.generate_terrain(Vec2::new(x, 0)))
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .filter(|pos| chunk.get(*pos) == Some(BlockId::AIR))
+                    .filter(|pos| {
+                        chunk
+                            .get(*pos + Vec3::unit_y())
+                            .is_some_and(|b| b != BlockId::AIR)
+                    })
+                    .count()
+            })
+            .sum::<usize>();
+        assert!(overhangs > 0);
+    }
+
+    #[test]
+    pub fn decorations_do_not_depend_on_the_generation_order() {
+        let registry = registry();
+        let config = WorldConfig::default();
+        // Trees in the tundra and the plains, and ores everywhere
+        let positions = (-5..=-3)
+            .flat_map(|x| (-7..=-5).map(move |z| Vec2::new(x, z)))
+            .collect::<Vec<_>>();
+
+        let mut forward = WorldGenerator::new(&config, &registry).unwrap();
+        let mut backward = WorldGenerator::new(&config, &registry).unwrap();
+        let chunks = positions
+            .iter()
+            .map(|pos| forward.generate_chunk(*pos))
+            .collect::<Vec<_>>();
+        let reversed = positions
+            .iter()
+            .rev()
+            .map(|pos| backward.generate_chunk(*pos))
+            .collect::<Vec<_>>();
+        for (chunk, other) in chunks.iter().zip(reversed.iter().rev()) {
+            assert_eq!(chunk.status(), GenerationStatus::Full);
+            assert_eq!(checksum(chunk), checksum(other));
+        }
+
+        let count = |name: &str| {
+            let id = registry.id(name).unwrap();
+            chunks
+                .iter()
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .filter(|pos| chunk.get(*pos) == Some(id))
+                        .count()
+                })
+                .sum::<usize>()
+        };
+        assert!(count("log") > 0);
+        assert!(count("leaves") > 0);
+        assert!(count("coal ore") > 0);
+        // The terrain alone has no decorations
+        let terrain = forward.generate_terrain(positions[0]);
+        assert_eq!(terrain.status(), GenerationStatus::Terrain);
+        let log = registry.id("log").unwrap();
+        assert!(terrain.iter().all(|pos| terrain.get(pos) != Some(log)));
+    }
+
+    #[test]
+    pub fn planned_chunks_are_released_when_chunks_are_generated_again() {
+        let config: WorldConfig = toml::from_str(
+            "[generator]\ntype = \"flat\"\nlayers = [{ block = \"stone\", height = 1 }]",
+        )
+        .unwrap();
+        let mut generator = WorldGenerator::new(&config, &registry()).unwrap();
+        generator.generate_chunk(Vec2::zero());
+        generator.generate_chunk(Vec2::zero());
+        for x in -2..=2 {
+            for z in -2..=2 {
+                generator.generate_chunk(Vec2::new(x, z));
+            }
+        }
+        // Only the chunks with unfinished neighbours are still planned
+        assert_eq!(generator.planned.len(), 7 * 7 - 3 * 3);
+        assert!(generator
+            .planned
+            .keys()
+            .all(|pos| pos.x.abs() >= 2 || pos.y.abs() >= 2));
+    }
+
+    #[test]
+    pub fn decoration_blocks_are_only_needed_with_decorations() {
+        let descriptors = ["Dirt", "Grass", "Stone", "Sand", "Snow"]
+            .map(|name| toml::from_str::<BlockDescriptor>(&format!("name = \"{}\"", name)).unwrap())
+            .to_vec();
+        let registry = BlockRegistry::from_descriptors(descriptors).unwrap();
+        assert!(WorldGenerator::new(&WorldConfig::default(), &registry).is_err());
+        let config: WorldConfig =
+            toml::from_str("[generator]\ntype = \"noise\"\ndecorations = { enabled = false }")
+                .unwrap();
+        assert!(WorldGenerator::new(&config, &registry).is_ok());
+    }
+
+    #[test]
+    pub fn flat_layers_stack_from_the_bottom() {
+        let registry = registry();
+        let config: WorldConfig = toml::from_str(
+            r#"
+            [generator]
+            type = "flat"
+            layers = [
+                { block = "stone", height = 2 },
+                { block = "grass", height = 1 },
+            ]
+            "#,
+        )
+        .unwrap();
+        let mut generator = WorldGenerator::new(&config, &registry).unwrap();
+        let chunk = generator.generate_chunk(Vec2::new(5, -2));
+        let stone = registry.id("stone").unwrap();
+        let grass = registry.id("grass").unwrap();
+        assert_eq!(chunk.get(Vec3::new(3, 0, 3)), Some(stone));
+        assert_eq!(chunk.get(Vec3::new(3, 1, 3)), Some(stone));
+        assert_eq!(chunk.get(Vec3::new(3, 2, 3)), Some(grass));
+        assert!(chunk.get_block(Vec3::new(3, 3, 3)).unwrap().is_air());
+        assert!(chunk.dirty().is_empty());
+
+        let missing = WorldConfig {
+            generator: GeneratorConfig::Flat {
+                layers: vec![super::FlatLayer {
+                    block: "bedrock".to_owned(),
+                    height: 1,
+                }],
+            },
+            ..WorldConfig::default()
+        };
+        assert!(WorldGenerator::new(&missing, &registry).is_err());
+    }
+
+    #[test]
+    pub fn unknown_keys_are_refused() {
+        let error =
+            toml::from_str::<WorldConfig>("[generator]\ntype = \"noise\"\nstone_scale = 700.0")
+                .unwrap_err();
+        assert!(error.to_string().contains("stone_scale"));
+        let caves = "[generator]\ntype = \"noise\"\ncaves = { enabled = false, depth = 3 }";
+        assert!(toml::from_str::<WorldConfig>(caves).is_err());
+        assert!(
+            toml::from_str::<WorldConfig>("[generator]\ntype = \"void\"\nlayers = []").is_err()
+        );
+    }
+
+    #[test]
+    pub fn void_worlds_are_empty() {
+        let config: WorldConfig = toml::from_str("generator = { type = \"void\" }").unwrap();
+        let mut generator = WorldGenerator::new(&config, &registry()).unwrap();
+        let chunk = generator.generate_chunk(Vec2::zero());
+        assert!(chunk.sections().iter().all(|section| section.is_air()));
+    }
+}
@@ -0,0 +1,245 @@
+use common::{
+    biome::Biome,
+    block::{Block, BlockId, BlockRegistry, RegistryError},
+    chunk::{Chunk, ChunkSection},
+};
+use noise::{BasicMulti, NoiseFn, Perlin};
+use rand_chacha::ChaCha8Rng;
+use rayon::{
+    iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+use serde::{Deserialize, Serialize};
+use vek::Vec2;
+
+use super::{
+    biome::BiomeMap,
+    caves::{CaveCarver, CaveConfig},
+    decoration::{DecorationBlocks, DecorationConfig, Decorator, Placement},
+    TerrainGenerator,
+};
+
+/// The shape of the noise terrain. Scales are the number of blocks
+/// one unit of noise is stretched over, so larger scales give smoother terrain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NoiseConfig {
+    /// The scale of the surface along the X axis.
+    pub scale_x: f64,
+    /// The scale of the surface along the Z axis.
+    pub scale_z: f64,
+    /// The scale of the temperature and humidity the biomes are picked from.
+    pub biome_scale: f64,
+    /// How far the height profiles of neighbouring biomes bleed into each other, as a
+    /// distance in climate. Larger values give smoother borders between biomes.
+    pub biome_blend: f64,
+    /// The scale of the 3D noise that bends the surface into cliffs and overhangs,
+    /// along the X and Z axes.
+    pub overhang_scale: f64,
+    /// The scale of the same noise along the Y axis. It has to be small compared to the
+    /// height of the overhangs, or the surface only gets bumpy.
+    pub overhang_vertical_scale: f64,
+    /// How many blocks the surface can move up or down with the overhang noise. 0 disables it.
+    pub overhang_height: f64,
+    pub caves: CaveConfig,
+    pub decorations: DecorationConfig,
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        Self {
+            scale_x: 330.0,
+            scale_z: 400.0,
+            biome_scale: 600.0,
+            biome_blend: 0.15,
+            overhang_scale: 32.0,
+            overhang_vertical_scale: 8.0,
+            overhang_height: 8.0,
+            caves: CaveConfig::default(),
+            decorations: DecorationConfig::default(),
+        }
+    }
+}
+
+/// The blocks used by [`NoiseGenerator`] to build the terrain.
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainBlocks {
+    pub grass: BlockId,
+    pub dirt: BlockId,
+    pub stone: BlockId,
+    pub sand: BlockId,
+    pub snow: BlockId,
+}
+
+impl TerrainBlocks {
+    /// Looks up the terrain blocks in the registry.
+    pub fn from_registry(registry: &BlockRegistry) -> Result<Self, RegistryError> {
+        let id = |name: &str| {
+            registry
+                .id(name)
+                .ok_or_else(|| RegistryError::MissingBlock(name.to_owned()))
+        };
+        Ok(Self {
+            grass: id("grass")?,
+            dirt: id("dirt")?,
+            stone: id("stone")?,
+            sand: id("sand")?,
+            snow: id("snow")?,
+        })
+    }
+
+    /// The top block of a biome, the block under it and how deep that block goes before the stone.
+    pub fn surface(&self, biome: Biome) -> (BlockId, BlockId, i32) {
+        match biome {
+            Biome::Plains | Biome::Forest => (self.grass, self.dirt, 3),
+            Biome::Desert => (self.sand, self.sand, 4),
+            Biome::Tundra => (self.snow, self.dirt, 3),
+            Biome::Mountains => (self.stone, self.stone, 0),
+        }
+    }
+}
+
+/// Generates hills whose height and blocks depend on the biome of each column.
+///
+/// The heightmap is bent by a 3D noise close to the surface, then caves are carved
+/// out of the ground by a [`CaveCarver`]. The terrain is decorated by a [`Decorator`].
+pub struct NoiseGenerator {
+    noise: BasicMulti<Perlin>,
+    overhangs: Perlin,
+    biomes: BiomeMap,
+    caves: CaveCarver,
+    /// Missing when the decorations are disabled.
+    decorator: Option<Decorator>,
+    config: NoiseConfig,
+    blocks: TerrainBlocks,
+}
+
+impl NoiseGenerator {
+    pub fn new(
+        seed: u32,
+        config: NoiseConfig,
+        blocks: TerrainBlocks,
+        decoration_blocks: Option<DecorationBlocks>,
+    ) -> Self {
+        Self {
+            noise: BasicMulti::new(seed),
+            overhangs: Perlin::new(seed.wrapping_add(7)),
+            biomes: BiomeMap::new(seed, config.biome_scale, config.biome_blend),
+            caves: CaveCarver::new(seed, config.caves.clone()),
+            decorator: decoration_blocks
+                .map(|blocks| Decorator::new(config.decorations.clone(), blocks)),
+            config,
+            blocks,
+        }
+    }
+
+    /// The biome and the height of the surface of a world column.
+    fn column(&self, world_x: f64, world_z: f64) -> (Biome, i32) {
+        let climate = self.biomes.column(Vec2::new(world_x, world_z));
+        // Noise values are in range [-1, 1]
+        let noise = self
+            .noise
+            .get([world_x / self.config.scale_x, world_z / self.config.scale_z]);
+        let height = climate.base_height + noise * climate.amplitude;
+        let height = (height as i32).clamp(1, Chunk::SIZE.y as i32 - 2);
+        (climate.biome, height)
+    }
+
+    /// Whether a block is in the ground before the caves are carved. Only the blocks
+    /// within [`NoiseConfig::overhang_height`] of the surface need the 3D noise.
+    fn is_ground(&self, x: f64, y: i32, z: f64, height: i32) -> bool {
+        let config = &self.config;
+        let offset = (y - height) as f64;
+        if offset.abs() >= config.overhang_height {
+            return offset <= 0.0;
+        }
+        let noise = self.overhangs.get([
+            x / config.overhang_scale,
+            y as f64 / config.overhang_vertical_scale,
+            z / config.overhang_scale,
+        ]);
+        offset <= noise * config.overhang_height
+    }
+
+    /// Fills a column of blocks, indexed by their height.
+    fn fill_column(&self, x: f64, z: f64, biome: Biome, height: i32, column: &mut [BlockId]) {
+        let (surface, filler, depth) = self.blocks.surface(biome);
+        let overhang = self.config.overhang_height.max(0.0).ceil() as i32;
+        let top = (height + overhang).min(Chunk::SIZE.y as i32 - 1);
+        let caves = self.caves.column(x, z, height);
+        // The number of ground blocks since the last air above. The surface blocks follow
+        // the ground before carving, so cave floors stay stone.
+        let mut buried = 0;
+        for y in (0..=top).rev() {
+            if !self.is_ground(x, y, z, height) {
+                buried = 0;
+                continue;
+            }
+            let block = if buried == 0 {
+                surface
+            } else if buried <= depth {
+                filler
+            } else {
+                self.blocks.stone
+            };
+            buried += 1;
+            if !caves.is_carved(y) {
+                column[y as usize] = block;
+            }
+        }
+    }
+}
+
+impl TerrainGenerator for NoiseGenerator {
+    fn generate_chunk(&self, offset: Vec2<i32>) -> Chunk {
+        let world_x = (offset.x * Chunk::SIZE.x as i32) as f64;
+        let world_z = (offset.y * Chunk::SIZE.z as i32) as f64;
+        let column_index = |x: usize, z: usize| x + z * Chunk::SIZE.x;
+
+        let mut columns = vec![(Biome::default(), 0); Chunk::COLUMNS];
+        columns
+            .par_chunks_mut(Chunk::SIZE.x)
+            .enumerate()
+            .for_each(|(z, row)| {
+                for (x, column) in row.iter_mut().enumerate() {
+                    *column = self.column(world_x + x as f64, world_z + z as f64);
+                }
+            });
+
+        // The blocks of every column, one after the other
+        let mut blocks = vec![BlockId::AIR; Chunk::COLUMNS * Chunk::SIZE.y];
+        blocks
+            .par_chunks_mut(Chunk::SIZE.y)
+            .enumerate()
+            .for_each(|(index, column)| {
+                let x = world_x + (index % Chunk::SIZE.x) as f64;
+                let z = world_z + (index / Chunk::SIZE.x) as f64;
+                let (biome, height) = columns[index];
+                self.fill_column(x, z, biome, height, column);
+            });
+
+        let sections = (0..Chunk::SECTION_COUNT)
+            .into_par_iter()
+            .map(|section| {
+                let mut section_blocks = vec![Block::AIR; ChunkSection::VOLUME];
+                for (id, block) in section_blocks.iter_mut().enumerate() {
+                    let x = id % ChunkSection::SIZE.x;
+                    let y = (id / ChunkSection::SIZE.x) % ChunkSection::SIZE.y;
+                    let z = id / (ChunkSection::SIZE.x * ChunkSection::SIZE.y);
+                    let y = section * ChunkSection::SIZE.y + y;
+                    *block = Block::new(blocks[column_index(x, z) * Chunk::SIZE.y + y]);
+                }
+                ChunkSection::from_blocks(&section_blocks)
+            })
+            .collect();
+
+        let biomes = columns.into_iter().map(|(biome, _)| biome).collect();
+        Chunk::from_sections(sections, biomes)
+    }
+
+    fn decorate(&self, pos: Vec2<i32>, terrain: &Chunk, rng: &mut ChaCha8Rng) -> Vec<Placement> {
+        self.decorator
+            .as_ref()
+            .map_or_else(Vec::new, |decorator| decorator.decorate(pos, terrain, rng))
+    }
+}